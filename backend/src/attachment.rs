@@ -0,0 +1,483 @@
+use crate::{
+    auth::AuthUser,
+    blob::{put_content, Blobs},
+    channel::Channel,
+    config::{AttachmentConfig, Limits},
+    error::ServerErr,
+    media::{strip_gps, thumbnail_content_type, ImageQueue},
+    message::{CreateMessageParams, Message, MessageId},
+    permission::{MemberAccess, Permissions},
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{collections::HashMap, ops::Range};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+pub type AttachmentId = i32;
+
+pub const ATTACHMENT_MAX_SIZE: usize = 8 * 1024 * 1024;
+pub const ATTACHMENTS_PER_MESSAGE_MAX: usize = 10;
+pub const FILENAME_MAX_LEN: usize = 255;
+/// The multipart field files are sent in.
+pub const ATTACHMENT_FIELD: &str = "files";
+pub const CREATE_MESSAGE_WITH_ATTACHMENTS_PATH: &str = "/create-message-with-attachments";
+pub const ATTACHMENT_PATH: &str = "/attachments/{attachment_id}";
+pub const ATTACHMENT_THUMBNAIL_PATH: &str = "/attachments/{attachment_id}/thumbnail";
+/// Files and thumbnails are stored by hash, so what a URL serves never
+/// changes.
+const CACHE_FOREVER: &str = "private, max-age=31536000, immutable";
+
+/// A file sent with a message.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Attachment {
+    pub id: AttachmentId,
+    pub message_id: MessageId,
+    pub filename: String,
+    pub content_type: String,
+    /// In bytes.
+    #[ts(type = "number")]
+    pub size: i64,
+    /// SHA-256 of the content, hex encoded.
+    pub hash: String,
+    /// Pixel size, for images once they've been processed.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// SHA-256 of the image's thumbnail, once it has one.
+    pub thumbnail_hash: Option<String>,
+    /// Where to get the thumbnail. Filled in from `thumbnail_hash` rather
+    /// than stored.
+    #[sqlx(skip)]
+    pub thumbnail_url: Option<String>,
+}
+
+/// A file read from an upload, not stored yet.
+pub struct Upload {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Bytes,
+}
+
+impl Attachment {
+    /// Fills in each message's attachments.
+    pub async fn attach<'a>(
+        db: &Db,
+        messages: impl IntoIterator<Item = &'a mut Message>,
+    ) -> Result<(), ServerErr> {
+        let messages: Vec<&mut Message> = messages.into_iter().collect();
+        let ids: Vec<MessageId> = messages.iter().map(|message| message.id).collect();
+        let mut attachments: HashMap<MessageId, Vec<Attachment>> = HashMap::new();
+        for attachment in db.get_attachments(&ids).await? {
+            attachments
+                .entry(attachment.message_id)
+                .or_default()
+                .push(attachment);
+        }
+        for message in messages {
+            message.attachments = attachments.remove(&message.id).unwrap_or_default();
+            for attachment in &mut message.attachments {
+                attachment.fill_thumbnail_url();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fill_thumbnail_url(&mut self) {
+        self.thumbnail_url = self
+            .thumbnail_hash
+            .as_ref()
+            .map(|_| ATTACHMENT_THUMBNAIL_PATH.replace("{attachment_id}", &self.id.to_string()));
+    }
+
+    /// Gets the attachment if the user can view its channel.
+    async fn get_viewable(
+        db: &Db,
+        attachment_id: AttachmentId,
+        user_id: UserId,
+    ) -> Result<Self, ServerErr> {
+        let attachment = db
+            .get_attachment(attachment_id)
+            .await?
+            .ok_or(ServerErr::NoAttachmentId(attachment_id))?;
+        let message = Message::get(db, attachment.message_id).await?;
+        let channel = Channel::get(db, message.channel_id).await?;
+        MemberAccess::require_channel(db, &channel, user_id, Permissions::VIEW_CHANNEL).await?;
+        Ok(attachment)
+    }
+
+    /// Stores the uploads' contents, skipping blobs that are already stored,
+    /// and records them as the message's attachments. GPS positions are
+    /// taken out of images first.
+    pub async fn store(
+        db: &Db,
+        blobs: &Blobs,
+        message_id: MessageId,
+        uploads: Vec<Upload>,
+    ) -> Result<Vec<Self>, ServerErr> {
+        let mut attachments = Vec::with_capacity(uploads.len());
+        for upload in uploads {
+            let mut attachment = Self {
+                id: 0,
+                message_id,
+                filename: upload.filename,
+                content_type: upload.content_type,
+                size: upload.data.len() as i64,
+                hash: String::new(),
+                width: None,
+                height: None,
+                thumbnail_hash: None,
+                thumbnail_url: None,
+            };
+            let data = if attachment.is_image() {
+                strip_gps(upload.data)
+            } else {
+                upload.data
+            };
+            attachment.hash = put_content(blobs, data).await?;
+            attachment.id = db.insert_attachment(&attachment).await?;
+            attachments.push(attachment);
+        }
+        Ok(attachments)
+    }
+
+    /// Whether the image workers should look at the file. SVGs are left out
+    /// since they're drawn rather than decoded.
+    pub fn is_image(&self) -> bool {
+        self.content_type.starts_with("image/") && self.content_type != "image/svg+xml"
+    }
+
+    /// Whether browsers may show the file in the page rather than only
+    /// download it.
+    fn inline(&self) -> bool {
+        ["image/", "video/", "audio/"]
+            .iter()
+            .any(|prefix| self.content_type.starts_with(prefix))
+            && self.content_type != "image/svg+xml"
+    }
+
+    /// `Content-Disposition` with the filename, as plain ASCII for old
+    /// clients and percent-encoded UTF-8 for the rest.
+    fn disposition(&self) -> String {
+        let ascii: String = self
+            .filename
+            .chars()
+            .map(|c| match c {
+                ' '..='~' if c != '"' && c != '\\' => c,
+                _ => '_',
+            })
+            .collect();
+        let mut encoded = String::new();
+        for b in self.filename.bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                encoded.push(b as char);
+            } else {
+                encoded.push_str(&format!("%{b:02X}"));
+            }
+        }
+        let kind = if self.inline() {
+            "inline"
+        } else {
+            "attachment"
+        };
+        format!("{kind}; filename=\"{ascii}\"; filename*=UTF-8''{encoded}")
+    }
+}
+
+impl AttachmentConfig {
+    /// Whether files of the content type are accepted. Patterns ending in
+    /// `/*` accept every subtype.
+    pub fn allows(&self, content_type: &str) -> bool {
+        self.content_types.is_empty()
+            || self
+                .content_types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix("/*") {
+                    Some(kind) => content_type.split('/').next() == Some(kind),
+                    None => content_type == allowed,
+                })
+    }
+}
+
+/// Whether the text is a `type/subtype` pair of MIME tokens.
+fn is_content_type(text: &str) -> bool {
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+    };
+    matches!(text.split_once('/'), Some((kind, subtype)) if is_token(kind) && is_token(subtype))
+}
+
+/// Drops any path and control characters, and keeps to the length limit
+/// without splitting a character.
+fn clean_filename(filename: Option<&str>) -> String {
+    let name = filename
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let mut clean = String::new();
+    for c in name.chars().filter(|c| !c.is_control()) {
+        if clean.len() + c.len_utf8() > FILENAME_MAX_LEN {
+            break;
+        }
+        clean.push(c);
+    }
+    if clean.is_empty() {
+        "file".to_string()
+    } else {
+        clean
+    }
+}
+
+/// Reads the files out of the form, enforcing the attachment limits as it
+/// goes.
+async fn read_uploads(
+    config: &AttachmentConfig,
+    mut multipart: Multipart,
+) -> Result<Vec<Upload>, ServerErr> {
+    let mut uploads = Vec::new();
+    while let Some(mut field) = multipart.next_field().await? {
+        if field.name() != Some(ATTACHMENT_FIELD) {
+            return Err(ServerErr::BadRequest(format!(
+                "Unexpected form field {}, files go in {ATTACHMENT_FIELD}",
+                field.name().unwrap_or_default()
+            )));
+        }
+        if uploads.len() == config.max_per_message {
+            return Err(ServerErr::BadRequest(format!(
+                "Messages can have at most {} attachments",
+                config.max_per_message
+            )));
+        }
+        // Parameters like `charset` aren't kept.
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if !is_content_type(&content_type) || !config.allows(&content_type) {
+            return Err(ServerErr::ContentTypeNotAllowed(content_type));
+        }
+        let filename = clean_filename(field.file_name());
+        let mut data = BytesMut::new();
+        while let Some(chunk) = field.chunk().await? {
+            if data.len() + chunk.len() > config.max_size {
+                return Err(ServerErr::AttachmentTooLarge(filename, config.max_size));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        uploads.push(Upload {
+            filename,
+            content_type,
+            data: data.freeze(),
+        });
+    }
+    if uploads.is_empty() {
+        return Err(ServerErr::BadRequest(format!(
+            "No files in {ATTACHMENT_FIELD}"
+        )));
+    }
+    Ok(uploads)
+}
+
+/// The form `create_message_with_attachments` takes.
+#[derive(ToSchema)]
+#[allow(unused)]
+pub struct AttachmentForm {
+    /// One part per file, with its filename and content type.
+    #[schema(value_type = Vec<String>, format = Binary)]
+    files: Vec<Vec<u8>>,
+}
+
+#[utoipa::path(
+    post,
+    path = CREATE_MESSAGE_WITH_ATTACHMENTS_PATH,
+    params(CreateMessageParams),
+    request_body(content = AttachmentForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Create a new message with files attached", body = Message),
+        (status = 400, description = "No files, too many, or a content type that isn't accepted", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing SEND_MESSAGES, or the thread is locked", body = String),
+        (status = 413, description = "A file is over the size limit", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_message_with_attachments(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(config): State<AttachmentConfig>,
+    State(blobs): State<Blobs>,
+    State(images): State<ImageQueue>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<CreateMessageParams>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, ServerErr> {
+    let uploads = read_uploads(&config, multipart).await?;
+    let mut message = Message::insert(&db, &limits, user.id, query).await?;
+    // Nobody has seen the message yet, so it can go if the files can't be
+    // stored.
+    message.attachments = match Attachment::store(&db, &blobs, message.id, uploads).await {
+        Ok(attachments) => attachments,
+        Err(err) => {
+            db.delete_message(message.id).await?;
+            return Err(err);
+        }
+    };
+    message.publish(&db, &send).await?;
+    images.push(&message.attachments);
+    Ok(Json(message))
+}
+
+/// Parses a `Range` header for a single range of bytes. Returns `None` to
+/// send the whole file, which is also what happens with several ranges or
+/// ones that don't parse, and `Err` for ranges outside the file.
+fn parse_range(value: &HeaderValue, size: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some(spec) = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("bytes="))
+    else {
+        return Ok(None);
+    };
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // The last `end` bytes.
+        (Err(_), Ok(suffix)) if start.is_empty() => size.saturating_sub(suffix)..size,
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        (Ok(start), Ok(end)) if start <= end => start..(end + 1).min(size),
+        _ => return Ok(None),
+    };
+    if range.start >= size || range.is_empty() {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+#[utoipa::path(
+    get,
+    path = ATTACHMENT_PATH,
+    params(("attachment_id" = AttachmentId, Path, description = "Attachment ID")),
+    responses(
+        (status = 200, description = "Download an attachment. A single `Range` of bytes is honored", body = Vec<u8>),
+        (status = 206, description = "The requested range of the attachment", body = Vec<u8>),
+        (status = 400, description = "No such attachment", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 416, description = "The range is outside the file"),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_attachment(
+    State(db): State<Db>,
+    State(blobs): State<Blobs>,
+    AuthUser(user): AuthUser,
+    Path(attachment_id): Path<AttachmentId>,
+    headers: HeaderMap,
+) -> Result<Response, ServerErr> {
+    let attachment = Attachment::get_viewable(&db, attachment_id, user.id).await?;
+    let size = attachment.size as u64;
+    let range = match headers
+        .get(header::RANGE)
+        .map(|value| parse_range(value, size))
+    {
+        Some(Ok(range)) => range,
+        Some(Err(())) => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+            )
+                .into_response());
+        }
+        None => None,
+    };
+    let data = blobs
+        .get(&attachment.hash, range.clone().unwrap_or(0..size))
+        .await?;
+    let status = match range {
+        Some(_) => StatusCode::PARTIAL_CONTENT,
+        None => StatusCode::OK,
+    };
+    let mut headers = vec![
+        (header::CONTENT_TYPE, attachment.content_type.clone()),
+        (header::CONTENT_DISPOSITION, attachment.disposition()),
+        (header::ACCEPT_RANGES, "bytes".to_string()),
+        (header::ETAG, format!("\"{}\"", attachment.hash)),
+        (header::CACHE_CONTROL, CACHE_FOREVER.to_string()),
+        // Uploaders pick the content type, so browsers shouldn't second
+        // guess it into something that runs.
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+    if let Some(range) = range {
+        headers.push((
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{size}", range.start, range.end - 1),
+        ));
+    }
+    let mut response = (status, data).into_response();
+    for (name, value) in headers {
+        // Content types are checked on upload and filenames are encoded, so
+        // every value is valid.
+        let value =
+            HeaderValue::try_from(value).map_err(|err| ServerErr::BlobErr(err.to_string()))?;
+        response.headers_mut().insert(name, value);
+    }
+    Ok(response)
+}
+
+#[utoipa::path(
+    get,
+    path = ATTACHMENT_THUMBNAIL_PATH,
+    params(("attachment_id" = AttachmentId, Path, description = "Attachment ID")),
+    responses(
+        (status = 200, description = "Download an image attachment's thumbnail, a PNG or JPEG", body = Vec<u8>),
+        (status = 400, description = "No such attachment, or it has no thumbnail (yet)", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_attachment_thumbnail(
+    State(db): State<Db>,
+    State(blobs): State<Blobs>,
+    AuthUser(user): AuthUser,
+    Path(attachment_id): Path<AttachmentId>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let attachment = Attachment::get_viewable(&db, attachment_id, user.id).await?;
+    let hash = attachment
+        .thumbnail_hash
+        .ok_or(ServerErr::NoThumbnail(attachment_id))?;
+    let data = blobs.get_all(&hash).await?;
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                thumbnail_content_type(&data).to_string(),
+            ),
+            (header::ETAG, format!("\"{hash}\"")),
+            (header::CACHE_CONTROL, CACHE_FOREVER.to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        data,
+    ))
+}
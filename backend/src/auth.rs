@@ -0,0 +1,203 @@
+use crate::{config::AuthConfig, error::ServerErr, storage::Db, user::User};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{FromRef, FromRequestParts, State},
+    http::{
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        request::Parts,
+        HeaderMap,
+    },
+    response::{AppendHeaders, IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+pub const LOGIN_PATH: &str = "/login";
+pub const LOGOUT_PATH: &str = "/logout";
+pub const SESSION_COOKIE: &str = "session";
+pub const PASSWORD_MIN_LEN: usize = 8;
+pub const PASSWORD_MAX_LEN: usize = 128;
+
+/// A logged in session. The token is also set as an HttpOnly cookie, so
+/// browser clients don't need to store it; other clients send it as a
+/// `Authorization: Bearer <token>` header.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Session {
+    pub user: User,
+    pub token: String,
+    pub expires_ts: DateTime<Utc>,
+}
+
+impl Session {
+    pub async fn create(db: &Db, auth: &AuthConfig, user: User) -> Result<Self, ServerErr> {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+        let created_ts = Utc::now();
+        let expires_ts = created_ts + Duration::hours(auth.session_ttl_hours);
+        db.insert_session(&hash_token(&token), user.id, created_ts, expires_ts)
+            .await?;
+        Ok(Self {
+            user,
+            token,
+            expires_ts,
+        })
+    }
+
+    /// Responds with the session as JSON and sets the session cookie.
+    pub fn respond(self, auth: &AuthConfig) -> Response {
+        let max_age = (self.expires_ts - Utc::now()).num_seconds().max(0);
+        let secure = if auth.secure_cookies { "; Secure" } else { "" };
+        let cookie = format!(
+            "{SESSION_COOKIE}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={max_age}{secure}",
+            self.token
+        );
+        (AppendHeaders([(SET_COOKIE, cookie)]), Json(self)).into_response()
+    }
+}
+
+/// The user making the request, resolved from the session token.
+/// Rejects the request with 401 if there is no valid session.
+pub struct AuthUser(pub User);
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    Db: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ServerErr;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = session_token(&parts.headers).ok_or(ServerErr::Unauthorized)?;
+        let db = Db::from_ref(state);
+        db.get_session_user(&hash_token(&token), Utc::now())
+            .await?
+            .map(AuthUser)
+            .ok_or(ServerErr::Unauthorized)
+    }
+}
+
+/// Reads the session token from the bearer header, falling back to the cookie.
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.trim().to_string());
+    }
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token.to_string())
+}
+
+/// Sessions are stored hashed so a leaked database can't be used to log in.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn validate_password(password: &str) -> Result<(), ServerErr> {
+    let len = password.len();
+    if len < PASSWORD_MIN_LEN {
+        Err(ServerErr::PasswordTooShort(len, PASSWORD_MIN_LEN))
+    } else if len > PASSWORD_MAX_LEN {
+        Err(ServerErr::PasswordTooLong(len, PASSWORD_MAX_LEN))
+    } else {
+        Ok(())
+    }
+}
+
+/// Hashes with argon2 on the blocking pool, since it is deliberately slow.
+pub async fn hash_password(password: String) -> Result<String, ServerErr> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|err| ServerErr::PasswordHashErr(err.to_string()))
+    })
+    .await
+    .map_err(|err| ServerErr::PasswordHashErr(err.to_string()))?
+}
+
+pub async fn verify_password(password: String, hash: String) -> Result<bool, ServerErr> {
+    tokio::task::spawn_blocking(move || {
+        let hash =
+            PasswordHash::new(&hash).map_err(|err| ServerErr::PasswordHashErr(err.to_string()))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    })
+    .await
+    .map_err(|err| ServerErr::PasswordHashErr(err.to_string()))?
+}
+
+#[derive(Serialize, Deserialize, TS, ToSchema, Clone)]
+pub struct LoginParams {
+    #[schema(example = "My User Name")]
+    name: String,
+    password: String,
+}
+
+#[utoipa::path(
+    post,
+    path = LOGIN_PATH,
+    request_body = LoginParams,
+    responses(
+        (status = 200, description = "Log in and start a session", body = Session),
+        (status = 401, description = "Wrong username or password", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn login(
+    State(db): State<Db>,
+    State(auth): State<AuthConfig>,
+    Json(params): Json<LoginParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (user, hash) = db
+        .get_credentials(&params.name)
+        .await?
+        .ok_or(ServerErr::InvalidCredentials)?;
+    if !verify_password(params.password, hash).await? {
+        return Err(ServerErr::InvalidCredentials);
+    }
+    let removed = db.delete_expired_sessions(Utc::now()).await?;
+    if removed > 0 {
+        tracing::info!("Removed {removed} expired sessions");
+    }
+    let session = Session::create(&db, &auth, user).await?;
+    Ok(session.respond(&auth))
+}
+
+#[utoipa::path(
+    post,
+    path = LOGOUT_PATH,
+    responses(
+        (status = 200, description = "End the current session", body = ()),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn logout(
+    State(db): State<Db>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ServerErr> {
+    if let Some(token) = session_token(&headers) {
+        db.delete_session(&hash_token(&token)).await?;
+    }
+    let cookie = format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0");
+    Ok(AppendHeaders([(SET_COOKIE, cookie)]))
+}
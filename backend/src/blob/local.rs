@@ -0,0 +1,89 @@
+use super::BlobStore;
+use crate::error::ServerErr;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{
+    io::{ErrorKind, SeekFrom},
+    ops::Range,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+/// Blobs as files in a directory, spread over subdirectories named after
+/// the first two characters of their keys.
+pub struct LocalBlobs {
+    root: PathBuf,
+    /// Numbers temporary files, so concurrent writes of a key don't collide.
+    next_tmp: AtomicU64,
+}
+
+impl LocalBlobs {
+    /// Uses the directory, creating it if needed.
+    pub async fn open(root: &str) -> Result<Self, ServerErr> {
+        fs::create_dir_all(root).await.map_err(|err| {
+            ServerErr::ConfigErr(format!("Unable to create blob directory {root}: {err}"))
+        })?;
+        Ok(Self {
+            root: PathBuf::from(root),
+            next_tmp: AtomicU64::new(0),
+        })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, ServerErr> {
+        if key.len() < 2 || !key.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(ServerErr::BlobErr(format!("Invalid blob key {key}")));
+        }
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobs {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), ServerErr> {
+        let path = self.path(key)?;
+        let dir = path.parent().expect("blob paths have a parent");
+        fs::create_dir_all(dir).await?;
+        // Written under another name first, so readers never see part of a
+        // blob.
+        let tmp = dir.join(format!(
+            ".{key}.{}.tmp",
+            self.next_tmp.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, &data).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, ServerErr> {
+        Ok(fs::try_exists(self.path(key)?).await?)
+    }
+
+    async fn get(&self, key: &str, range: Range<u64>) -> Result<Bytes, ServerErr> {
+        let mut file = File::open(self.path(key)?)
+            .await
+            .map_err(|err| not_found(key, err))?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let mut data = vec![0; (range.end - range.start) as usize];
+        file.read_exact(&mut data).await?;
+        Ok(data.into())
+    }
+
+    async fn get_all(&self, key: &str) -> Result<Bytes, ServerErr> {
+        let data = fs::read(self.path(key)?)
+            .await
+            .map_err(|err| not_found(key, err))?;
+        Ok(data.into())
+    }
+}
+
+fn not_found(key: &str, err: std::io::Error) -> ServerErr {
+    if err.kind() == ErrorKind::NotFound {
+        ServerErr::BlobErr(format!("Blob {key} does not exist"))
+    } else {
+        err.into()
+    }
+}
@@ -0,0 +1,94 @@
+use crate::error::ServerErr;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{ops::Range, sync::Arc};
+
+pub mod local;
+#[cfg(feature = "s3")]
+pub mod s3;
+
+pub const DEFAULT_BLOBS_URL: &str = "blobs";
+pub const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// Shared handle to whichever blob store was selected at startup.
+pub type Blobs = Arc<dyn BlobStore>;
+
+/// Storage for file contents, addressed by key.
+///
+/// Keys are content hashes chosen by the caller, so a key's data never
+/// changes once written.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores the data under the key, replacing whatever was there.
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), ServerErr>;
+    async fn exists(&self, key: &str) -> Result<bool, ServerErr>;
+    /// Reads the bytes in `range`, which must lie within the blob.
+    async fn get(&self, key: &str, range: Range<u64>) -> Result<Bytes, ServerErr>;
+    async fn get_all(&self, key: &str) -> Result<Bytes, ServerErr>;
+}
+
+/// Stores the data under its SHA-256, unless it's already there, and returns
+/// the key.
+pub async fn put_content(blobs: &Blobs, data: Bytes) -> Result<String, ServerErr> {
+    let hash = hex::encode(Sha256::digest(&data));
+    if !blobs.exists(&hash).await? {
+        blobs.put(&hash, data).await?;
+    }
+    Ok(hash)
+}
+
+/// Blob store settings, the `[blobs]` table of the config file.
+///
+/// `url` is a local directory, or `s3://<bucket>` when built with the `s3`
+/// feature. S3 credentials come from the usual `AWS_*` environment variables.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlobConfig {
+    pub url: String,
+    /// S3-compatible endpoint to use instead of AWS. Empty for AWS.
+    pub endpoint: String,
+    pub region: String,
+}
+
+impl Default for BlobConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_BLOBS_URL.to_string(),
+            endpoint: String::new(),
+            region: DEFAULT_S3_REGION.to_string(),
+        }
+    }
+}
+
+impl BlobConfig {
+    fn is_s3(&self) -> bool {
+        self.url.starts_with("s3://")
+    }
+
+    /// Picks a blob store from the URL scheme and checks it can be used.
+    pub async fn connect(&self) -> Result<Blobs, ServerErr> {
+        if self.is_s3() {
+            self.connect_s3()
+        } else {
+            let blobs = local::LocalBlobs::open(&self.url).await?;
+            tracing::info!("Storing blobs in {}", self.url);
+            Ok(Arc::new(blobs))
+        }
+    }
+
+    #[cfg(feature = "s3")]
+    fn connect_s3(&self) -> Result<Blobs, ServerErr> {
+        let blobs = s3::S3Blobs::new(self)?;
+        tracing::info!("Storing blobs in {}", self.url);
+        Ok(Arc::new(blobs))
+    }
+
+    #[cfg(not(feature = "s3"))]
+    fn connect_s3(&self) -> Result<Blobs, ServerErr> {
+        Err(ServerErr::ConfigErr(
+            "S3 blob storage requires building with the `s3` feature".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,74 @@
+use super::{BlobConfig, BlobStore};
+use crate::error::ServerErr;
+use async_trait::async_trait;
+use bytes::Bytes;
+use object_store::{
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path,
+    Error as ObjectStoreError, ObjectStore,
+};
+use std::ops::Range;
+
+/// Blobs as objects in an S3 bucket, or a bucket of anything speaking the
+/// S3 API.
+pub struct S3Blobs {
+    store: AmazonS3,
+}
+
+impl S3Blobs {
+    pub fn new(config: &BlobConfig) -> Result<Self, ServerErr> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_url(&config.url)
+            .with_region(&config.region);
+        if !config.endpoint.is_empty() {
+            // Stand-ins for S3 are usually local and don't route
+            // `<bucket>.<host>` names.
+            builder = builder
+                .with_endpoint(&config.endpoint)
+                .with_allow_http(config.endpoint.starts_with("http://"))
+                .with_virtual_hosted_style_request(false);
+        }
+        let store = builder
+            .build()
+            .map_err(|err| ServerErr::ConfigErr(format!("Invalid S3 blob store: {err}")))?;
+        Ok(Self { store })
+    }
+}
+
+fn blob_err(err: ObjectStoreError) -> ServerErr {
+    ServerErr::BlobErr(err.to_string())
+}
+
+#[async_trait]
+impl BlobStore for S3Blobs {
+    async fn put(&self, key: &str, data: Bytes) -> Result<(), ServerErr> {
+        self.store
+            .put(&Path::from(key), data.into())
+            .await
+            .map_err(blob_err)?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, ServerErr> {
+        match self.store.head(&Path::from(key)).await {
+            Ok(_) => Ok(true),
+            Err(ObjectStoreError::NotFound { .. }) => Ok(false),
+            Err(err) => Err(blob_err(err)),
+        }
+    }
+
+    async fn get(&self, key: &str, range: Range<u64>) -> Result<Bytes, ServerErr> {
+        if range.is_empty() {
+            return Ok(Bytes::new());
+        }
+        self.store
+            .get_range(&Path::from(key), range)
+            .await
+            .map_err(blob_err)
+    }
+
+    async fn get_all(&self, key: &str) -> Result<Bytes, ServerErr> {
+        let object = self.store.get(&Path::from(key)).await.map_err(blob_err)?;
+        object.bytes().await.map_err(blob_err)
+    }
+}
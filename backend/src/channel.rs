@@ -1,11 +1,21 @@
-use crate::{error::ServerErr, server::ServerId, snapshot::Update, Sender};
+use crate::{
+    auth::AuthUser,
+    config::Limits,
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
 use axum::{
     extract::{Query, State},
-    response::{sse::Event, IntoResponse},
+    response::IntoResponse,
     Json,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_scalar, SqlitePool};
+use sqlx::FromRow;
 use ts_rs::TS;
 use utoipa::{IntoParams, ToSchema};
 
@@ -14,43 +24,61 @@ pub type ChannelId = i32;
 pub const CHANNEL_NAME_MAX_LEN: usize = 32;
 pub const CREATE_CHANNEL_PATH: &str = "/create-channel";
 
-#[derive(Serialize, Deserialize, TS, ToSchema, Clone, Debug)]
+/// Server channels are text channels; DMs belong to their recipients
+/// instead of a server.
+#[derive(Serialize, Deserialize, TS, ToSchema, Clone, Copy, Debug, PartialEq, Eq, sqlx::Type)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+#[repr(i32)]
+pub enum ChannelKind {
+    Text = 0,
+    /// Between two users, who stay its only recipients.
+    Dm = 1,
+    /// Recipients can be added, and removed by the owner.
+    GroupDm = 2,
+}
+
+#[derive(Serialize, Deserialize, TS, ToSchema, Clone, Debug, FromRow)]
 #[ts(export, export_to = "../../frontend/src/bindings/")]
 pub struct Channel {
-    pub server_id: ServerId,
+    /// Unset for DMs.
+    pub server_id: Option<ServerId>,
     pub id: ChannelId,
     pub name: String,
+    pub kind: ChannelKind,
+    /// Who can remove others from a group DM. Unset for other channels.
+    pub owner_id: Option<UserId>,
 }
 
 impl Channel {
     pub async fn insert(
-        pool: &SqlitePool,
+        db: &Db,
+        limits: &Limits,
         server_id: ServerId,
         name: String,
     ) -> Result<Self, ServerErr> {
         let len = name.len();
-        if len > CHANNEL_NAME_MAX_LEN {
-            Err(ServerErr::ChannelNameTooLong(len))
+        if len > limits.channel_name_max_len {
+            Err(ServerErr::ChannelNameTooLong(
+                len,
+                limits.channel_name_max_len,
+            ))
         } else {
-            let id = query!(
-                r#"
-                INSERT INTO channels (server_id, name)
-                VALUES (?1, ?2)
-                RETURNING id AS "id!: i32";
-                "#,
-                server_id,
-                name
-            )
-            .fetch_one(pool)
-            .await?
-            .id;
+            let id = db.insert_channel(server_id, &name).await?;
             Ok(Self {
-                server_id,
+                server_id: Some(server_id),
                 id,
                 name,
+                kind: ChannelKind::Text,
+                owner_id: None,
             })
         }
     }
+
+    pub async fn get(db: &Db, channel_id: ChannelId) -> Result<Self, ServerErr> {
+        db.get_channel(channel_id)
+            .await?
+            .ok_or(ServerErr::NoChannelId(channel_id))
+    }
 }
 
 #[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
@@ -67,27 +95,21 @@ pub struct CreateChannelParams {
     params(CreateChannelParams),
     responses(
         (status = 200, description = "Create a new channel", body = Channel),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_CHANNELS", body = String),
         (status = 500, description = "Internal server error", body = String)
     )
 )]
 pub async fn create_channel(
-    State(pool): State<SqlitePool>,
+    State(db): State<Db>,
+    State(limits): State<Limits>,
     State(send): State<Sender>,
+    AuthUser(user): AuthUser,
     Query(query): Query<CreateChannelParams>,
 ) -> Result<impl IntoResponse, ServerErr> {
-    let server_id_exists = 1
-        == query_scalar!(
-            r#"SELECT EXISTS(SELECT 1 FROM servers WHERE id = ?1);"#,
-            query.server_id
-        )
-        .fetch_one(&pool)
-        .await?;
-    if !server_id_exists {
-        return Err(ServerErr::NoServerId(query.server_id));
-    }
-    let channel = Channel::insert(&pool, query.server_id, query.name).await?;
-    let event = Event::default().json_data(Update::Channel(channel.clone()))?;
-    if let Err(err) = send.send(event) {
+    MemberAccess::require(&db, query.server_id, user.id, Permissions::MANAGE_CHANNELS).await?;
+    let channel = Channel::insert(&db, &limits, query.server_id, query.name).await?;
+    if let Err(err) = send.send(Update::Channel(channel.clone())) {
         tracing::error!("Error sending event: {err:?}");
     }
     Ok(Json(channel))
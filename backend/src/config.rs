@@ -0,0 +1,574 @@
+use crate::{
+    attachment::{ATTACHMENTS_PER_MESSAGE_MAX, ATTACHMENT_MAX_SIZE},
+    blob::BlobConfig,
+    channel::CHANNEL_NAME_MAX_LEN,
+    db::DbConfig,
+    dm::GROUP_DM_MAX_RECIPIENTS,
+    emoji::EMOJI_NAME_MAX_LEN,
+    error::ServerErr,
+    gateway::HEARTBEAT_INTERVAL_MS,
+    media::{IMAGE_QUEUE_LEN, IMAGE_WORKERS, THUMBNAIL_SIZE},
+    message::{BULK_DELETE_MAX, HISTORY_PAGE_MAX, MESSAGE_MAX_LEN},
+    role::ROLE_NAME_MAX_LEN,
+    search::SEARCH_PAGE_MAX,
+    server::SERVER_NAME_MAX_LEN,
+    snapshot::{SNAPSHOT_CHANNEL_MESSAGES, SNAPSHOT_DEPTH},
+    thread::{THREAD_ARCHIVE_CHECK_SECS, THREAD_AUTO_ARCHIVE_SECS, THREAD_NAME_MAX_LEN},
+    update_log::{UPDATE_LOG_LEN, UPDATE_REPLAY_LEN},
+    user::USERNAME_MAX_LEN,
+};
+use clap::{builder::BoolishValueParser, Parser};
+use serde::Deserialize;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+pub const DEFAULT_BIND_ADDR: &str = "0.0.0.0:3000";
+pub const DEFAULT_STATIC_DIR: &str = "frontend/out";
+pub const DEFAULT_MAX_BROADCAST: usize = 1000;
+pub const DEFAULT_SESSION_TTL_HOURS: i64 = 24 * 30;
+
+/// Runtime configuration.
+///
+/// Layered from lowest to highest priority: built-in defaults, the TOML file,
+/// environment variables, then command line flags.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_addr: SocketAddr,
+    pub static_dir: PathBuf,
+    pub max_broadcast: usize,
+    pub database: DbConfig,
+    pub blobs: BlobConfig,
+    pub limits: Limits,
+    pub attachments: AttachmentConfig,
+    pub auth: AuthConfig,
+    pub threads: ThreadConfig,
+    pub updates: UpdateConfig,
+    pub gateway: GatewayConfig,
+    pub seed: SeedConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_addr: DEFAULT_BIND_ADDR
+                .parse()
+                .expect("valid default bind address"),
+            static_dir: PathBuf::from(DEFAULT_STATIC_DIR),
+            max_broadcast: DEFAULT_MAX_BROADCAST,
+            database: DbConfig::default(),
+            blobs: BlobConfig::default(),
+            limits: Limits::default(),
+            attachments: AttachmentConfig::default(),
+            auth: AuthConfig::default(),
+            threads: ThreadConfig::default(),
+            updates: UpdateConfig::default(),
+            gateway: GatewayConfig::default(),
+            seed: SeedConfig::default(),
+        }
+    }
+}
+
+/// Size limits enforced by the handlers.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub snapshot_depth: i64,
+    /// Newest messages of each channel included in the snapshot.
+    pub snapshot_channel_messages: i64,
+    pub message_max_len: usize,
+    pub username_max_len: usize,
+    pub channel_name_max_len: usize,
+    pub server_name_max_len: usize,
+    pub role_name_max_len: usize,
+    pub emoji_name_max_len: usize,
+    pub thread_name_max_len: usize,
+    /// Most messages a bulk delete or purge removes at once.
+    pub bulk_delete_max: usize,
+    /// Most messages one page of channel history returns.
+    pub history_page_max: usize,
+    /// Most messages one page of search results returns.
+    pub search_page_max: usize,
+    /// Most users in a group DM, its owner included.
+    pub group_dm_max_recipients: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            snapshot_depth: SNAPSHOT_DEPTH,
+            snapshot_channel_messages: SNAPSHOT_CHANNEL_MESSAGES,
+            message_max_len: MESSAGE_MAX_LEN,
+            username_max_len: USERNAME_MAX_LEN,
+            channel_name_max_len: CHANNEL_NAME_MAX_LEN,
+            server_name_max_len: SERVER_NAME_MAX_LEN,
+            role_name_max_len: ROLE_NAME_MAX_LEN,
+            emoji_name_max_len: EMOJI_NAME_MAX_LEN,
+            thread_name_max_len: THREAD_NAME_MAX_LEN,
+            bulk_delete_max: BULK_DELETE_MAX,
+            history_page_max: HISTORY_PAGE_MAX,
+            search_page_max: SEARCH_PAGE_MAX,
+            group_dm_max_recipients: GROUP_DM_MAX_RECIPIENTS,
+        }
+    }
+}
+
+/// Files sent with messages.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentConfig {
+    /// Largest accepted file, in bytes.
+    pub max_size: usize,
+    /// Most files on one message.
+    pub max_per_message: usize,
+    /// Accepted content types. `image/*` accepts every image type, and an
+    /// empty list accepts anything.
+    pub content_types: Vec<String>,
+    /// Longest side of image thumbnails, in pixels.
+    pub thumbnail_size: u32,
+    /// How many images are processed at once.
+    pub image_workers: usize,
+    /// How many images can wait for a worker. Uploads beyond that are
+    /// processed after the next restart.
+    pub image_queue_len: usize,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            max_size: ATTACHMENT_MAX_SIZE,
+            max_per_message: ATTACHMENTS_PER_MESSAGE_MAX,
+            content_types: [
+                "image/*",
+                "video/*",
+                "audio/*",
+                "text/plain",
+                "application/pdf",
+                "application/zip",
+            ]
+            .map(String::from)
+            .to_vec(),
+            thumbnail_size: THUMBNAIL_SIZE,
+            image_workers: IMAGE_WORKERS,
+            image_queue_len: IMAGE_QUEUE_LEN,
+        }
+    }
+}
+
+/// Session settings.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub session_ttl_hours: i64,
+    /// Mark the session cookie `Secure`; enable when served over HTTPS.
+    pub secure_cookies: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            session_ttl_hours: DEFAULT_SESSION_TTL_HOURS,
+            secure_cookies: false,
+        }
+    }
+}
+
+/// Thread auto-archiving.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreadConfig {
+    /// Threads without new messages for this long are archived.
+    pub auto_archive_secs: i64,
+    /// How often to look for threads to archive.
+    pub archive_check_secs: u64,
+}
+
+impl Default for ThreadConfig {
+    fn default() -> Self {
+        Self {
+            auto_archive_secs: THREAD_AUTO_ARCHIVE_SECS,
+            archive_check_secs: THREAD_ARCHIVE_CHECK_SECS,
+        }
+    }
+}
+
+/// Catching up clients that reconnect to `/updates` or fall behind.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdateConfig {
+    /// How many of the latest updates are kept in memory.
+    pub replay_len: usize,
+    /// Also keep updates in the database, so clients can catch up from
+    /// further back and across restarts.
+    pub log: bool,
+    /// How many updates the database keeps.
+    pub log_len: usize,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            replay_len: UPDATE_REPLAY_LEN,
+            log: false,
+            log_len: UPDATE_LOG_LEN,
+        }
+    }
+}
+
+/// The `/gateway` WebSocket.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayConfig {
+    /// How often clients are asked to heartbeat. Connections that miss one
+    /// by half an interval are closed.
+    pub heartbeat_interval_ms: u64,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval_ms: HEARTBEAT_INTERVAL_MS,
+        }
+    }
+}
+
+/// Data created on startup when the database has no servers yet.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeedConfig {
+    pub enabled: bool,
+    pub server_name: String,
+    pub channel_name: String,
+    /// Code of a permanent invite to the seeded server. Empty to skip it.
+    pub invite_code: String,
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            server_name: "My First Server".to_string(),
+            channel_name: "Home".to_string(),
+            invite_code: "welcome".to_string(),
+        }
+    }
+}
+
+/// Command line flags. Each flag can also be set with the environment variable
+/// named next to it; flags win over the environment, which wins over the file.
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "Discord mockup backend")]
+pub struct Cli {
+    /// TOML config file [default: config.toml, if present]
+    #[arg(long, env = "CONFIG_PATH")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "BIND_ADDR")]
+    pub bind_addr: Option<SocketAddr>,
+    #[arg(long, env = "STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+    #[arg(long, env = "MAX_BROADCAST")]
+    pub max_broadcast: Option<usize>,
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: Option<String>,
+    #[arg(long, env = "DATABASE_MAX_CONNECTIONS")]
+    pub database_max_connections: Option<u32>,
+    #[arg(long, env = "DATABASE_MIN_CONNECTIONS")]
+    pub database_min_connections: Option<u32>,
+    #[arg(long, env = "DATABASE_BUSY_TIMEOUT_MS")]
+    pub database_busy_timeout_ms: Option<u64>,
+    #[arg(long, env = "DATABASE_WAL", value_parser = BoolishValueParser::new())]
+    pub database_wal: Option<bool>,
+    #[arg(long, env = "BLOBS_URL")]
+    pub blobs_url: Option<String>,
+    #[arg(long, env = "BLOBS_ENDPOINT")]
+    pub blobs_endpoint: Option<String>,
+    #[arg(long, env = "BLOBS_REGION")]
+    pub blobs_region: Option<String>,
+    #[arg(long, env = "SNAPSHOT_DEPTH")]
+    pub snapshot_depth: Option<i64>,
+    #[arg(long, env = "SNAPSHOT_CHANNEL_MESSAGES")]
+    pub snapshot_channel_messages: Option<i64>,
+    #[arg(long, env = "MESSAGE_MAX_LEN")]
+    pub message_max_len: Option<usize>,
+    #[arg(long, env = "USERNAME_MAX_LEN")]
+    pub username_max_len: Option<usize>,
+    #[arg(long, env = "CHANNEL_NAME_MAX_LEN")]
+    pub channel_name_max_len: Option<usize>,
+    #[arg(long, env = "SERVER_NAME_MAX_LEN")]
+    pub server_name_max_len: Option<usize>,
+    #[arg(long, env = "ROLE_NAME_MAX_LEN")]
+    pub role_name_max_len: Option<usize>,
+    #[arg(long, env = "EMOJI_NAME_MAX_LEN")]
+    pub emoji_name_max_len: Option<usize>,
+    #[arg(long, env = "THREAD_NAME_MAX_LEN")]
+    pub thread_name_max_len: Option<usize>,
+    #[arg(long, env = "BULK_DELETE_MAX")]
+    pub bulk_delete_max: Option<usize>,
+    #[arg(long, env = "HISTORY_PAGE_MAX")]
+    pub history_page_max: Option<usize>,
+    #[arg(long, env = "SEARCH_PAGE_MAX")]
+    pub search_page_max: Option<usize>,
+    #[arg(long, env = "GROUP_DM_MAX_RECIPIENTS")]
+    pub group_dm_max_recipients: Option<usize>,
+    #[arg(long, env = "ATTACHMENT_MAX_SIZE")]
+    pub attachment_max_size: Option<usize>,
+    #[arg(long, env = "ATTACHMENTS_PER_MESSAGE_MAX")]
+    pub attachments_per_message_max: Option<usize>,
+    /// Comma separated
+    #[arg(long, env = "ATTACHMENT_CONTENT_TYPES", value_delimiter = ',')]
+    pub attachment_content_types: Option<Vec<String>>,
+    #[arg(long, env = "THUMBNAIL_SIZE")]
+    pub thumbnail_size: Option<u32>,
+    #[arg(long, env = "IMAGE_WORKERS")]
+    pub image_workers: Option<usize>,
+    #[arg(long, env = "IMAGE_QUEUE_LEN")]
+    pub image_queue_len: Option<usize>,
+    #[arg(long, env = "SESSION_TTL_HOURS")]
+    pub session_ttl_hours: Option<i64>,
+    #[arg(long, env = "SECURE_COOKIES", value_parser = BoolishValueParser::new())]
+    pub secure_cookies: Option<bool>,
+    #[arg(long, env = "THREAD_AUTO_ARCHIVE_SECS")]
+    pub thread_auto_archive_secs: Option<i64>,
+    #[arg(long, env = "THREAD_ARCHIVE_CHECK_SECS")]
+    pub thread_archive_check_secs: Option<u64>,
+    #[arg(long, env = "UPDATE_REPLAY_LEN")]
+    pub update_replay_len: Option<usize>,
+    #[arg(long, env = "UPDATE_LOG", value_parser = BoolishValueParser::new())]
+    pub update_log: Option<bool>,
+    #[arg(long, env = "UPDATE_LOG_LEN")]
+    pub update_log_len: Option<usize>,
+    #[arg(long, env = "GATEWAY_HEARTBEAT_INTERVAL_MS")]
+    pub gateway_heartbeat_interval_ms: Option<u64>,
+    #[arg(long, env = "SEED", value_parser = BoolishValueParser::new())]
+    pub seed: Option<bool>,
+    #[arg(long, env = "SEED_INVITE_CODE")]
+    pub seed_invite_code: Option<String>,
+}
+
+impl Cli {
+    fn apply(self, config: &mut Config) {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        set(&mut config.bind_addr, self.bind_addr);
+        set(&mut config.static_dir, self.static_dir);
+        set(&mut config.max_broadcast, self.max_broadcast);
+        set(&mut config.database.url, self.database_url);
+        set(
+            &mut config.database.max_connections,
+            self.database_max_connections,
+        );
+        set(
+            &mut config.database.min_connections,
+            self.database_min_connections,
+        );
+        set(
+            &mut config.database.busy_timeout_ms,
+            self.database_busy_timeout_ms,
+        );
+        set(&mut config.database.wal, self.database_wal);
+        set(&mut config.blobs.url, self.blobs_url);
+        set(&mut config.blobs.endpoint, self.blobs_endpoint);
+        set(&mut config.blobs.region, self.blobs_region);
+        set(&mut config.limits.snapshot_depth, self.snapshot_depth);
+        set(
+            &mut config.limits.snapshot_channel_messages,
+            self.snapshot_channel_messages,
+        );
+        set(&mut config.limits.message_max_len, self.message_max_len);
+        set(&mut config.limits.username_max_len, self.username_max_len);
+        set(
+            &mut config.limits.channel_name_max_len,
+            self.channel_name_max_len,
+        );
+        set(
+            &mut config.limits.server_name_max_len,
+            self.server_name_max_len,
+        );
+        set(&mut config.limits.role_name_max_len, self.role_name_max_len);
+        set(
+            &mut config.limits.emoji_name_max_len,
+            self.emoji_name_max_len,
+        );
+        set(
+            &mut config.limits.thread_name_max_len,
+            self.thread_name_max_len,
+        );
+        set(&mut config.limits.bulk_delete_max, self.bulk_delete_max);
+        set(&mut config.limits.history_page_max, self.history_page_max);
+        set(&mut config.limits.search_page_max, self.search_page_max);
+        set(
+            &mut config.limits.group_dm_max_recipients,
+            self.group_dm_max_recipients,
+        );
+        set(&mut config.attachments.max_size, self.attachment_max_size);
+        set(
+            &mut config.attachments.max_per_message,
+            self.attachments_per_message_max,
+        );
+        set(
+            &mut config.attachments.content_types,
+            self.attachment_content_types,
+        );
+        set(&mut config.attachments.thumbnail_size, self.thumbnail_size);
+        set(&mut config.attachments.image_workers, self.image_workers);
+        set(
+            &mut config.attachments.image_queue_len,
+            self.image_queue_len,
+        );
+        set(&mut config.auth.session_ttl_hours, self.session_ttl_hours);
+        set(&mut config.auth.secure_cookies, self.secure_cookies);
+        set(
+            &mut config.threads.auto_archive_secs,
+            self.thread_auto_archive_secs,
+        );
+        set(
+            &mut config.threads.archive_check_secs,
+            self.thread_archive_check_secs,
+        );
+        set(&mut config.updates.replay_len, self.update_replay_len);
+        set(&mut config.updates.log, self.update_log);
+        set(&mut config.updates.log_len, self.update_log_len);
+        set(
+            &mut config.gateway.heartbeat_interval_ms,
+            self.gateway_heartbeat_interval_ms,
+        );
+        set(&mut config.seed.enabled, self.seed);
+        set(&mut config.seed.invite_code, self.seed_invite_code);
+    }
+}
+
+impl Config {
+    /// Loads the config from the process arguments and environment.
+    pub fn load() -> Result<Self, ServerErr> {
+        Self::from_cli(Cli::parse())
+    }
+
+    pub fn from_cli(cli: Cli) -> Result<Self, ServerErr> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+        cli.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ServerErr> {
+        let text = fs::read_to_string(path).map_err(|err| {
+            ServerErr::ConfigErr(format!("Unable to read {}: {err}", path.display()))
+        })?;
+        toml::from_str(&text).map_err(|err| {
+            ServerErr::ConfigErr(format!("Unable to parse {}: {err}", path.display()))
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), ServerErr> {
+        if self.max_broadcast == 0 {
+            return Err(ServerErr::ConfigErr(
+                "max_broadcast must be at least 1".to_string(),
+            ));
+        }
+        self.database.validate()?;
+        self.limits.validate()?;
+        if self.attachments.max_size == 0 || self.attachments.max_per_message == 0 {
+            return Err(ServerErr::ConfigErr(
+                "attachments.max_size and attachments.max_per_message must be at least 1"
+                    .to_string(),
+            ));
+        }
+        if self.attachments.thumbnail_size == 0
+            || self.attachments.image_workers == 0
+            || self.attachments.image_queue_len == 0
+        {
+            return Err(ServerErr::ConfigErr(
+                "attachments.thumbnail_size, attachments.image_workers and attachments.image_queue_len must be at least 1"
+                    .to_string(),
+            ));
+        }
+        if self.auth.session_ttl_hours <= 0 {
+            return Err(ServerErr::ConfigErr(
+                "auth.session_ttl_hours must be at least 1".to_string(),
+            ));
+        }
+        if self.threads.auto_archive_secs <= 0 {
+            return Err(ServerErr::ConfigErr(
+                "threads.auto_archive_secs must be at least 1".to_string(),
+            ));
+        }
+        if self.threads.archive_check_secs == 0 {
+            return Err(ServerErr::ConfigErr(
+                "threads.archive_check_secs must be at least 1".to_string(),
+            ));
+        }
+        if self.updates.replay_len == 0 || self.updates.log_len == 0 {
+            return Err(ServerErr::ConfigErr(
+                "updates.replay_len and updates.log_len must be at least 1".to_string(),
+            ));
+        }
+        if self.gateway.heartbeat_interval_ms == 0 {
+            return Err(ServerErr::ConfigErr(
+                "gateway.heartbeat_interval_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.seed.enabled {
+            if self.seed.server_name.len() > self.limits.server_name_max_len {
+                return Err(ServerErr::ConfigErr(
+                    "seed.server_name exceeds limits.server_name_max_len".to_string(),
+                ));
+            }
+            if self.seed.channel_name.len() > self.limits.channel_name_max_len {
+                return Err(ServerErr::ConfigErr(
+                    "seed.channel_name exceeds limits.channel_name_max_len".to_string(),
+                ));
+            }
+        }
+        if !self.static_dir.is_dir() {
+            tracing::warn!(
+                "Static dir {} does not exist, the frontend will not be served",
+                self.static_dir.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), ServerErr> {
+        let limits = [
+            ("snapshot_depth", self.snapshot_depth.max(0) as usize),
+            (
+                "snapshot_channel_messages",
+                self.snapshot_channel_messages.max(0) as usize,
+            ),
+            ("message_max_len", self.message_max_len),
+            ("username_max_len", self.username_max_len),
+            ("channel_name_max_len", self.channel_name_max_len),
+            ("server_name_max_len", self.server_name_max_len),
+            ("role_name_max_len", self.role_name_max_len),
+            ("emoji_name_max_len", self.emoji_name_max_len),
+            ("thread_name_max_len", self.thread_name_max_len),
+            ("bulk_delete_max", self.bulk_delete_max),
+            ("history_page_max", self.history_page_max),
+            ("search_page_max", self.search_page_max),
+            ("group_dm_max_recipients", self.group_dm_max_recipients),
+        ];
+        for (name, value) in limits {
+            if value == 0 {
+                return Err(ServerErr::ConfigErr(format!(
+                    "limits.{name} must be at least 1"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
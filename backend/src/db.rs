@@ -0,0 +1,178 @@
+use crate::{
+    error::ServerErr,
+    storage::{sqlite::SqliteStorage, Db},
+};
+use serde::Deserialize;
+use sqlx::{
+    migrate,
+    migrate::{Migrate, MigrateError},
+    query,
+    sqlite::{
+        SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions,
+        SqliteSynchronous,
+    },
+    Executor,
+};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://discord.db";
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 8;
+pub const DEFAULT_MIN_CONNECTIONS: u32 = 1;
+pub const DEFAULT_BUSY_TIMEOUT_MS: u64 = 5000;
+
+/// Database settings, the `[database]` table of the config file.
+///
+/// `busy_timeout_ms` doubles as the pool acquire timeout for Postgres.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DbConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub busy_timeout_ms: u64,
+    pub wal: bool,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_DATABASE_URL.to_string(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            min_connections: DEFAULT_MIN_CONNECTIONS,
+            busy_timeout_ms: DEFAULT_BUSY_TIMEOUT_MS,
+            wal: true,
+        }
+    }
+}
+
+impl DbConfig {
+    pub fn validate(&self) -> Result<(), ServerErr> {
+        if self.max_connections == 0 {
+            return Err(ServerErr::ConfigErr(
+                "database.max_connections must be at least 1".to_string(),
+            ));
+        }
+        if self.min_connections > self.max_connections {
+            return Err(ServerErr::ConfigErr(format!(
+                "database.min_connections ({}) exceeds database.max_connections ({})",
+                self.min_connections, self.max_connections
+            )));
+        }
+        Ok(())
+    }
+
+    fn busy_timeout(&self) -> Duration {
+        Duration::from_millis(self.busy_timeout_ms)
+    }
+
+    fn in_memory(&self) -> bool {
+        self.url.contains(":memory:") || self.url.contains("mode=memory")
+    }
+
+    fn is_postgres(&self) -> bool {
+        self.url.starts_with("postgres://") || self.url.starts_with("postgresql://")
+    }
+
+    /// Picks a storage backend from the URL scheme, connects and runs its migrations.
+    pub async fn connect(&self) -> Result<Db, ServerErr> {
+        if self.is_postgres() {
+            self.connect_postgres().await
+        } else {
+            self.connect_sqlite().await
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    async fn connect_postgres(&self) -> Result<Db, ServerErr> {
+        use crate::storage::postgres::PostgresStorage;
+        use sqlx::postgres::PgPoolOptions;
+
+        let pool = PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.busy_timeout())
+            .connect(&self.url)
+            .await?;
+        migrate!("../migrations_postgres").run(&pool).await?;
+        tracing::info!("Connected to Postgres database");
+        Ok(Arc::new(PostgresStorage::new(pool)))
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    async fn connect_postgres(&self) -> Result<Db, ServerErr> {
+        Err(ServerErr::ConfigErr(
+            "Postgres support requires building with the `postgres` feature".to_string(),
+        ))
+    }
+
+    /// Opens the pool, creating the database file if needed, and runs migrations.
+    async fn connect_sqlite(&self) -> Result<Db, ServerErr> {
+        let journal_mode = if self.wal && !self.in_memory() {
+            SqliteJournalMode::Wal
+        } else {
+            SqliteJournalMode::Delete
+        };
+        let options = SqliteConnectOptions::from_str(&self.url)?
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .journal_mode(journal_mode)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(self.busy_timeout());
+        let mut pool_options = SqlitePoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections);
+        if self.in_memory() {
+            // Every connection to `sqlite::memory:` is its own database, so keep exactly one alive.
+            pool_options = pool_options
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
+        let pool = pool_options.connect_with(options).await?;
+        migrate_sqlite(&mut *pool.acquire().await?).await?;
+        tracing::info!("Connected to SQLite database at {}", self.url);
+        Ok(Arc::new(SqliteStorage::new(pool)))
+    }
+}
+
+/// Runs the pending SQLite migrations in order. sqlx 0.8 runs every SQLite
+/// migration in a transaction, but ones marked `-- no-transaction` rebuild
+/// tables and have to turn foreign keys off first, which SQLite ignores inside
+/// a transaction. Those are run as they are, and manage their own transaction.
+async fn migrate_sqlite(conn: &mut SqliteConnection) -> Result<(), ServerErr> {
+    conn.ensure_migrations_table().await?;
+    let applied: HashMap<i64, _> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect();
+    for migration in migrate!("../migrations").iter() {
+        if migration.migration_type.is_down_migration() {
+            continue;
+        }
+        if let Some(checksum) = applied.get(&migration.version) {
+            if *checksum != migration.checksum {
+                return Err(MigrateError::VersionMismatch(migration.version).into());
+            }
+            continue;
+        }
+        if !migration.no_tx {
+            conn.apply(migration).await?;
+            continue;
+        }
+        conn.execute(&*migration.sql)
+            .await
+            .map_err(|err| MigrateError::ExecuteMigration(err, migration.version))?;
+        query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (?1, ?2, TRUE, ?3, -1)",
+        )
+        .bind(migration.version)
+        .bind(&*migration.description)
+        .bind(&*migration.checksum)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId, ChannelKind},
+    config::Limits,
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub const GROUP_DM_MAX_RECIPIENTS: usize = 10;
+pub const OPEN_DM_PATH: &str = "/open-dm";
+pub const CREATE_GROUP_DM_PATH: &str = "/create-group-dm";
+pub const ADD_RECIPIENT_PATH: &str = "/add-recipient";
+pub const REMOVE_RECIPIENT_PATH: &str = "/remove-recipient";
+
+/// Users can only DM, or add to a group DM, users they share a server with.
+async fn check_reachable(db: &Db, user_id: UserId, other_id: UserId) -> Result<(), ServerErr> {
+    if !db.user_exists(other_id).await? {
+        return Err(ServerErr::NoUserId(other_id));
+    }
+    if !db.shares_server(user_id, other_id).await? {
+        return Err(ServerErr::Forbidden(format!(
+            "No server in common with user ID {other_id}"
+        )));
+    }
+    Ok(())
+}
+
+/// Gets a channel that recipients can be added to or removed from.
+async fn get_group_dm(db: &Db, channel_id: ChannelId) -> Result<Channel, ServerErr> {
+    let channel = Channel::get(db, channel_id).await?;
+    if channel.kind != ChannelKind::GroupDm {
+        return Err(ServerErr::BadRequest(format!(
+            "Channel ID {channel_id} is not a group DM"
+        )));
+    }
+    Ok(channel)
+}
+
+fn send_channel(send: &Sender, channel: &Channel) {
+    if let Err(err) = send.send(Update::Channel(channel.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct OpenDmParams {
+    #[param(required = true)]
+    user_id: UserId,
+}
+
+#[utoipa::path(
+    post,
+    path = OPEN_DM_PATH,
+    params(OpenDmParams),
+    responses(
+        (status = 200, description = "Get the DM with a user, creating it if there isn't one", body = Channel),
+        (status = 400, description = "No such user, or yourself", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "No server in common with the user", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn open_dm(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<OpenDmParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    if query.user_id == user.id {
+        return Err(ServerErr::BadRequest("Can't DM yourself".to_string()));
+    }
+    if let Some(channel) = db.get_dm(user.id, query.user_id).await? {
+        return Ok(Json(channel));
+    }
+    check_reachable(&db, user.id, query.user_id).await?;
+    let mut channel = Channel {
+        server_id: None,
+        id: 0,
+        name: String::new(),
+        kind: ChannelKind::Dm,
+        owner_id: None,
+    };
+    channel.id = match db
+        .insert_dm(&channel, &[user.id, query.user_id], Utc::now())
+        .await
+    {
+        Ok(id) => id,
+        // Opened from the other side at the same time.
+        Err(ServerErr::SqlxErr(err))
+            if err
+                .as_database_error()
+                .is_some_and(|err| err.is_unique_violation()) =>
+        {
+            let channel = db.get_dm(user.id, query.user_id).await?;
+            return Ok(Json(channel.ok_or(ServerErr::SqlxErr(err))?));
+        }
+        Err(err) => return Err(err),
+    };
+    send_channel(&send, &channel);
+    Ok(Json(channel))
+}
+
+#[derive(Serialize, Deserialize, TS, ToSchema, Clone)]
+pub struct CreateGroupDmParams {
+    /// Defaults to no name, for clients to list the recipients instead.
+    name: Option<String>,
+    /// Everyone to add besides yourself.
+    user_ids: Vec<UserId>,
+}
+
+#[utoipa::path(
+    post,
+    path = CREATE_GROUP_DM_PATH,
+    request_body = CreateGroupDmParams,
+    responses(
+        (status = 200, description = "Create a group DM owned by you", body = Channel),
+        (status = 400, description = "No recipients, too many, or a name that's too long", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "No server in common with a recipient", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn create_group_dm(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Json(params): Json<CreateGroupDmParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let name = params.name.unwrap_or_default();
+    if name.len() > limits.channel_name_max_len {
+        return Err(ServerErr::ChannelNameTooLong(
+            name.len(),
+            limits.channel_name_max_len,
+        ));
+    }
+    let mut recipients = vec![user.id];
+    for user_id in params.user_ids {
+        if !recipients.contains(&user_id) {
+            recipients.push(user_id);
+        }
+    }
+    if recipients.len() < 2 || recipients.len() > limits.group_dm_max_recipients {
+        return Err(ServerErr::BadRequest(format!(
+            "Group DMs have 2 to {} recipients, got {}",
+            limits.group_dm_max_recipients,
+            recipients.len()
+        )));
+    }
+    for user_id in &recipients[1..] {
+        check_reachable(&db, user.id, *user_id).await?;
+    }
+    let mut channel = Channel {
+        server_id: None,
+        id: 0,
+        name,
+        kind: ChannelKind::GroupDm,
+        owner_id: Some(user.id),
+    };
+    channel.id = db.insert_dm(&channel, &recipients, Utc::now()).await?;
+    send_channel(&send, &channel);
+    Ok(Json(channel))
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct RecipientParams {
+    #[param(required = true)]
+    channel_id: ChannelId,
+    #[param(required = true)]
+    user_id: UserId,
+}
+
+#[utoipa::path(
+    post,
+    path = ADD_RECIPIENT_PATH,
+    params(RecipientParams),
+    responses(
+        (status = 200, description = "Add a user to a group DM you're in", body = ()),
+        (status = 400, description = "Not a group DM, already a recipient, or the group DM is full", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a recipient, or no server in common with the user", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn add_recipient(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<RecipientParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let channel = get_group_dm(&db, query.channel_id).await?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::VIEW_CHANNEL).await?;
+    check_reachable(&db, user.id, query.user_id).await?;
+    if db.get_recipients(channel.id).await?.len() >= limits.group_dm_max_recipients {
+        return Err(ServerErr::BadRequest(format!(
+            "Group DMs have at most {} recipients",
+            limits.group_dm_max_recipients
+        )));
+    }
+    if !db
+        .insert_recipient(channel.id, query.user_id, Utc::now())
+        .await?
+    {
+        return Err(ServerErr::BadRequest(format!(
+            "User ID {} is already a recipient",
+            query.user_id
+        )));
+    }
+    if let Err(err) = send.send(Update::RecipientAdd {
+        channel_id: channel.id,
+        user_id: query.user_id,
+    }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = REMOVE_RECIPIENT_PATH,
+    params(RecipientParams),
+    responses(
+        (status = 200, description = "Leave a group DM, or remove someone from one you own", body = ()),
+        (status = 400, description = "Not a group DM, or not a recipient", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a recipient, or removing someone else without owning the group DM", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn remove_recipient(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<RecipientParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let mut channel = get_group_dm(&db, query.channel_id).await?;
+    let access =
+        MemberAccess::require_channel(&db, &channel, user.id, Permissions::VIEW_CHANNEL).await?;
+    if query.user_id != user.id && !access.owner {
+        return Err(ServerErr::Forbidden(
+            "Only the owner can remove others from a group DM".to_string(),
+        ));
+    }
+    if !db.delete_recipient(channel.id, query.user_id).await? {
+        return Err(ServerErr::BadRequest(format!(
+            "User ID {} is not a recipient",
+            query.user_id
+        )));
+    }
+    if let Err(err) = send.send(Update::RecipientRemove {
+        channel_id: channel.id,
+        user_id: query.user_id,
+    }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    // When the owner leaves, whoever was added first takes over. The last
+    // one out deletes the group DM.
+    if channel.owner_id == Some(query.user_id) {
+        match db.get_recipients(channel.id).await?.first() {
+            Some(&owner_id) => {
+                db.set_channel_owner(channel.id, owner_id).await?;
+                channel.owner_id = Some(owner_id);
+                send_channel(&send, &channel);
+            }
+            None => {
+                db.delete_channel(channel.id).await?;
+            }
+        }
+    } else if db.get_recipients(channel.id).await?.is_empty() {
+        db.delete_channel(channel.id).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use crate::{
+    auth::AuthUser,
+    config::Limits,
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub type EmojiId = i32;
+
+pub const EMOJI_NAME_MAX_LEN: usize = 32;
+pub const EMOJIS_PATH: &str = "/emojis";
+pub const CREATE_EMOJI_PATH: &str = "/create-emoji";
+pub const DELETE_EMOJI_PATH: &str = "/delete-emoji";
+
+/// A custom emoji members of its server can react with.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Emoji {
+    pub id: EmojiId,
+    pub server_id: ServerId,
+    /// Letters, digits and underscores, unique within the server.
+    pub name: String,
+    /// Unset if the creator's account was deleted.
+    pub creator_id: Option<UserId>,
+    pub created_ts: DateTime<Utc>,
+}
+
+impl Emoji {
+    pub async fn insert(
+        db: &Db,
+        limits: &Limits,
+        server_id: ServerId,
+        creator_id: UserId,
+        name: String,
+    ) -> Result<Self, ServerErr> {
+        let len = name.len();
+        if len > limits.emoji_name_max_len {
+            return Err(ServerErr::EmojiNameTooLong(len, limits.emoji_name_max_len));
+        }
+        if len < 2 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(ServerErr::BadRequest(
+                "Emoji names need at least 2 letters, digits or underscores".to_string(),
+            ));
+        }
+        let mut emoji = Self {
+            id: 0,
+            server_id,
+            name,
+            creator_id: Some(creator_id),
+            created_ts: Utc::now(),
+        };
+        emoji.id = match db.insert_emoji(&emoji).await {
+            Ok(id) => id,
+            Err(ServerErr::SqlxErr(err))
+                if err
+                    .as_database_error()
+                    .is_some_and(|err| err.is_unique_violation()) =>
+            {
+                return Err(ServerErr::BadRequest(format!(
+                    "Server ID {server_id} already has an emoji named {}",
+                    emoji.name
+                )));
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(emoji)
+    }
+
+    pub async fn get(db: &Db, emoji_id: EmojiId) -> Result<Self, ServerErr> {
+        db.get_emoji(emoji_id)
+            .await?
+            .ok_or(ServerErr::NoEmojiId(emoji_id))
+    }
+}
+
+/// The whole list is sent so clients can replace theirs, like roles.
+async fn send_emojis(db: &Db, send: &Sender, server_id: ServerId) -> Result<(), ServerErr> {
+    let emojis = db.get_emojis(server_id).await?;
+    if let Err(err) = send.send(Update::Emojis { server_id, emojis }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct EmojisParams {
+    #[param(required = true)]
+    server_id: ServerId,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct CreateEmojiParams {
+    #[param(required = true)]
+    server_id: ServerId,
+    #[param(example = "party_parrot", required = true)]
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct EmojiParams {
+    #[param(required = true)]
+    emoji_id: EmojiId,
+}
+
+#[utoipa::path(
+    get,
+    path = EMOJIS_PATH,
+    params(EmojisParams),
+    responses(
+        (status = 200, description = "List a server's custom emoji", body = Vec<Emoji>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a member", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_emojis(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<EmojisParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    MemberAccess::resolve(&db, query.server_id, user.id).await?;
+    let emojis = db.get_emojis(query.server_id).await?;
+    Ok(Json(emojis))
+}
+
+#[utoipa::path(
+    post,
+    path = CREATE_EMOJI_PATH,
+    params(CreateEmojiParams),
+    responses(
+        (status = 200, description = "Add a custom emoji to a server", body = Emoji),
+        (status = 400, description = "Invalid or taken name", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_SERVER", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn create_emoji(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<CreateEmojiParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    MemberAccess::require(&db, query.server_id, user.id, Permissions::MANAGE_SERVER).await?;
+    let emoji = Emoji::insert(&db, &limits, query.server_id, user.id, query.name).await?;
+    send_emojis(&db, &send, emoji.server_id).await?;
+    Ok(Json(emoji))
+}
+
+#[utoipa::path(
+    post,
+    path = DELETE_EMOJI_PATH,
+    params(EmojiParams),
+    responses(
+        (status = 200, description = "Remove a custom emoji and its reactions", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_SERVER", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn delete_emoji(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<EmojiParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let emoji = Emoji::get(&db, query.emoji_id).await?;
+    MemberAccess::require(&db, emoji.server_id, user.id, Permissions::MANAGE_SERVER).await?;
+    if db.delete_emoji(emoji.id).await? {
+        send_emojis(&db, &send, emoji.server_id).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use crate::error::ServerErr;
+use axum::extract::ws::Message as WsMessage;
+use flate2::write::ZlibEncoder;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::Write;
+use ts_rs::TS;
+
+/// How events are written on the WebSockets, chosen per connection with
+/// `?encoding=`. JSON goes in text frames and the others in binary frames.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    #[serde(rename = "cbor")]
+    Cbor,
+}
+
+/// Compression of everything the server sends on a connection, chosen with
+/// `?compress=`. The connection shares one compression stream, flushed after
+/// each event, so every event is one binary frame and the client keeps one
+/// decompressor for the whole connection.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub enum Compression {
+    /// Each frame ends with a zlib sync flush, `00 00 ff ff`.
+    #[serde(rename = "zlib-stream")]
+    Zlib,
+    #[serde(rename = "zstd-stream")]
+    Zstd,
+}
+
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Default)]
+pub struct EncodingParams {
+    #[serde(default)]
+    pub encoding: Encoding,
+    pub compress: Option<Compression>,
+}
+
+impl Encoding {
+    fn to_vec<T: Serialize>(self, value: &T) -> Result<Vec<u8>, ServerErr> {
+        match self {
+            Self::Json => {
+                serde_json::to_vec(value).map_err(|err| ServerErr::EncodeErr(err.to_string()))
+            }
+            // Named fields, so messages read the same as the JSON ones.
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|err| ServerErr::EncodeErr(err.to_string()))
+            }
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)
+                    .map_err(|err| ServerErr::EncodeErr(err.to_string()))?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Reads a frame sent by the client. Clients never compress, and can
+    /// always send JSON in text frames. Returns `None` for frames without
+    /// data, like pings.
+    pub fn decode<T: DeserializeOwned>(self, message: &WsMessage) -> Option<Result<T, ServerErr>> {
+        let decoded = match message {
+            WsMessage::Text(text) => {
+                serde_json::from_str(text).map_err(|err| ServerErr::BadRequest(err.to_string()))
+            }
+            WsMessage::Binary(bytes) => match self {
+                Self::Json => serde_json::from_slice(bytes)
+                    .map_err(|err| ServerErr::BadRequest(err.to_string())),
+                Self::MessagePack => rmp_serde::from_slice(bytes)
+                    .map_err(|err| ServerErr::BadRequest(err.to_string())),
+                Self::Cbor => ciborium::from_reader(bytes.as_ref())
+                    .map_err(|err| ServerErr::BadRequest(err.to_string())),
+            },
+            _ => return None,
+        };
+        Some(decoded)
+    }
+}
+
+/// Writes one connection's events in its encoding and compression.
+pub struct Encoder {
+    encoding: Encoding,
+    compressor: Option<Compressor>,
+}
+
+enum Compressor {
+    Zlib(ZlibEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    pub fn new(params: EncodingParams) -> Result<Self, ServerErr> {
+        let compressor = match params.compress {
+            None => None,
+            Some(Compression::Zlib) => Some(Compressor::Zlib(ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            Some(Compression::Zstd) => Some(Compressor::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?)),
+        };
+        Ok(Self {
+            encoding: params.encoding,
+            compressor,
+        })
+    }
+
+    pub fn encode<T: Serialize>(&mut self, value: &T) -> Result<WsMessage, ServerErr> {
+        let bytes = self.encoding.to_vec(value)?;
+        let message = match (&mut self.compressor, self.encoding) {
+            (Some(compressor), _) => WsMessage::Binary(compressor.compress(&bytes)?.into()),
+            (None, Encoding::Json) => {
+                // serde_json only writes UTF-8.
+                let text = String::from_utf8(bytes)
+                    .map_err(|err| ServerErr::EncodeErr(err.to_string()))?;
+                WsMessage::Text(text.into())
+            }
+            (None, _) => WsMessage::Binary(bytes.into()),
+        };
+        Ok(message)
+    }
+}
+
+impl Compressor {
+    /// Compresses one event and flushes it, keeping the stream's history for
+    /// the next.
+    fn compress(&mut self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Zlib(encoder) => {
+                encoder.write_all(bytes)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::Zstd(encoder) => {
+                encoder.write_all(bytes)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+}
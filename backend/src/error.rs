@@ -1,12 +1,16 @@
-use crate::{channel::*, message::*, server::*, user::*};
+use crate::{
+    attachment::AttachmentId, channel::ChannelId, emoji::EmojiId, message::MessageId, role::RoleId,
+    server::ServerId, snapshot::Update, thread::ThreadId, update_log::Seq, user::UserId,
+};
 use axum::{
-    response::{sse::Event, IntoResponse, Response},
+    extract::multipart::MultipartError,
+    response::{IntoResponse, Response},
     Error as AxumError, Json,
 };
 use hyper::StatusCode;
 use sqlx::{migrate::MigrateError, Error as SqlxError};
 use thiserror::Error;
-use tokio::sync::broadcast::error::SendError;
+use tokio::sync::mpsc::error::SendError;
 
 #[derive(Error, Debug)]
 pub enum ServerErr {
@@ -16,14 +20,26 @@ pub enum ServerErr {
     SqlxErr(#[from] SqlxError),
     #[error("Error migrating sql")]
     SqlxMigrateErr(#[from] MigrateError),
-    #[error("Username is too long: {0}/{USERNAME_MAX_LEN} bytes")]
-    UsernameTooLong(usize),
-    #[error("Message text is too long: {0}/{MESSAGE_MAX_LEN} bytes")]
-    MessageTooLong(usize),
-    #[error("Channel name is too long: {0}/{CHANNEL_NAME_MAX_LEN} bytes")]
-    ChannelNameTooLong(usize),
-    #[error("Server name is too long: {0}/{SERVER_NAME_MAX_LEN} bytes")]
-    ServerNameTooLong(usize),
+    #[error("Error reading upload: {0}")]
+    MultipartErr(#[from] MultipartError),
+    #[error("Error accessing blob storage: {0}")]
+    BlobErr(String),
+    #[error("Error accessing files: {0}")]
+    IoErr(#[from] std::io::Error),
+    #[error("Username is too long: {0}/{1} bytes")]
+    UsernameTooLong(usize, usize),
+    #[error("Message text is too long: {0}/{1} bytes")]
+    MessageTooLong(usize, usize),
+    #[error("Channel name is too long: {0}/{1} bytes")]
+    ChannelNameTooLong(usize, usize),
+    #[error("Server name is too long: {0}/{1} bytes")]
+    ServerNameTooLong(usize, usize),
+    #[error("Role name is too long: {0}/{1} bytes")]
+    RoleNameTooLong(usize, usize),
+    #[error("Thread name is too long: {0}/{1} bytes")]
+    ThreadNameTooLong(usize, usize),
+    #[error("Emoji name is too long: {0}/{1} bytes")]
+    EmojiNameTooLong(usize, usize),
     #[error("Server ID {0} does not exist")]
     NoServerId(ServerId),
     #[error("Channel ID {0} does not exist")]
@@ -32,10 +48,62 @@ pub enum ServerErr {
     NoUserId(UserId),
     #[error("Message ID {0} does not exist")]
     NoMessageId(MessageId),
+    #[error("Role ID {0} does not exist")]
+    NoRoleId(RoleId),
+    #[error("Thread ID {0} does not exist")]
+    NoThreadId(ThreadId),
+    #[error("Emoji ID {0} does not exist")]
+    NoEmojiId(EmojiId),
+    #[error("Attachment ID {0} does not exist")]
+    NoAttachmentId(AttachmentId),
+    #[error("File {0} is too large: the limit is {1} bytes")]
+    AttachmentTooLarge(String, usize),
+    #[error("Attachment ID {0} has no thumbnail")]
+    NoThumbnail(AttachmentId),
+    #[error("Error processing image: {0}")]
+    ImageErr(String),
+    #[error("Files of type {0} are not accepted")]
+    ContentTypeNotAllowed(String),
+    #[error("Updates since {0} are no longer kept, fetch a new snapshot")]
+    SyncGone(Seq),
+    #[error("Error encoding event: {0}")]
+    EncodeErr(String),
     #[error("Error sending SSE event: {0}")]
-    SendErr(#[from] SendError<Event>),
+    SendErr(#[from] Box<SendError<Update>>),
     #[error("Bad request: {0}")]
     BadRequest(String),
+    #[error("Invalid configuration: {0}")]
+    ConfigErr(String),
+    #[error("Not logged in")]
+    Unauthorized,
+    #[error("Wrong username or password")]
+    InvalidCredentials,
+    #[error("Username {0} is already taken")]
+    UsernameTaken(String),
+    #[error("Password is too short: {0}/{1} bytes")]
+    PasswordTooShort(usize, usize),
+    #[error("Password is too long: {0}/{1} bytes")]
+    PasswordTooLong(usize, usize),
+    #[error("Error hashing password: {0}")]
+    PasswordHashErr(String),
+    #[error("Not a member of server ID {0}")]
+    NotMember(ServerId),
+    #[error("Not a recipient of DM channel ID {0}")]
+    NotRecipient(ChannelId),
+    #[error("Already a member of server ID {0}")]
+    AlreadyMember(ServerId),
+    #[error("Invite {0} does not exist")]
+    NoInvite(String),
+    #[error("Invite {0} has expired")]
+    InviteExpired(String),
+    #[error("Invite {0} has reached its maximum uses")]
+    InviteExhausted(String),
+    #[error("Banned from server ID {0}")]
+    Banned(ServerId),
+    #[error("Thread ID {0} is locked")]
+    ThreadLocked(ThreadId),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
 }
 
 impl IntoResponse for ServerErr {
@@ -45,7 +113,34 @@ impl IntoResponse for ServerErr {
             Self::NoUserId(_) => StatusCode::BAD_REQUEST,
             Self::NoChannelId(_) => StatusCode::BAD_REQUEST,
             Self::NoMessageId(_) => StatusCode::BAD_REQUEST,
+            Self::NoRoleId(_) => StatusCode::BAD_REQUEST,
+            Self::NoThreadId(_) => StatusCode::BAD_REQUEST,
+            Self::NoEmojiId(_) => StatusCode::BAD_REQUEST,
+            Self::NoAttachmentId(_) => StatusCode::BAD_REQUEST,
+            Self::NoThumbnail(_) => StatusCode::BAD_REQUEST,
+            Self::ContentTypeNotAllowed(_) => StatusCode::BAD_REQUEST,
+            Self::AttachmentTooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
+            // Includes 413 when the form is over the body limit.
+            Self::MultipartErr(ref err) => err.status(),
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::PasswordTooShort(..) => StatusCode::BAD_REQUEST,
+            Self::PasswordTooLong(..) => StatusCode::BAD_REQUEST,
+            Self::RoleNameTooLong(..) => StatusCode::BAD_REQUEST,
+            Self::ThreadNameTooLong(..) => StatusCode::BAD_REQUEST,
+            Self::EmojiNameTooLong(..) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::NotMember(_) => StatusCode::FORBIDDEN,
+            Self::NotRecipient(_) => StatusCode::FORBIDDEN,
+            Self::Banned(_) => StatusCode::FORBIDDEN,
+            Self::ThreadLocked(_) => StatusCode::FORBIDDEN,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NoInvite(_) => StatusCode::NOT_FOUND,
+            Self::InviteExpired(_) => StatusCode::GONE,
+            Self::InviteExhausted(_) => StatusCode::GONE,
+            Self::SyncGone(_) => StatusCode::GONE,
+            Self::UsernameTaken(_) => StatusCode::CONFLICT,
+            Self::AlreadyMember(_) => StatusCode::CONFLICT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
         (status_code, Json(self.to_string())).into_response()
@@ -0,0 +1,303 @@
+use crate::{
+    auth::AuthUser,
+    channel::ChannelId,
+    config::GatewayConfig,
+    encoding::{Encoder, Encoding, EncodingParams},
+    error::ServerErr,
+    presence::{PresenceState, PresenceStatus},
+    scope::UpdateScope,
+    snapshot::Update,
+    storage::Db,
+    typing::send_typing,
+    update_log::{Delivery, Seq, Subscription},
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message as WsMessage, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+};
+use futures_util::{
+    stream::{SplitSink, StreamExt},
+    SinkExt,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+pub const GATEWAY_PATH: &str = "/gateway";
+pub const HEARTBEAT_INTERVAL_MS: u64 = 30_000;
+/// Events waiting to be written to one connection. When it's full the
+/// connection falls behind and catches up like an SSE client would.
+const GATEWAY_BUFFER: usize = 64;
+
+/// Close codes, following Discord's.
+pub const CLOSE_DECODE_ERROR: u16 = 4002;
+pub const CLOSE_NOT_IDENTIFIED: u16 = 4003;
+pub const CLOSE_ALREADY_IDENTIFIED: u16 = 4005;
+pub const CLOSE_SESSION_TIMED_OUT: u16 = 4009;
+
+/// Sent by the server as `{"op": ..., "d": ...}`, in the connection's
+/// encoding.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+#[serde(tag = "op", content = "d")]
+pub enum GatewayEvent {
+    /// Sent on connecting. The client has to send `Heartbeat` at least this
+    /// often, or the connection is closed.
+    Hello {
+        #[ts(type = "number")]
+        heartbeat_interval_ms: u64,
+    },
+    /// Reply to `Identify`. Updates follow from `seq`.
+    Ready {
+        #[ts(type = "number")]
+        seq: Seq,
+    },
+    Dispatch {
+        #[ts(type = "number")]
+        seq: Seq,
+        update: Box<Update>,
+    },
+    HeartbeatAck,
+    /// The server is going away. Connect again and `Resume`.
+    Reconnect,
+    /// The updates the client needs are no longer kept, either to resume or
+    /// because it fell too far behind. Fetch a new snapshot and `Identify`
+    /// again.
+    InvalidSession,
+}
+
+/// Sent by the client, in the same form as `GatewayEvent`.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+#[serde(tag = "op", content = "d")]
+pub enum GatewayCommand {
+    /// Starts receiving updates from now.
+    Identify,
+    /// Starts receiving updates after `seq`, a snapshot's or the last
+    /// dispatched, with what was missed first.
+    Resume {
+        #[ts(type = "number")]
+        seq: Seq,
+    },
+    Heartbeat,
+    Typing {
+        channel_id: ChannelId,
+        typing: bool,
+    },
+    Presence {
+        status: PresenceStatus,
+    },
+}
+
+pub async fn gateway(
+    ws: WebSocketUpgrade,
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    State(presence): State<PresenceState>,
+    State(config): State<GatewayConfig>,
+    AuthUser(user): AuthUser,
+    Query(params): Query<EncodingParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let encoder = Encoder::new(params)?;
+    Ok(ws.on_upgrade(move |socket| {
+        let connection = Connection {
+            db,
+            send,
+            presence,
+            user_id: user.id,
+            online: false,
+            encoding: params.encoding,
+            encoder,
+        };
+        connection.run(socket, config)
+    }))
+}
+
+struct Connection {
+    db: Db,
+    send: Sender,
+    presence: PresenceState,
+    user_id: UserId,
+    /// Counted towards the user's presence, from the first identify.
+    online: bool,
+    /// Commands in binary frames are read in this encoding.
+    encoding: Encoding,
+    encoder: Encoder,
+}
+
+impl Connection {
+    async fn run(mut self, socket: WebSocket, config: GatewayConfig) {
+        let interval = Duration::from_millis(config.heartbeat_interval_ms);
+        // A heartbeat can be up to half an interval late.
+        let timeout = interval + interval / 2;
+        let (mut sink, mut stream) = socket.split();
+        let (events, mut outgoing) = mpsc::channel(GATEWAY_BUFFER);
+        let mut dispatch: Option<JoinHandle<()>> = None;
+        let mut deadline = Instant::now() + timeout;
+        let hello = GatewayEvent::Hello {
+            heartbeat_interval_ms: config.heartbeat_interval_ms,
+        };
+        if self.write(&mut sink, &hello).await.is_err() {
+            return;
+        }
+        let close = loop {
+            tokio::select! {
+                message = stream.next() => {
+                    let message = match message {
+                        Some(Ok(WsMessage::Close(_)) | Err(_)) | None => break None,
+                        Some(Ok(message)) => message,
+                    };
+                    let command = match self.encoding.decode::<GatewayCommand>(&message) {
+                        Some(Ok(command)) => command,
+                        Some(Err(_)) => break Some((CLOSE_DECODE_ERROR, "Invalid command")),
+                        // Pings are answered for us.
+                        None => continue,
+                    };
+                    let identified = dispatch.as_ref().is_some_and(|task| !task.is_finished());
+                    match command {
+                        GatewayCommand::Heartbeat => {
+                            deadline = Instant::now() + timeout;
+                            if self.write(&mut sink, &GatewayEvent::HeartbeatAck).await.is_err() {
+                                break None;
+                            }
+                        }
+                        GatewayCommand::Identify | GatewayCommand::Resume { .. } if identified => {
+                            break Some((CLOSE_ALREADY_IDENTIFIED, "Already identified"));
+                        }
+                        GatewayCommand::Identify => {
+                            let subscription = Subscription::new(self.send.clone()).await;
+                            dispatch = Some(self.start(subscription, events.clone()).await);
+                        }
+                        GatewayCommand::Resume { seq } => {
+                            let subscription = Subscription::resume(self.send.clone(), seq).await;
+                            dispatch = Some(self.start(subscription, events.clone()).await);
+                        }
+                        _ if !identified => {
+                            break Some((CLOSE_NOT_IDENTIFIED, "Not identified"));
+                        }
+                        GatewayCommand::Typing { channel_id, typing } => {
+                            if let Err(err) =
+                                send_typing(&self.db, &self.send, self.user_id, channel_id, typing).await
+                            {
+                                tracing::warn!("Rejecting typing from {}: {err}", self.user_id);
+                            }
+                        }
+                        GatewayCommand::Presence { status } => {
+                            self.presence.set(&self.send, self.user_id, status).await;
+                        }
+                    }
+                }
+                Some(event) = outgoing.recv() => {
+                    let reconnect = matches!(event, GatewayEvent::Reconnect);
+                    if self.write(&mut sink, &event).await.is_err() || reconnect {
+                        break None;
+                    }
+                }
+                _ = sleep_until(deadline) => {
+                    break Some((CLOSE_SESSION_TIMED_OUT, "Missed heartbeats"));
+                }
+            }
+        };
+        if let Some(task) = dispatch {
+            task.abort();
+        }
+        if self.online {
+            self.presence.disconnect(&self.send, self.user_id).await;
+        }
+        if let Some((code, reason)) = close {
+            let frame = CloseFrame {
+                code,
+                reason: reason.into(),
+            };
+            let _ = sink.send(WsMessage::Close(Some(frame))).await;
+        }
+    }
+
+    /// Starts sending the subscription's updates, and counts the user as
+    /// online if this is the connection's first identify.
+    async fn start(
+        &mut self,
+        subscription: Subscription,
+        events: mpsc::Sender<GatewayEvent>,
+    ) -> JoinHandle<()> {
+        if !self.online {
+            self.online = true;
+            self.presence.connect(&self.send, self.user_id).await;
+        }
+        tokio::spawn(dispatch(
+            self.db.clone(),
+            self.user_id,
+            subscription,
+            events,
+        ))
+    }
+
+    async fn write(
+        &mut self,
+        sink: &mut SplitSink<WebSocket, WsMessage>,
+        event: &GatewayEvent,
+    ) -> Result<(), axum::Error> {
+        match self.encoder.encode(event) {
+            Ok(message) => sink.send(message).await,
+            Err(err) => {
+                tracing::error!("Error encoding gateway event: {err:?}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Sends the updates in the user's scope until the connection closes, or
+/// the session can't go on.
+async fn dispatch(
+    db: Db,
+    user_id: UserId,
+    mut subscription: Subscription,
+    events: mpsc::Sender<GatewayEvent>,
+) {
+    // Subscribed first, so changes to the scope while it's read come in as
+    // updates.
+    let mut scope = match UpdateScope::new(&db, user_id).await {
+        Ok(scope) => scope,
+        Err(err) => {
+            tracing::error!("Error reading update scope: {err:?}");
+            let _ = events.send(GatewayEvent::InvalidSession).await;
+            return;
+        }
+    };
+    while let Some(delivery) = subscription.next().await {
+        let event = match delivery {
+            Delivery::Ready(seq) => GatewayEvent::Ready { seq },
+            Delivery::Update(logged) => match scope.admit(&db, &logged.update).await {
+                Ok(true) => GatewayEvent::Dispatch {
+                    seq: logged.seq,
+                    update: Box::new(logged.update.clone()),
+                },
+                Ok(false) => continue,
+                Err(err) => {
+                    tracing::error!("Error checking update visibility: {err:?}");
+                    continue;
+                }
+            },
+            Delivery::Resync(_) => {
+                let _ = events.send(GatewayEvent::InvalidSession).await;
+                return;
+            }
+        };
+        if events.send(event).await.is_err() {
+            return;
+        }
+    }
+    let _ = events.send(GatewayEvent::Reconnect).await;
+}
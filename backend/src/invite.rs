@@ -0,0 +1,343 @@
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    member::ServerMember,
+    permission::{MemberAccess, Permissions},
+    server::{Server, ServerId},
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub const CREATE_INVITE_PATH: &str = "/create-invite";
+pub const INVITE_PATH: &str = "/invite";
+pub const REDEEM_INVITE_PATH: &str = "/redeem-invite";
+pub const INVITES_PATH: &str = "/invites";
+pub const REVOKE_INVITE_PATH: &str = "/revoke-invite";
+pub const INVITE_CODE_LEN: usize = 8;
+
+const INVITE_CODE_CHARS: &[u8] = b"abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Invite {
+    pub code: String,
+    pub server_id: ServerId,
+    /// The channel the invite points at, if any.
+    pub channel_id: Option<ChannelId>,
+    /// Unset for invites made by the server itself.
+    pub creator_id: Option<UserId>,
+    pub created_ts: DateTime<Utc>,
+    /// Never expires if unset.
+    pub expires_ts: Option<DateTime<Utc>>,
+    /// Unlimited if unset.
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+}
+
+/// What someone holding an invite code can see before redeeming it.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct InvitePreview {
+    pub code: String,
+    pub server: Server,
+    pub channel: Option<Channel>,
+    pub member_count: i64,
+    pub expires_ts: Option<DateTime<Utc>>,
+}
+
+impl Invite {
+    pub async fn insert(
+        db: &Db,
+        creator_id: UserId,
+        params: CreateInviteParams,
+    ) -> Result<Self, ServerErr> {
+        let server_id = params.server_id;
+        if let Some(channel_id) = params.channel_id {
+            let channel = db
+                .get_channel(channel_id)
+                .await?
+                .ok_or(ServerErr::NoChannelId(channel_id))?;
+            if channel.server_id != Some(server_id) {
+                return Err(ServerErr::BadRequest(format!(
+                    "Channel ID {channel_id} is not in server ID {server_id}"
+                )));
+            }
+            let permissions = Permissions::VIEW_CHANNEL | Permissions::CREATE_INVITE;
+            MemberAccess::require_channel(db, &channel, creator_id, permissions).await?;
+        } else {
+            MemberAccess::require(db, server_id, creator_id, Permissions::CREATE_INVITE).await?;
+        }
+        if params.max_uses.is_some_and(|uses| uses < 1) {
+            return Err(ServerErr::BadRequest(
+                "max_uses must be at least 1".to_string(),
+            ));
+        }
+        if params.max_age_secs.is_some_and(|secs| secs < 1) {
+            return Err(ServerErr::BadRequest(
+                "max_age_secs must be at least 1".to_string(),
+            ));
+        }
+        let created_ts = Utc::now();
+        let invite = Self {
+            code: generate_code(),
+            server_id,
+            channel_id: params.channel_id,
+            creator_id: Some(creator_id),
+            created_ts,
+            expires_ts: params
+                .max_age_secs
+                .map(|secs| created_ts + Duration::seconds(secs)),
+            max_uses: params.max_uses,
+            uses: 0,
+        };
+        db.insert_invite(&invite).await?;
+        Ok(invite)
+    }
+
+    /// An unlimited invite with no creator, used for the seeded server.
+    pub fn permanent(server_id: ServerId, code: String) -> Self {
+        Self {
+            code,
+            server_id,
+            channel_id: None,
+            creator_id: None,
+            created_ts: Utc::now(),
+            expires_ts: None,
+            max_uses: None,
+            uses: 0,
+        }
+    }
+
+    pub async fn get(db: &Db, code: &str) -> Result<Self, ServerErr> {
+        db.get_invite(code)
+            .await?
+            .ok_or_else(|| ServerErr::NoInvite(code.to_string()))
+    }
+
+    /// Rejects invites that are expired or used up.
+    pub fn check(&self, now: DateTime<Utc>) -> Result<(), ServerErr> {
+        if self.expires_ts.is_some_and(|expires_ts| expires_ts <= now) {
+            Err(ServerErr::InviteExpired(self.code.clone()))
+        } else if self.max_uses.is_some_and(|max_uses| self.uses >= max_uses) {
+            Err(ServerErr::InviteExhausted(self.code.clone()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Joins the user to the invite's server and counts the use.
+    pub async fn redeem(&self, db: &Db, user_id: UserId) -> Result<ServerMember, ServerErr> {
+        let now = Utc::now();
+        self.check(now)?;
+        if db.is_member(self.server_id, user_id).await? {
+            return Err(ServerErr::AlreadyMember(self.server_id));
+        }
+        if db.is_banned(self.server_id, user_id).await? {
+            return Err(ServerErr::Banned(self.server_id));
+        }
+        // The checks above can race with other redemptions, so the use is
+        // only counted if the invite is still valid when it's written, and
+        // only kept if the user joins.
+        if !db
+            .redeem_invite(&self.code, self.server_id, user_id, now)
+            .await?
+        {
+            return Err(if db.is_member(self.server_id, user_id).await? {
+                ServerErr::AlreadyMember(self.server_id)
+            } else {
+                ServerErr::InviteExhausted(self.code.clone())
+            });
+        }
+        Ok(ServerMember {
+            server_id: self.server_id,
+            user_id,
+            joined_ts: now,
+        })
+    }
+}
+
+/// Random code made of characters that are hard to mix up when typed.
+fn generate_code() -> String {
+    let mut code = String::with_capacity(INVITE_CODE_LEN);
+    while code.len() < INVITE_CODE_LEN {
+        let mut bytes = [0u8; INVITE_CODE_LEN];
+        OsRng.fill_bytes(&mut bytes);
+        // Skip bytes past the last full multiple of the alphabet so every
+        // character is equally likely.
+        let limit = 256 - 256 % INVITE_CODE_CHARS.len();
+        code.extend(
+            bytes
+                .into_iter()
+                .filter(|byte| (*byte as usize) < limit)
+                .map(|byte| INVITE_CODE_CHARS[byte as usize % INVITE_CODE_CHARS.len()] as char),
+        );
+        code.truncate(INVITE_CODE_LEN);
+    }
+    code
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct CreateInviteParams {
+    #[param(required = true)]
+    server_id: ServerId,
+    channel_id: Option<ChannelId>,
+    /// Seconds until the invite expires. Never expires if unset.
+    max_age_secs: Option<i64>,
+    /// Number of times the invite can be redeemed. Unlimited if unset.
+    max_uses: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct InviteParams {
+    #[param(example = "aB3dE5gH", required = true)]
+    code: String,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct InvitesParams {
+    #[param(required = true)]
+    server_id: ServerId,
+}
+
+#[utoipa::path(
+    post,
+    path = CREATE_INVITE_PATH,
+    params(CreateInviteParams),
+    responses(
+        (status = 200, description = "Create an invite to a server", body = Invite),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing CREATE_INVITE", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn create_invite(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<CreateInviteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let invite = Invite::insert(&db, user.id, query).await?;
+    Ok(Json(invite))
+}
+
+#[utoipa::path(
+    get,
+    path = INVITE_PATH,
+    params(InviteParams),
+    responses(
+        (status = 200, description = "Preview the server an invite is for", body = InvitePreview),
+        (status = 404, description = "Unknown invite", body = String),
+        (status = 410, description = "Invite is expired or used up", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_invite(
+    State(db): State<Db>,
+    Query(query): Query<InviteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let invite = Invite::get(&db, &query.code).await?;
+    invite.check(Utc::now())?;
+    let server = db
+        .get_server(invite.server_id)
+        .await?
+        .ok_or(ServerErr::NoServerId(invite.server_id))?;
+    let channel = match invite.channel_id {
+        Some(channel_id) => db.get_channel(channel_id).await?,
+        None => None,
+    };
+    let member_count = db.member_count(invite.server_id).await?;
+    Ok(Json(InvitePreview {
+        code: invite.code,
+        server,
+        channel,
+        member_count,
+        expires_ts: invite.expires_ts,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = REDEEM_INVITE_PATH,
+    params(InviteParams),
+    responses(
+        (status = 200, description = "Join the server an invite is for", body = ServerMember),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Unknown invite", body = String),
+        (status = 403, description = "Banned from the server", body = String),
+        (status = 409, description = "Already a member", body = String),
+        (status = 410, description = "Invite is expired or used up", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn redeem_invite(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<InviteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let invite = Invite::get(&db, &query.code).await?;
+    let member = invite.redeem(&db, user.id).await?;
+    if let Err(err) = send.send(Update::MemberJoin(member.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    get,
+    path = INVITES_PATH,
+    params(InvitesParams),
+    responses(
+        (status = 200, description = "List a server's invites", body = Vec<Invite>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_SERVER", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_invites(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<InvitesParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    MemberAccess::require(&db, query.server_id, user.id, Permissions::MANAGE_SERVER).await?;
+    let invites = db.get_invites(query.server_id).await?;
+    Ok(Json(invites))
+}
+
+#[utoipa::path(
+    post,
+    path = REVOKE_INVITE_PATH,
+    params(InviteParams),
+    responses(
+        (status = 200, description = "Revoke an invite", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not the creator and missing MANAGE_SERVER", body = String),
+        (status = 404, description = "Unknown invite", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn revoke_invite(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<InviteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let invite = Invite::get(&db, &query.code).await?;
+    if invite.creator_id != Some(user.id) {
+        MemberAccess::require(&db, invite.server_id, user.id, Permissions::MANAGE_SERVER).await?;
+    }
+    db.delete_invite(&invite.code).await?;
+    Ok(())
+}
@@ -1,10 +1,8 @@
 use axum::{
-    extract::FromRef,
-    response::sse::Event,
-    routing::{get, post},
+    extract::{DefaultBodyLimit, FromRef},
+    routing::{get, patch, post},
     Router,
 };
-use sqlx::{migrate, migrate::MigrateDatabase, Sqlite, SqlitePool};
 use tokio::sync::broadcast;
 use tower_http::{
     compression::CompressionLayer,
@@ -14,46 +12,106 @@ use tower_http::{
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+use blob::Blobs;
+use config::{AttachmentConfig, AuthConfig, Config, GatewayConfig, Limits};
 use error::ServerErr;
+use media::ImageQueue;
+use presence::PresenceState;
+use storage::Db;
+use update_log::UpdateLog;
 
+use attachment::*;
+use auth::*;
 use channel::*;
+use dm::*;
+use emoji::*;
+use gateway::*;
+use invite::*;
+use member::*;
 use message::*;
+use overwrite::*;
+use reaction::*;
+use read_state::*;
+use role::*;
+use search::*;
 use server::*;
 use snapshot::*;
+use thread::*;
 use typing::*;
 use user::*;
 use voice_signal::*;
 
+pub mod attachment;
+pub mod auth;
+pub mod blob;
 pub mod channel;
+pub mod config;
+pub mod db;
+pub mod dm;
+pub mod emoji;
+pub mod encoding;
 pub mod error;
+pub mod gateway;
+pub mod invite;
+pub mod media;
+pub mod member;
+pub mod mention;
 pub mod message;
+pub mod overwrite;
+pub mod permission;
+pub mod presence;
+pub mod reaction;
+pub mod read_state;
+pub mod role;
+pub mod scope;
+pub mod search;
 pub mod server;
 pub mod snapshot;
+pub mod storage;
+pub mod thread;
 pub mod typing;
+pub mod update_log;
 pub mod user;
 pub mod voice_signal;
 
-const MAX_BROADCAST: usize = 1000;
-
-pub type Sender = broadcast::Sender<Event>;
+pub type Sender = update_log::UpdateLog;
 
 #[derive(Clone, FromRef)]
 struct AppState {
-    pool: SqlitePool,
+    db: Db,
+    blobs: Blobs,
+    limits: Limits,
+    attachments: AttachmentConfig,
+    images: ImageQueue,
+    auth: AuthConfig,
+    gateway: GatewayConfig,
     send_update: Sender,
     send_voice: VoiceSender,
     voice_state: VoiceState,
+    presence: PresenceState,
 }
 
 impl AppState {
-    fn new(pool: SqlitePool) -> Self {
-        let (send_update, _recv) = broadcast::channel(MAX_BROADCAST);
-        let (send_voice, _recv) = broadcast::channel(MAX_BROADCAST);
+    fn new(db: Db, blobs: Blobs, send_update: Sender, config: &Config) -> Self {
+        let (send_voice, _recv) = broadcast::channel(config.max_broadcast);
+        let images = ImageQueue::spawn(
+            db.clone(),
+            blobs.clone(),
+            send_update.clone(),
+            &config.attachments,
+        );
         Self {
-            pool,
+            db,
+            blobs,
+            limits: config.limits,
+            attachments: config.attachments.clone(),
+            images,
+            auth: config.auth.clone(),
+            gateway: config.gateway.clone(),
             send_update,
             send_voice,
             voice_state: VoiceState::default(),
+            presence: PresenceState::default(),
         }
     }
 }
@@ -61,46 +119,181 @@ impl AppState {
 #[derive(OpenApi)]
 #[openapi(paths(
     create_user,
+    login,
+    logout,
     create_server,
     create_channel,
+    open_dm,
+    create_group_dm,
+    add_recipient,
+    remove_recipient,
+    join_server,
+    leave_server,
+    get_members,
+    kick_member,
+    ban_member,
+    unban_member,
+    get_roles,
+    create_role,
+    update_role,
+    move_role,
+    delete_role,
+    get_member_roles,
+    add_member_role,
+    remove_member_role,
+    get_channel_overwrites,
+    set_channel_overwrite,
+    clear_channel_overwrite,
+    create_invite,
+    get_invite,
+    redeem_invite,
+    get_invites,
+    revoke_invite,
     create_message,
+    create_message_with_attachments,
+    get_attachment,
+    get_attachment_thumbnail,
+    edit_message,
+    get_message_edits,
+    delete_message,
+    bulk_delete_messages,
+    purge_messages,
+    get_channel_messages,
+    search_messages,
+    ack,
+    add_reaction,
+    remove_reaction,
+    get_emojis,
+    create_emoji,
+    delete_emoji,
+    create_thread,
+    get_threads,
+    archive_thread,
+    lock_thread,
+    get_thread_members,
+    join_thread,
+    leave_thread,
     typing,
     get_snapshot,
     get_updates,
+    get_sync,
 ))]
 struct ApiDoc;
 
+/// Creates a default server on first startup so fresh databases aren't empty.
+async fn seed(db: &Db, config: &Config) -> Result<(), ServerErr> {
+    if config.seed.enabled && !db.has_servers().await? {
+        let limits = &config.limits;
+        let server = Server::insert(db, limits, config.seed.server_name.clone(), None).await?;
+        let _channel =
+            Channel::insert(db, limits, server.id, config.seed.channel_name.clone()).await?;
+        if !config.seed.invite_code.is_empty() {
+            let invite = Invite::permanent(server.id, config.seed.invite_code.clone());
+            db.insert_invite(&invite).await?;
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), ServerErr> {
-    let dir = "frontend/out";
-    let static_service =
-        ServeDir::new(dir).not_found_service(ServeFile::new(format!("{dir}/404.html")));
-
-    Sqlite::create_database("sqlite::memory:").await?;
-    let pool = SqlitePool::connect("sqlite::memory:").await?;
-    migrate!("../migrations").run(&pool).await?;
+    let config = Config::load()?;
+    let dir = &config.static_dir;
+    let static_service = ServeDir::new(dir).not_found_service(ServeFile::new(dir.join("404.html")));
 
-    let server = Server::insert(&pool, "My First Server".to_string()).await?;
-    let _channel = Channel::insert(&pool, server.id, "Home".to_string()).await?;
+    let db = config.database.connect().await?;
+    seed(&db, &config).await?;
 
-    let state = AppState::new(pool);
+    let blobs = config.blobs.connect().await?;
+    let send_update = UpdateLog::start(&db, &config.updates, config.max_broadcast).await?;
+    let state = AppState::new(db, blobs, send_update, &config);
+    // Room for every file of a message at its largest, plus the form around
+    // them. Limits too large to add up are as good as none.
+    let upload_limit = config
+        .attachments
+        .max_size
+        .saturating_mul(config.attachments.max_per_message)
+        .saturating_add(64 * 1024);
+    spawn_auto_archive(
+        state.db.clone(),
+        state.send_update.clone(),
+        config.threads.clone(),
+    );
 
     let app = Router::new()
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
         .route(CREATE_USER_PATH, post(create_user))
+        .route(LOGIN_PATH, post(login))
+        .route(LOGOUT_PATH, post(logout))
         .route(CREATE_SERVER_PATH, post(create_server))
         .route(CREATE_CHANNEL_PATH, post(create_channel))
+        .route(OPEN_DM_PATH, post(open_dm))
+        .route(CREATE_GROUP_DM_PATH, post(create_group_dm))
+        .route(ADD_RECIPIENT_PATH, post(add_recipient))
+        .route(REMOVE_RECIPIENT_PATH, post(remove_recipient))
+        .route(JOIN_SERVER_PATH, post(join_server))
+        .route(LEAVE_SERVER_PATH, post(leave_server))
+        .route(SERVER_MEMBERS_PATH, get(get_members))
+        .route(KICK_MEMBER_PATH, post(kick_member))
+        .route(BAN_MEMBER_PATH, post(ban_member))
+        .route(UNBAN_MEMBER_PATH, post(unban_member))
+        .route(ROLES_PATH, get(get_roles))
+        .route(CREATE_ROLE_PATH, post(create_role))
+        .route(UPDATE_ROLE_PATH, post(update_role))
+        .route(MOVE_ROLE_PATH, post(move_role))
+        .route(DELETE_ROLE_PATH, post(delete_role))
+        .route(MEMBER_ROLES_PATH, get(get_member_roles))
+        .route(ADD_MEMBER_ROLE_PATH, post(add_member_role))
+        .route(REMOVE_MEMBER_ROLE_PATH, post(remove_member_role))
+        .route(CHANNEL_OVERWRITES_PATH, get(get_channel_overwrites))
+        .route(SET_CHANNEL_OVERWRITE_PATH, post(set_channel_overwrite))
+        .route(CLEAR_CHANNEL_OVERWRITE_PATH, post(clear_channel_overwrite))
+        .route(CREATE_INVITE_PATH, post(create_invite))
+        .route(INVITE_PATH, get(get_invite))
+        .route(REDEEM_INVITE_PATH, post(redeem_invite))
+        .route(INVITES_PATH, get(get_invites))
+        .route(REVOKE_INVITE_PATH, post(revoke_invite))
         .route(CREATE_MESSAGE_PATH, post(create_message))
+        .route(
+            CREATE_MESSAGE_WITH_ATTACHMENTS_PATH,
+            post(create_message_with_attachments).layer(DefaultBodyLimit::max(upload_limit)),
+        )
+        .route(ATTACHMENT_PATH, get(get_attachment))
+        .route(ATTACHMENT_THUMBNAIL_PATH, get(get_attachment_thumbnail))
+        .route(EDIT_MESSAGE_PATH, patch(edit_message))
+        .route(MESSAGE_EDITS_PATH, get(get_message_edits))
+        .route(DELETE_MESSAGE_PATH, post(delete_message))
+        .route(BULK_DELETE_MESSAGES_PATH, post(bulk_delete_messages))
+        .route(PURGE_MESSAGES_PATH, post(purge_messages))
+        .route(CHANNEL_MESSAGES_PATH, get(get_channel_messages))
+        .route(SEARCH_PATH, get(search_messages))
+        .route(ACK_PATH, post(ack))
+        .route(ADD_REACTION_PATH, post(add_reaction))
+        .route(REMOVE_REACTION_PATH, post(remove_reaction))
+        .route(EMOJIS_PATH, get(get_emojis))
+        .route(CREATE_EMOJI_PATH, post(create_emoji))
+        .route(DELETE_EMOJI_PATH, post(delete_emoji))
+        .route(CREATE_THREAD_PATH, post(create_thread))
+        .route(THREADS_PATH, get(get_threads))
+        .route(ARCHIVE_THREAD_PATH, post(archive_thread))
+        .route(LOCK_THREAD_PATH, post(lock_thread))
+        .route(THREAD_MEMBERS_PATH, get(get_thread_members))
+        .route(JOIN_THREAD_PATH, post(join_thread))
+        .route(LEAVE_THREAD_PATH, post(leave_thread))
         .route(TYPING_PATH, post(typing))
         .route(SNAPSHOT_PATH, get(get_snapshot))
         .route(GET_UPDATES_PATH, get(get_updates))
+        .route(SYNC_PATH, get(get_sync))
+        .route(GATEWAY_PATH, get(gateway))
         .route(VOICE_WS_PATH, get(voice_ws))
         .fallback_service(static_service)
         .with_state(state)
         .layer(CompressionLayer::new())
         .layer(TraceLayer::new_for_http());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    let listener = tokio::net::TcpListener::bind(config.bind_addr)
+        .await
+        .unwrap();
     axum::serve(listener, app).await.unwrap();
     Ok(())
 }
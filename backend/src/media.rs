@@ -0,0 +1,606 @@
+use crate::{
+    attachment::Attachment,
+    blob::{put_content, Blobs},
+    config::AttachmentConfig,
+    error::ServerErr,
+    message::Message,
+    snapshot::Update,
+    storage::Db,
+    Sender,
+};
+use bytes::Bytes;
+use image::{
+    codecs::jpeg::JpegEncoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, ImageResult,
+    Limits,
+};
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{mpsc, mpsc::error::TryRecvError, Mutex};
+
+pub const THUMBNAIL_SIZE: u32 = 320;
+pub const IMAGE_WORKERS: usize = 2;
+pub const IMAGE_QUEUE_LEN: usize = 256;
+/// Images wider or taller than this aren't decoded.
+pub const IMAGE_MAX_DIMENSION: u32 = 16384;
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+/// The EXIF tag pointing to the GPS directory.
+const GPS_IFD_TAG: usize = 0x8825;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Queues image attachments for the background workers, which record their
+/// size and make thumbnails.
+#[derive(Clone)]
+pub struct ImageQueue {
+    queue: mpsc::Sender<Attachment>,
+    /// Set when attachments were left out of the queue, so the first worker
+    /// to find it empty loads them from the database.
+    missed: Arc<AtomicBool>,
+}
+
+impl ImageQueue {
+    /// Starts the workers, which begin with whatever wasn't processed before
+    /// the last shutdown.
+    pub fn spawn(db: Db, blobs: Blobs, send: Sender, config: &AttachmentConfig) -> Self {
+        let (queue, jobs) = mpsc::channel::<Attachment>(config.image_queue_len);
+        let images = Self {
+            queue,
+            missed: Arc::new(AtomicBool::new(true)),
+        };
+        let jobs = Arc::new(Mutex::new(jobs));
+        for _ in 0..config.image_workers {
+            let (images, jobs) = (images.clone(), jobs.clone());
+            let (db, blobs, send) = (db.clone(), blobs.clone(), send.clone());
+            let config = config.clone();
+            tokio::spawn(async move {
+                loop {
+                    // The lock is only held while waiting or sweeping, so
+                    // another worker takes the next job while this one
+                    // processes.
+                    let attachment = {
+                        let mut jobs = jobs.lock().await;
+                        match jobs.try_recv() {
+                            Ok(attachment) => attachment,
+                            Err(TryRecvError::Empty)
+                                if images.missed.swap(false, Ordering::Relaxed) =>
+                            {
+                                images.sweep(&db).await;
+                                continue;
+                            }
+                            Err(_) => match jobs.recv().await {
+                                Some(attachment) => attachment,
+                                None => break,
+                            },
+                        }
+                    };
+                    let id = attachment.id;
+                    if let Err(err) = process(&db, &blobs, &send, &config, attachment).await {
+                        tracing::error!("Error processing attachment {id}: {err:?}");
+                    }
+                }
+            });
+        }
+        images
+    }
+
+    /// Queues the images among the attachments without waiting. When the
+    /// queue is full they're picked up once it drains.
+    pub fn push(&self, attachments: &[Attachment]) {
+        for attachment in attachments
+            .iter()
+            .filter(|attachment| attachment.is_image())
+        {
+            if let Err(err) = self.queue.try_send(attachment.clone()) {
+                tracing::warn!("Unable to queue attachment {}: {err}", attachment.id);
+                self.missed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Queues the unprocessed attachments in the database, as many as fit.
+    /// Some may already be with another worker, which only repeats the work.
+    async fn sweep(&self, db: &Db) {
+        let attachments = match db.get_unprocessed_attachments().await {
+            Ok(attachments) => attachments,
+            Err(err) => {
+                tracing::error!("Error loading unprocessed attachments: {err:?}");
+                return;
+            }
+        };
+        for attachment in attachments {
+            if self.queue.try_send(attachment).is_err() {
+                self.missed.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+}
+
+/// Records the image's size and thumbnail, and sends the result to the
+/// channel. Files that are missing or don't decode, or turn out to be a type
+/// that isn't allowed, are marked processed without either.
+async fn process(
+    db: &Db,
+    blobs: &Blobs,
+    send: &Sender,
+    config: &AttachmentConfig,
+    mut attachment: Attachment,
+) -> Result<(), ServerErr> {
+    if attachment.is_image() {
+        match read_image(blobs, &attachment, config.thumbnail_size).await {
+            // The uploaded type was checked against the allowed ones, so it's
+            // kept rather than swapped for one that wasn't.
+            Ok(info) if !config.allows(info.content_type) => {
+                tracing::warn!(
+                    "Attachment {} is {}, which isn't allowed",
+                    attachment.id,
+                    info.content_type
+                );
+            }
+            Ok(info) => {
+                attachment.content_type = info.content_type.to_string();
+                attachment.width = Some(info.width as i32);
+                attachment.height = Some(info.height as i32);
+                attachment.thumbnail_hash = Some(put_content(blobs, info.thumbnail).await?);
+            }
+            Err(err) => {
+                tracing::warn!("Unable to read attachment {}: {err}", attachment.id);
+            }
+        }
+    }
+    db.update_attachment_image(&attachment).await?;
+    if attachment.thumbnail_hash.is_none() {
+        return Ok(());
+    }
+    attachment.fill_thumbnail_url();
+    let message = Message::get(db, attachment.message_id).await?;
+    let update = Update::AttachmentUpdate {
+        server_id: message.server_id,
+        channel_id: message.channel_id,
+        attachment,
+    };
+    if let Err(err) = send.send(update) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+/// Reads the attachment's blob and decodes it.
+async fn read_image(
+    blobs: &Blobs,
+    attachment: &Attachment,
+    size: u32,
+) -> Result<ImageInfo, ServerErr> {
+    let data = blobs.get_all(&attachment.hash).await?;
+    tokio::task::spawn_blocking(move || ImageInfo::read(&data, size))
+        .await
+        .map_err(|err| ServerErr::ImageErr(err.to_string()))?
+        .map_err(|err| ServerErr::ImageErr(err.to_string()))
+}
+
+/// What decoding an image found out.
+struct ImageInfo {
+    /// From the file's contents rather than what the uploader said.
+    content_type: &'static str,
+    width: u32,
+    height: u32,
+    thumbnail: Bytes,
+}
+
+impl ImageInfo {
+    /// Decodes the image, turned upright if its EXIF says so, and makes a
+    /// thumbnail no larger than `size` on either side. Thumbnails are PNG
+    /// when the image has transparency and JPEG otherwise.
+    fn read(data: &[u8], size: u32) -> ImageResult<Self> {
+        let format = image::guess_format(data)?;
+        let mut reader = ImageReader::with_format(Cursor::new(data), format);
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(IMAGE_MAX_DIMENSION);
+        limits.max_image_height = Some(IMAGE_MAX_DIMENSION);
+        reader.limits(limits);
+        let mut decoder = reader.into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        let (width, height) = (image.width(), image.height());
+        let thumbnail = if width > size || height > size {
+            image.thumbnail(size, size)
+        } else {
+            image
+        };
+        let mut encoded = Cursor::new(Vec::new());
+        if thumbnail.color().has_alpha() {
+            thumbnail.write_to(&mut encoded, ImageFormat::Png)?;
+        } else {
+            thumbnail
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(
+                    &mut encoded,
+                    THUMBNAIL_JPEG_QUALITY,
+                ))?;
+        }
+        Ok(Self {
+            content_type: format.to_mime_type(),
+            width,
+            height,
+            thumbnail: encoded.into_inner().into(),
+        })
+    }
+}
+
+/// The content type of a thumbnail made by `ImageInfo::read`.
+pub fn thumbnail_content_type(data: &[u8]) -> &'static str {
+    if data.starts_with(PNG_SIGNATURE) {
+        "image/png"
+    } else {
+        "image/jpeg"
+    }
+}
+
+/// Blanks the GPS position in an image's EXIF data. Nothing in the file
+/// moves, so the rest of the metadata, orientation included, still applies.
+/// Handles JPEG, PNG and WebP, and anything else comes back unchanged.
+pub fn strip_gps(data: Bytes) -> Bytes {
+    let mut data = Vec::from(data);
+    if data.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg_gps(&mut data);
+    } else if data.starts_with(PNG_SIGNATURE) {
+        strip_png_gps(&mut data);
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        strip_webp_gps(&mut data);
+    }
+    data.into()
+}
+
+/// EXIF is in an APP1 segment, before the image data starts.
+fn strip_jpeg_gps(data: &mut [u8]) {
+    let mut pos = 2;
+    while let (Some(&0xFF), Some(&marker)) = (data.get(pos), data.get(pos + 1)) {
+        // Start of scan or end of image.
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let Some(len) = data.get(pos + 2..pos + 4).map(be_u16) else {
+            break;
+        };
+        let end = pos + 2 + len;
+        if marker == 0xE1
+            && let Some(segment) = data.get_mut(pos + 4..end)
+            && segment.starts_with(EXIF_HEADER)
+        {
+            scrub_tiff(&mut segment[EXIF_HEADER.len()..]);
+        }
+        pos = end;
+    }
+}
+
+/// EXIF is in an `eXIf` chunk, whose checksum has to be redone.
+fn strip_png_gps(data: &mut [u8]) {
+    let mut pos = PNG_SIGNATURE.len();
+    while let Some(len) = data.get(pos..pos + 4).map(be_u32) {
+        let (start, end) = (pos + 8, pos + 8 + len);
+        if end + 4 > data.len() {
+            break;
+        }
+        if &data[pos + 4..start] == b"eXIf" {
+            scrub_tiff(&mut data[start..end]);
+            let crc = crc32fast::hash(&data[pos + 4..end]);
+            data[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+        }
+        pos = end + 4;
+    }
+}
+
+/// EXIF is in an `EXIF` chunk, sometimes with the JPEG header left on.
+fn strip_webp_gps(data: &mut [u8]) {
+    let mut pos = 12;
+    while let Some(len) = data.get(pos + 4..pos + 8).map(le_u32) {
+        let (start, end) = (pos + 8, pos + 8 + len);
+        if end > data.len() {
+            break;
+        }
+        if &data[pos..pos + 4] == b"EXIF" {
+            let chunk = &mut data[start..end];
+            let skip = if chunk.starts_with(EXIF_HEADER) {
+                EXIF_HEADER.len()
+            } else {
+                0
+            };
+            scrub_tiff(&mut chunk[skip..]);
+        }
+        // Chunks are padded to an even length.
+        pos = end + len % 2;
+    }
+}
+
+/// Empties the GPS directory of EXIF data, which is laid out like a TIFF
+/// file, along with the values it points to. The directory stays where it
+/// is with no entries, so no offsets change.
+fn scrub_tiff(tiff: &mut [u8]) -> Option<()> {
+    type Read = fn(&[u8]) -> usize;
+    let (read_u16, read_u32): (Read, Read) = match tiff.get(..2)? {
+        b"II" => (le_u16, le_u32),
+        b"MM" => (be_u16, be_u32),
+        _ => return None,
+    };
+    let u16_at = |tiff: &[u8], at: usize| tiff.get(at..at + 2).map(read_u16);
+    let u32_at = |tiff: &[u8], at: usize| tiff.get(at..at + 4).map(read_u32);
+
+    let ifd0 = u32_at(tiff, 4)?;
+    let gps_entry = (0..u16_at(tiff, ifd0)?)
+        .map(|i| ifd0 + 2 + 12 * i)
+        .find(|&entry| u16_at(tiff, entry) == Some(GPS_IFD_TAG))?;
+    let gps = u32_at(tiff, gps_entry + 8)?;
+    let count = u16_at(tiff, gps)?;
+    for i in 0..count {
+        let entry = gps + 2 + 12 * i;
+        let len = type_size(u16_at(tiff, entry + 2)?).saturating_mul(u32_at(tiff, entry + 4)?);
+        // Values that don't fit in the entry are stored at an offset.
+        if len > 4 {
+            let at = u32_at(tiff, entry + 8)?;
+            if let Some(value) = tiff.get_mut(at..at.saturating_add(len)) {
+                value.fill(0);
+            }
+        }
+    }
+    // No entries and no next directory.
+    let end = (gps + 2 + 12 * count + 4).min(tiff.len());
+    tiff.get_mut(gps..end)?.fill(0);
+    Some(())
+}
+
+/// Size in bytes of one value of a TIFF field type.
+fn type_size(kind: usize) -> usize {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+fn le_u16(bytes: &[u8]) -> usize {
+    u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+}
+
+fn be_u16(bytes: &[u8]) -> usize {
+    u16::from_be_bytes([bytes[0], bytes[1]]) as usize
+}
+
+fn le_u32(bytes: &[u8]) -> usize {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+}
+
+fn be_u32(bytes: &[u8]) -> usize {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageEncoder, RgbImage};
+
+    /// EXIF with an orientation and a GPS directory holding a latitude, which
+    /// is stored at an offset.
+    fn tiff(big_endian: bool) -> Vec<u8> {
+        let u16 = |n: u16| {
+            if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+        let u32 = |n: u32| {
+            if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+        let mut out = Vec::from(if big_endian { b"MM" } else { b"II" });
+        out.extend(u16(42));
+        out.extend(u32(8));
+        // IFD0 at 8, ending at 38.
+        out.extend(u16(2));
+        out.extend([u16(0x0112), u16(3)].concat());
+        out.extend(u32(1));
+        out.extend([u16(6), u16(0)].concat());
+        out.extend([u16(GPS_IFD_TAG as u16), u16(4)].concat());
+        out.extend([u32(1), u32(38)].concat());
+        out.extend(u32(0));
+        // GPS directory at 38, ending at 68.
+        out.extend(u16(2));
+        out.extend([u16(1), u16(2)].concat());
+        out.extend(u32(2));
+        out.extend(*b"N\0\0\0");
+        out.extend([u16(2), u16(5)].concat());
+        out.extend([u32(3), u32(68)].concat());
+        out.extend(u32(0));
+        // Latitude at 68.
+        for n in [51, 1, 30, 1, 1234, 100] {
+            out.extend(u32(n));
+        }
+        out
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let image = RgbImage::from_fn(16, 8, |x, y| image::Rgb([x as u8 * 16, y as u8 * 32, 0]));
+        let mut out = Vec::new();
+        JpegEncoder::new(&mut out)
+            .write_image(&image, 16, 8, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        out
+    }
+
+    fn jpeg_with_app1(payload: &[u8]) -> Vec<u8> {
+        let jpeg = jpeg();
+        let mut out = jpeg[..2].to_vec();
+        out.extend([0xFF, 0xE1]);
+        out.extend((payload.len() as u16 + 2).to_be_bytes());
+        out.extend(payload);
+        out.extend(&jpeg[2..]);
+        out
+    }
+
+    fn strip(data: &[u8]) -> Vec<u8> {
+        strip_gps(Bytes::copy_from_slice(data)).into()
+    }
+
+    #[test]
+    fn strips_gps_from_jpeg() {
+        let tiff = tiff(true);
+        let data = jpeg_with_app1(&[EXIF_HEADER, &tiff].concat());
+        let stripped = strip(&data);
+        assert_eq!(stripped.len(), data.len());
+        let start = 6 + EXIF_HEADER.len();
+        let tiff_out = &stripped[start..start + tiff.len()];
+        assert_eq!(tiff_out[..38], tiff[..38]);
+        assert!(tiff_out[38..].iter().all(|&b| b == 0));
+        assert_eq!(stripped[start + tiff.len()..], data[start + tiff.len()..]);
+
+        let reader = ImageReader::with_format(Cursor::new(&stripped), ImageFormat::Jpeg);
+        let mut decoder = reader.into_decoder().unwrap();
+        assert_eq!(
+            decoder.orientation().unwrap(),
+            image::metadata::Orientation::Rotate90
+        );
+        assert_eq!(decoder.dimensions(), (16, 8));
+    }
+
+    #[test]
+    fn strips_gps_from_png() {
+        let tiff = tiff(false);
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend((tiff.len() as u32).to_be_bytes());
+        data.extend(b"eXIf");
+        data.extend(&tiff);
+        data.extend(crc32fast::hash(&[b"eXIf", &tiff[..]].concat()).to_be_bytes());
+        let stripped = strip(&data);
+        let chunk = &stripped[PNG_SIGNATURE.len() + 4..stripped.len() - 4];
+        assert!(chunk[4 + 38..].iter().all(|&b| b == 0));
+        let crc = crc32fast::hash(chunk).to_be_bytes();
+        assert_eq!(stripped[stripped.len() - 4..], crc);
+    }
+
+    #[test]
+    fn strips_gps_from_webp() {
+        let tiff = tiff(true);
+        let mut data = b"RIFF\0\0\0\0WEBPEXIF".to_vec();
+        data.extend((tiff.len() as u32).to_le_bytes());
+        data.extend(&tiff);
+        let stripped = strip(&data);
+        assert!(stripped[20 + 38..].iter().all(|&b| b == 0));
+    }
+
+    /// Every cut of the file, whether in the middle of a segment header, the
+    /// EXIF header or the TIFF data.
+    #[test]
+    fn truncated_exif_is_left_alone() {
+        let tiff = tiff(true);
+        let data = jpeg_with_app1(&[EXIF_HEADER, &tiff].concat());
+        for len in 0..6 + EXIF_HEADER.len() + tiff.len() {
+            strip(&data[..len]);
+        }
+        for len in 0..tiff.len() {
+            strip(&jpeg_with_app1(&[EXIF_HEADER, &tiff[..len]].concat()));
+            let mut png = PNG_SIGNATURE.to_vec();
+            png.extend((len as u32).to_be_bytes());
+            png.extend(b"eXIf");
+            png.extend(&tiff[..len]);
+            png.extend([0; 4]);
+            strip(&png);
+            let mut webp = b"RIFF\0\0\0\0WEBPEXIF".to_vec();
+            webp.extend((len as u32).to_le_bytes());
+            webp.extend(&tiff[..len]);
+            strip(&webp);
+        }
+    }
+
+    #[test]
+    fn zero_lengths_are_left_alone() {
+        let jpeg = jpeg();
+        let mut data = jpeg[..2].to_vec();
+        data.extend([0xFF, 0xE1, 0, 0]);
+        data.extend(&jpeg[2..]);
+        assert_eq!(strip(&data), data);
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend([0, 0, 0, 0]);
+        png.extend(b"eXIf");
+        png.extend(crc32fast::hash(b"eXIf").to_be_bytes());
+        assert_eq!(strip(&png), png);
+
+        let webp = b"RIFF\0\0\0\0WEBPEXIF\0\0\0\0".to_vec();
+        assert_eq!(strip(&webp), webp);
+
+        // A GPS directory with no entries.
+        let mut tiff = tiff(true);
+        tiff[39] = 0;
+        scrub_tiff(&mut tiff);
+    }
+
+    #[test]
+    fn lengths_past_the_end_are_left_alone() {
+        let tiff = tiff(true);
+        let mut data = jpeg_with_app1(&[EXIF_HEADER, &tiff].concat());
+        data[4..6].copy_from_slice(&u16::MAX.to_be_bytes());
+        let len = 6 + EXIF_HEADER.len() + tiff.len();
+        data.truncate(len);
+        assert_eq!(strip(&data), data);
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(u32::MAX.to_be_bytes());
+        png.extend(b"eXIf");
+        png.extend(&tiff);
+        assert_eq!(strip(&png), png);
+
+        let mut webp = b"RIFF\0\0\0\0WEBPEXIF".to_vec();
+        webp.extend(u32::MAX.to_le_bytes());
+        webp.extend(&tiff);
+        assert_eq!(strip(&webp), webp);
+    }
+
+    #[test]
+    fn offsets_past_the_end_are_skipped() {
+        let original = tiff(true);
+        let offsets = [
+            // IFD0.
+            4..8,
+            // The GPS directory.
+            30..34,
+            // The latitude.
+            60..64,
+        ];
+        for offset in offsets {
+            for value in [original.len() as u32, u32::MAX] {
+                let mut tiff = original.clone();
+                tiff[offset.clone()].copy_from_slice(&value.to_be_bytes());
+                scrub_tiff(&mut tiff);
+            }
+        }
+        // Only the latitude is out of reach, so the rest is still blanked.
+        let mut tiff = original.clone();
+        tiff[60..64].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(scrub_tiff(&mut tiff).is_some());
+        assert!(tiff[38..68].iter().all(|&b| b == 0));
+        assert_eq!(tiff[68..], original[68..]);
+    }
+
+    #[test]
+    fn huge_counts_dont_overflow() {
+        let mut tiff = tiff(true);
+        // The latitude's count.
+        tiff[56..60].copy_from_slice(&u32::MAX.to_be_bytes());
+        scrub_tiff(&mut tiff);
+        let mut tiff = self::tiff(true);
+        // The GPS directory's entry count.
+        tiff[38..40].copy_from_slice(&u16::MAX.to_be_bytes());
+        scrub_tiff(&mut tiff);
+    }
+}
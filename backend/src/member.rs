@@ -0,0 +1,257 @@
+use crate::{
+    auth::AuthUser,
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub const JOIN_SERVER_PATH: &str = "/join-server";
+pub const LEAVE_SERVER_PATH: &str = "/leave-server";
+pub const SERVER_MEMBERS_PATH: &str = "/server-members";
+pub const KICK_MEMBER_PATH: &str = "/kick-member";
+pub const BAN_MEMBER_PATH: &str = "/ban-member";
+pub const UNBAN_MEMBER_PATH: &str = "/unban-member";
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct ServerMember {
+    pub server_id: ServerId,
+    pub user_id: UserId,
+    pub joined_ts: DateTime<Utc>,
+}
+
+impl ServerMember {
+    pub async fn insert(db: &Db, server_id: ServerId, user_id: UserId) -> Result<Self, ServerErr> {
+        if !db.server_exists(server_id).await? {
+            return Err(ServerErr::NoServerId(server_id));
+        }
+        if db.is_banned(server_id, user_id).await? {
+            return Err(ServerErr::Banned(server_id));
+        }
+        let joined_ts = Utc::now();
+        if !db.insert_member(server_id, user_id, joined_ts).await? {
+            return Err(ServerErr::AlreadyMember(server_id));
+        }
+        Ok(Self {
+            server_id,
+            user_id,
+            joined_ts,
+        })
+    }
+
+    /// Rejects the request unless the user belongs to the server.
+    pub async fn check(db: &Db, server_id: ServerId, user_id: UserId) -> Result<(), ServerErr> {
+        if db.is_member(server_id, user_id).await? {
+            Ok(())
+        } else {
+            Err(ServerErr::NotMember(server_id))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct MemberParams {
+    #[param(required = true)]
+    server_id: ServerId,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct ModerateParams {
+    #[param(required = true)]
+    server_id: ServerId,
+    #[param(required = true)]
+    user_id: UserId,
+}
+
+/// Removes the member and tells everyone they left.
+async fn remove_member(
+    db: &Db,
+    send: &Sender,
+    server_id: ServerId,
+    user_id: UserId,
+) -> Result<bool, ServerErr> {
+    if !db.delete_member(server_id, user_id).await? {
+        return Ok(false);
+    }
+    if let Err(err) = send.send(Update::MemberLeave { server_id, user_id }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(true)
+}
+
+#[utoipa::path(
+    post,
+    path = JOIN_SERVER_PATH,
+    params(MemberParams),
+    responses(
+        (status = 200, description = "Join a server that has no owner", body = ServerMember),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Server has an owner or you're banned", body = String),
+        (status = 409, description = "Already a member", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn join_server(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MemberParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let server = db
+        .get_server(query.server_id)
+        .await?
+        .ok_or(ServerErr::NoServerId(query.server_id))?;
+    // Servers with an owner are joined by invite. Ownerless ones, the seeded
+    // server and those from before owners, stay open to everyone.
+    if server.owner_id.is_some() {
+        return Err(ServerErr::Forbidden(format!(
+            "Server ID {} can only be joined with an invite",
+            server.id
+        )));
+    }
+    let member = ServerMember::insert(&db, server.id, user.id).await?;
+    if let Err(err) = send.send(Update::MemberJoin(member.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = LEAVE_SERVER_PATH,
+    params(MemberParams),
+    responses(
+        (status = 200, description = "Leave a server", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a member", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn leave_server(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MemberParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let access = MemberAccess::resolve(&db, query.server_id, user.id).await?;
+    if access.owner {
+        return Err(ServerErr::BadRequest(
+            "The owner can't leave their server".to_string(),
+        ));
+    }
+    remove_member(&db, &send, query.server_id, user.id).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = KICK_MEMBER_PATH,
+    params(ModerateParams),
+    responses(
+        (status = 200, description = "Remove a member from a server", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing KICK_MEMBERS or member is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn kick_member(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ModerateParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let access =
+        MemberAccess::require(&db, query.server_id, user.id, Permissions::KICK_MEMBERS).await?;
+    let target = MemberAccess::resolve(&db, query.server_id, query.user_id).await?;
+    access.check_outranks_member(&target)?;
+    remove_member(&db, &send, query.server_id, query.user_id).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = BAN_MEMBER_PATH,
+    params(ModerateParams),
+    responses(
+        (status = 200, description = "Remove a user from a server and stop them rejoining", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing BAN_MEMBERS or member is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn ban_member(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ModerateParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let access =
+        MemberAccess::require(&db, query.server_id, user.id, Permissions::BAN_MEMBERS).await?;
+    // Users can be banned before they ever join.
+    if db.is_member(query.server_id, query.user_id).await? {
+        let target = MemberAccess::resolve(&db, query.server_id, query.user_id).await?;
+        access.check_outranks_member(&target)?;
+    } else if !db.user_exists(query.user_id).await? {
+        return Err(ServerErr::NoUserId(query.user_id));
+    }
+    db.insert_ban(query.server_id, query.user_id, Utc::now())
+        .await?;
+    remove_member(&db, &send, query.server_id, query.user_id).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = UNBAN_MEMBER_PATH,
+    params(ModerateParams),
+    responses(
+        (status = 200, description = "Let a banned user rejoin", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing BAN_MEMBERS", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn unban_member(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ModerateParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    MemberAccess::require(&db, query.server_id, user.id, Permissions::BAN_MEMBERS).await?;
+    db.delete_ban(query.server_id, query.user_id).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = SERVER_MEMBERS_PATH,
+    params(MemberParams),
+    responses(
+        (status = 200, description = "List the members of a server", body = Vec<ServerMember>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a member", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_members(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MemberParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    ServerMember::check(&db, query.server_id, user.id).await?;
+    let members = db.get_members(query.server_id).await?;
+    Ok(Json(members))
+}
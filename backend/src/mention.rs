@@ -0,0 +1,114 @@
+use crate::{
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    role::RoleId,
+    storage::Db,
+    user::UserId,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+/// Who and what a message mentions, resolved from `<@user_id>`,
+/// `<@&role_id>`, `<#channel_id>`, `@everyone` and `@here` in its text.
+/// Mentions of things outside the message's server are dropped, and so are
+/// role and everyone mentions by authors without MENTION_EVERYONE. In DMs
+/// only recipients can be mentioned.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Mentions {
+    /// Members of the server.
+    #[sqlx(rename = "mention_users")]
+    pub users: Vec<UserId>,
+    #[sqlx(rename = "mention_roles")]
+    pub roles: Vec<RoleId>,
+    /// Channels of the server.
+    #[sqlx(rename = "mention_channels")]
+    pub channels: Vec<ChannelId>,
+    /// Whether the message mentions `@everyone` or `@here`.
+    #[sqlx(rename = "mention_everyone")]
+    pub everyone: bool,
+}
+
+impl Mentions {
+    /// Finds the mention tokens in the text, without checking them.
+    pub fn parse(text: &str) -> Self {
+        let mut mentions = Self::default();
+        for (start, _) in text.match_indices('<') {
+            let rest = &text[start + 1..];
+            let Some(end) = rest.find('>') else {
+                break;
+            };
+            let token = &rest[..end];
+            if let Some(id) = token.strip_prefix("@&").and_then(parse_id) {
+                mentions.roles.push(id);
+            } else if let Some(id) = token.strip_prefix('@').and_then(parse_id) {
+                mentions.users.push(id);
+            } else if let Some(id) = token.strip_prefix('#').and_then(parse_id) {
+                mentions.channels.push(id);
+            }
+        }
+        for ids in [
+            &mut mentions.users,
+            &mut mentions.roles,
+            &mut mentions.channels,
+        ] {
+            ids.sort_unstable();
+            ids.dedup();
+        }
+        mentions.everyone = text.contains("@everyone") || text.contains("@here");
+        mentions
+    }
+
+    /// Keeps the mentions that exist in the channel's server, or DM, and
+    /// that the author may make there.
+    pub async fn resolve(
+        mut self,
+        db: &Db,
+        channel: &Channel,
+        access: &MemberAccess,
+    ) -> Result<Self, ServerErr> {
+        let mut users = Vec::with_capacity(self.users.len());
+        let Some(server_id) = access.server_id else {
+            for user_id in self.users {
+                if db.is_recipient(channel.id, user_id).await? {
+                    users.push(user_id);
+                }
+            }
+            return Ok(Self {
+                users,
+                ..Self::default()
+            });
+        };
+        let mass = access.permissions.contains(Permissions::MENTION_EVERYONE);
+        for user_id in self.users {
+            if db.is_member(server_id, user_id).await? {
+                users.push(user_id);
+            }
+        }
+        self.users = users;
+        if mass && !self.roles.is_empty() {
+            let roles = db.get_roles(server_id).await?;
+            self.roles
+                .retain(|role_id| roles.iter().any(|role| role.id == *role_id));
+        } else {
+            self.roles.clear();
+        }
+        if !self.channels.is_empty() {
+            let channels = db.get_server_channels(server_id).await?;
+            self.channels
+                .retain(|channel_id| channels.iter().any(|channel| channel.id == *channel_id));
+        }
+        self.everyone &= mass;
+        Ok(self)
+    }
+}
+
+fn parse_id(id: &str) -> Option<i32> {
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    id.parse().ok()
+}
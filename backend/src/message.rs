@@ -1,70 +1,273 @@
 use crate::{
-    channel::ChannelId, error::ServerErr, server::ServerId, snapshot::Update, user::UserId, Sender,
+    attachment::Attachment,
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    config::Limits,
+    error::ServerErr,
+    mention::Mentions,
+    permission::{MemberAccess, Permissions},
+    reaction::{Reaction, ReactionCount},
+    read_state::ReadState,
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    thread::{Thread, ThreadId},
+    user::UserId,
+    Sender,
 };
 use axum::{
-    extract::{Query, State},
-    response::{sse::Event, IntoResponse},
+    extract::{Path, Query, State},
+    response::IntoResponse,
     Json,
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_scalar, SqlitePool};
+use sqlx::FromRow;
 use ts_rs::TS;
 use utoipa::{IntoParams, ToSchema};
 
 pub type MessageId = i64;
 
 pub const MESSAGE_MAX_LEN: usize = 512;
+pub const BULK_DELETE_MAX: usize = 100;
+pub const HISTORY_PAGE_MAX: usize = 100;
+pub const HISTORY_PAGE_DEFAULT: usize = 50;
+/// How many characters of a replied-to message are sent along with replies.
+pub const REPLY_EXCERPT_LEN: i32 = 100;
 pub const CREATE_MESSAGE_PATH: &str = "/create-message";
+pub const EDIT_MESSAGE_PATH: &str = "/edit-message";
+pub const MESSAGE_EDITS_PATH: &str = "/message-edits";
+pub const DELETE_MESSAGE_PATH: &str = "/delete-message";
+pub const BULK_DELETE_MESSAGES_PATH: &str = "/bulk-delete-messages";
+pub const PURGE_MESSAGES_PATH: &str = "/purge-messages";
+pub const CHANNEL_MESSAGES_PATH: &str = "/channels/{channel_id}/messages";
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
 #[ts(export, export_to = "../../frontend/src/bindings/")]
 pub struct Message {
     pub user_id: UserId,
     pub channel_id: ChannelId,
-    pub server_id: ServerId,
+    /// Unset in DMs.
+    pub server_id: Option<ServerId>,
+    /// The thread in the channel this was posted in, if any.
+    pub thread_id: Option<ThreadId>,
     pub ts: DateTime<Utc>,
     pub id: MessageId,
     pub text: String,
+    /// When the text was last changed. Unset if it never was.
+    pub edited_ts: Option<DateTime<Utc>>,
+    /// The message this replies to. Stays set if that message is deleted.
+    pub reply_to: Option<MessageId>,
+    /// Author of the replied-to message. Unset if it was deleted.
+    pub reply_user_id: Option<UserId>,
+    /// Start of the replied-to message's text. Unset if it was deleted.
+    pub reply_excerpt: Option<String>,
+    #[sqlx(flatten)]
+    pub mentions: Mentions,
+    /// Reactions as seen by the requesting user. Only filled in where
+    /// messages are fetched, not in updates.
+    #[sqlx(skip)]
+    pub reactions: Vec<ReactionCount>,
+    /// Files sent with the message. Not filled in by storage, and left out
+    /// of edit updates since they can't change.
+    #[sqlx(skip)]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A previous version of a message's text.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct MessageEdit {
+    pub message_id: MessageId,
+    pub text: String,
+    /// When this text was replaced.
+    pub edited_ts: DateTime<Utc>,
 }
 
 impl Message {
     pub async fn insert(
-        pool: &SqlitePool,
+        db: &Db,
+        limits: &Limits,
         user_id: UserId,
-        channel_id: ChannelId,
-        server_id: ServerId,
-        text: String,
+        params: CreateMessageParams,
     ) -> Result<Self, ServerErr> {
-        let len = text.len();
-        if len > MESSAGE_MAX_LEN {
-            Err(ServerErr::MessageTooLong(len))
-        } else {
-            let ts = Utc::now();
-            let id = query!(
-                r#"
-                INSERT INTO messages (user_id, channel_id, server_id, text, ts)
-                VALUES ($1, $2, $3, $4, $5)
-                RETURNING id;
-                "#,
-                user_id,
-                channel_id,
-                server_id,
-                text,
-                ts
-            )
-            .fetch_one(pool)
+        validate_text(limits, &params.text)?;
+        let channel = Channel::get(db, params.channel_id).await?;
+        if channel.server_id != params.server_id {
+            return Err(ServerErr::BadRequest(match params.server_id {
+                Some(server_id) => {
+                    format!("Channel ID {} is not in server ID {server_id}", channel.id)
+                }
+                None => format!("Channel ID {} is not a DM", channel.id),
+            }));
+        }
+        let access = MemberAccess::require_channel(
+            db,
+            &channel,
+            user_id,
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+        )
+        .await?;
+        if let Some(thread_id) = params.thread_id {
+            let thread = Thread::get(db, thread_id).await?;
+            if thread.channel_id != channel.id {
+                return Err(ServerErr::BadRequest(format!(
+                    "Thread ID {thread_id} is not in channel ID {}",
+                    channel.id
+                )));
+            }
+            thread.check_postable(&access)?;
+        }
+        let parent = match params.reply_to {
+            Some(reply_to) => Some(Self::get(db, reply_to).await?),
+            None => None,
+        };
+        if let Some(parent) = &parent
+            && (parent.channel_id != channel.id || parent.thread_id != params.thread_id)
+        {
+            return Err(ServerErr::BadRequest(format!(
+                "Message ID {} is not in the same channel and thread",
+                parent.id
+            )));
+        }
+        let mentions = Mentions::parse(&params.text)
+            .resolve(db, &channel, &access)
+            .await?;
+        let mut message = Self {
+            user_id,
+            channel_id: channel.id,
+            server_id: channel.server_id,
+            thread_id: params.thread_id,
+            ts: Utc::now(),
+            id: 0,
+            text: params.text,
+            edited_ts: None,
+            reply_to: params.reply_to,
+            reply_user_id: parent.as_ref().map(|parent| parent.user_id),
+            reply_excerpt: parent.map(|parent| parent.excerpt()),
+            mentions,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+        };
+        message.id = db.insert_message(&message).await?;
+        Ok(message)
+    }
+
+    /// Counts a new message towards its thread and read states and sends it
+    /// to viewers of the channel.
+    pub async fn publish(&self, db: &Db, send: &Sender) -> Result<(), ServerErr> {
+        if let Some(thread) = Thread::record_message(db, self).await?
+            && let Err(err) = send.send(Update::ThreadUpdate(thread))
+        {
+            tracing::error!("Error sending event: {err:?}");
+        }
+        ReadState::record_message(db, self).await?;
+        let receiver_count = send.receiver_count();
+        tracing::info!("Sending message update to {} SSE clients", receiver_count);
+        if let Err(err) = send.send(Update::Message(self.clone())) {
+            tracing::error!("Error sending event: {err:?}");
+        }
+        Ok(())
+    }
+
+    /// What replies to this message show of it.
+    pub fn excerpt(&self) -> String {
+        self.text.chars().take(REPLY_EXCERPT_LEN as usize).collect()
+    }
+
+    pub async fn get(db: &Db, message_id: MessageId) -> Result<Self, ServerErr> {
+        db.get_message(message_id)
             .await?
-            .id;
-            Ok(Self {
-                user_id,
-                channel_id,
-                server_id,
-                ts,
-                id,
-                text,
-            })
+            .ok_or(ServerErr::NoMessageId(message_id))
+    }
+
+    /// Replaces the text. Only the author can edit, and only while they can
+    /// still see the channel.
+    pub async fn edit(
+        mut self,
+        db: &Db,
+        limits: &Limits,
+        user_id: UserId,
+        text: String,
+    ) -> Result<Self, ServerErr> {
+        validate_text(limits, &text)?;
+        if self.user_id != user_id {
+            return Err(ServerErr::Forbidden(
+                "Only the author can edit a message".to_string(),
+            ));
         }
+        let channel = Channel::get(db, self.channel_id).await?;
+        let access =
+            MemberAccess::require_channel(db, &channel, user_id, Permissions::VIEW_CHANNEL).await?;
+        let mentions = Mentions::parse(&text)
+            .resolve(db, &channel, &access)
+            .await?;
+        let edited_ts = Utc::now();
+        db.edit_message(self.id, &text, edited_ts, &mentions)
+            .await?;
+        self.text = text;
+        self.edited_ts = Some(edited_ts);
+        self.mentions = mentions;
+        Ok(self)
+    }
+
+    /// A page of the channel's history, oldest first. `around` includes the
+    /// message itself along with those on either side of it.
+    pub async fn history(
+        db: &Db,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        cursor: HistoryCursor,
+        limit: i64,
+    ) -> Result<Vec<Self>, ServerErr> {
+        let messages = match cursor {
+            HistoryCursor::Before(before) => {
+                let mut messages = db
+                    .get_messages_before(channel_id, thread_id, before, limit)
+                    .await?;
+                messages.reverse();
+                messages
+            }
+            HistoryCursor::After(after) => {
+                db.get_messages_after(channel_id, thread_id, after, limit)
+                    .await?
+            }
+            HistoryCursor::Around(around) => {
+                let older = limit / 2;
+                let mut messages = db
+                    .get_messages_before(channel_id, thread_id, around, older)
+                    .await?;
+                messages.reverse();
+                messages.extend(
+                    db.get_messages_after(
+                        channel_id,
+                        thread_id,
+                        around.saturating_sub(1),
+                        limit - older,
+                    )
+                    .await?,
+                );
+                messages
+            }
+        };
+        Ok(messages)
+    }
+}
+
+/// Where a page of history starts. The newest messages are those before
+/// `MessageId::MAX`.
+pub enum HistoryCursor {
+    Before(MessageId),
+    After(MessageId),
+    Around(MessageId),
+}
+
+fn validate_text(limits: &Limits, text: &str) -> Result<(), ServerErr> {
+    let len = text.len();
+    if len > limits.message_max_len {
+        Err(ServerErr::MessageTooLong(len, limits.message_max_len))
+    } else {
+        Ok(())
     }
 }
 
@@ -73,11 +276,13 @@ pub struct CreateMessageParams {
     #[param(example = "Hello, world!", required = true)]
     text: String,
     #[param(required = true)]
-    user_id: UserId,
-    #[param(required = true)]
     channel_id: ChannelId,
-    #[param(required = true)]
-    server_id: ServerId,
+    /// The channel's server. Unset for DMs.
+    server_id: Option<ServerId>,
+    /// The thread in the channel to post in.
+    thread_id: Option<ThreadId>,
+    /// The message in the same channel and thread this replies to.
+    reply_to: Option<MessageId>,
 }
 
 #[utoipa::path(
@@ -86,57 +291,307 @@ pub struct CreateMessageParams {
     params(CreateMessageParams),
     responses(
         (status = 200, description = "Create a new message", body = Message),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing SEND_MESSAGES, or the thread is locked", body = String),
         (status = 500, description = "Internal message error", body = String)
     )
 )]
 pub async fn create_message(
-    State(pool): State<SqlitePool>,
+    State(db): State<Db>,
+    State(limits): State<Limits>,
     State(send): State<Sender>,
+    AuthUser(user): AuthUser,
     Query(query): Query<CreateMessageParams>,
 ) -> Result<impl IntoResponse, ServerErr> {
-    let user_id_exists = 1
-        == query_scalar!(
-            r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = ?1);"#,
-            query.user_id
-        )
-        .fetch_one(&pool)
-        .await?;
-    if !user_id_exists {
-        return Err(ServerErr::NoUserId(query.user_id));
+    let message = Message::insert(&db, &limits, user.id, query).await?;
+    message.publish(&db, &send).await?;
+    Ok(Json(message))
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct EditMessageParams {
+    #[param(required = true)]
+    message_id: MessageId,
+    #[param(example = "Hello, world!", required = true)]
+    text: String,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct MessageParams {
+    #[param(required = true)]
+    message_id: MessageId,
+}
+
+#[derive(Serialize, Deserialize, TS, ToSchema, Clone)]
+pub struct BulkDeleteParams {
+    channel_id: ChannelId,
+    /// IDs of messages in the channel. IDs of other messages are ignored.
+    message_ids: Vec<MessageId>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct PurgeParams {
+    #[param(required = true)]
+    channel_id: ChannelId,
+    /// Only delete messages by this user.
+    user_id: Option<UserId>,
+    /// Only delete messages sent after this time.
+    after: Option<DateTime<Utc>>,
+    /// Only delete messages sent before this time.
+    before: Option<DateTime<Utc>>,
+    /// How many of the newest matching messages to delete. Defaults to, and
+    /// can't exceed, the bulk delete limit.
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct HistoryParams {
+    /// Messages older than this one.
+    before: Option<MessageId>,
+    /// Messages newer than this one.
+    after: Option<MessageId>,
+    /// This message and those around it. Set at most one of `before`,
+    /// `after` and `around`; with none, the newest messages are returned.
+    around: Option<MessageId>,
+    /// The thread in the channel to page through. Unset for messages
+    /// outside threads.
+    thread_id: Option<ThreadId>,
+    /// How many messages to return. Can't exceed the history page limit.
+    limit: Option<usize>,
+}
+
+/// Tells viewers of the channel which messages are gone.
+fn send_bulk_delete(send: &Sender, channel: &Channel, message_ids: Vec<MessageId>) {
+    if message_ids.is_empty() {
+        return;
     }
-    let channel_id_exists = 1
-        == query_scalar!(
-            r#"SELECT EXISTS(SELECT 1 FROM channels WHERE id = ?1);"#,
-            query.channel_id
-        )
-        .fetch_one(&pool)
+    if let Err(err) = send.send(Update::MessageBulkDelete {
+        server_id: channel.server_id,
+        channel_id: channel.id,
+        message_ids,
+    }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = EDIT_MESSAGE_PATH,
+    params(EditMessageParams),
+    responses(
+        (status = 200, description = "Change a message's text", body = Message),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not the author", body = String),
+        (status = 500, description = "Internal message error", body = String)
+    )
+)]
+pub async fn edit_message(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<EditMessageParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let message = Message::get(&db, query.message_id)
+        .await?
+        .edit(&db, &limits, user.id, query.text)
         .await?;
-    if !channel_id_exists {
-        return Err(ServerErr::NoChannelId(query.channel_id));
+    if let Err(err) = send.send(Update::MessageEdit(message.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(Json(message))
+}
+
+#[utoipa::path(
+    get,
+    path = MESSAGE_EDITS_PATH,
+    params(MessageParams),
+    responses(
+        (status = 200, description = "List a message's previous versions", body = Vec<MessageEdit>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal message error", body = String)
+    )
+)]
+pub async fn get_message_edits(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MessageParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let message = Message::get(&db, query.message_id).await?;
+    if !MemberAccess::can_view(&db, message.channel_id, user.id).await? {
+        return Err(ServerErr::Forbidden(
+            "Missing permissions: VIEW_CHANNEL".to_string(),
+        ));
+    }
+    let edits = db.get_message_edits(message.id).await?;
+    Ok(Json(edits))
+}
+
+#[utoipa::path(
+    post,
+    path = DELETE_MESSAGE_PATH,
+    params(MessageParams),
+    responses(
+        (status = 200, description = "Delete a message", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not the author and missing MANAGE_MESSAGES", body = String),
+        (status = 500, description = "Internal message error", body = String)
+    )
+)]
+pub async fn delete_message(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MessageParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let message = Message::get(&db, query.message_id).await?;
+    let channel = Channel::get(&db, message.channel_id).await?;
+    let permissions = if message.user_id == user.id {
+        Permissions::VIEW_CHANNEL
+    } else {
+        Permissions::MANAGE_MESSAGES
+    };
+    MemberAccess::require_channel(&db, &channel, user.id, permissions).await?;
+    let deleted = db.delete_message(message.id).await?;
+    if deleted
+        && let Err(err) = send.send(Update::MessageDelete {
+            server_id: channel.server_id,
+            channel_id: channel.id,
+            message_id: message.id,
+        })
+    {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = BULK_DELETE_MESSAGES_PATH,
+    request_body = BulkDeleteParams,
+    responses(
+        (status = 200, description = "Delete a list of messages in a channel, returning the IDs deleted", body = Vec<MessageId>),
+        (status = 400, description = "No IDs or too many IDs", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_MESSAGES", body = String),
+        (status = 500, description = "Internal message error", body = String)
+    )
+)]
+pub async fn bulk_delete_messages(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Json(params): Json<BulkDeleteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let count = params.message_ids.len();
+    if count == 0 || count > limits.bulk_delete_max {
+        return Err(ServerErr::BadRequest(format!(
+            "Can delete 1 to {} messages at once, got {count}",
+            limits.bulk_delete_max
+        )));
+    }
+    let channel = Channel::get(&db, params.channel_id).await?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::MANAGE_MESSAGES).await?;
+    let deleted = db.delete_messages(channel.id, &params.message_ids).await?;
+    send_bulk_delete(&send, &channel, deleted.clone());
+    Ok(Json(deleted))
+}
+
+#[utoipa::path(
+    post,
+    path = PURGE_MESSAGES_PATH,
+    params(PurgeParams),
+    responses(
+        (status = 200, description = "Delete the newest messages in a channel matching the filters, returning the IDs deleted", body = Vec<MessageId>),
+        (status = 400, description = "Limit out of range", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_MESSAGES", body = String),
+        (status = 500, description = "Internal message error", body = String)
+    )
+)]
+pub async fn purge_messages(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<PurgeParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let limit = query.limit.unwrap_or(limits.bulk_delete_max);
+    if limit == 0 || limit > limits.bulk_delete_max {
+        return Err(ServerErr::BadRequest(format!(
+            "limit must be 1 to {}",
+            limits.bulk_delete_max
+        )));
     }
-    let server_id_exists = 1
-        == query_scalar!(
-            r#"SELECT EXISTS(SELECT 1 FROM servers WHERE id = ?1);"#,
-            query.server_id
+    let channel = Channel::get(&db, query.channel_id).await?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::MANAGE_MESSAGES).await?;
+    let deleted = db
+        .purge_messages(
+            channel.id,
+            query.user_id,
+            query.after,
+            query.before,
+            limit as i64,
         )
-        .fetch_one(&pool)
         .await?;
-    if !server_id_exists {
-        return Err(ServerErr::NoServerId(query.server_id));
-    }
-    let message = Message::insert(
-        &pool,
-        query.user_id,
-        query.channel_id,
-        query.server_id,
-        query.text,
+    send_bulk_delete(&send, &channel, deleted.clone());
+    Ok(Json(deleted))
+}
+
+#[utoipa::path(
+    get,
+    path = CHANNEL_MESSAGES_PATH,
+    params(("channel_id" = ChannelId, Path), HistoryParams),
+    responses(
+        (status = 200, description = "Page through a channel's messages, oldest first", body = Vec<Message>),
+        (status = 400, description = "Limit out of range, more than one cursor, or a thread from another channel", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal message error", body = String)
     )
-    .await?;
-    let event = Event::default().json_data(Update::Message(message.clone()))?;
-    let receiver_count = send.receiver_count();
-    tracing::info!("Sending message update to {} SSE clients", receiver_count);
-    if let Err(err) = send.send(event) {
-        tracing::error!("Error sending event: {err:?}");
+)]
+pub async fn get_channel_messages(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    AuthUser(user): AuthUser,
+    Path(channel_id): Path<ChannelId>,
+    Query(query): Query<HistoryParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let limit = query
+        .limit
+        .unwrap_or(HISTORY_PAGE_DEFAULT.min(limits.history_page_max));
+    if limit == 0 || limit > limits.history_page_max {
+        return Err(ServerErr::BadRequest(format!(
+            "limit must be 1 to {}",
+            limits.history_page_max
+        )));
     }
-    Ok(Json(message))
+    let cursor = match (query.before, query.after, query.around) {
+        (None, None, None) => HistoryCursor::Before(MessageId::MAX),
+        (Some(before), None, None) => HistoryCursor::Before(before),
+        (None, Some(after), None) => HistoryCursor::After(after),
+        (None, None, Some(around)) => HistoryCursor::Around(around),
+        _ => {
+            return Err(ServerErr::BadRequest(
+                "Set at most one of before, after and around".to_string(),
+            ));
+        }
+    };
+    let channel = Channel::get(&db, channel_id).await?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::VIEW_CHANNEL).await?;
+    if let Some(thread_id) = query.thread_id
+        && Thread::get(&db, thread_id).await?.channel_id != channel.id
+    {
+        return Err(ServerErr::BadRequest(format!(
+            "Thread ID {thread_id} is not in channel ID {}",
+            channel.id
+        )));
+    }
+    let mut messages =
+        Message::history(&db, channel.id, query.thread_id, cursor, limit as i64).await?;
+    Reaction::attach(&db, messages.iter_mut(), user.id).await?;
+    Attachment::attach(&db, messages.iter_mut()).await?;
+    Ok(Json(messages))
 }
@@ -0,0 +1,271 @@
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    role::{Role, RoleId},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub const CHANNEL_OVERWRITES_PATH: &str = "/channel-overwrites";
+pub const SET_CHANNEL_OVERWRITE_PATH: &str = "/set-channel-overwrite";
+pub const CLEAR_CHANNEL_OVERWRITE_PATH: &str = "/clear-channel-overwrite";
+
+/// Permissions allowed or denied in one channel for a role or a single
+/// member, on top of what their roles grant server-wide. Exactly one of
+/// `role_id` and `user_id` is set.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct ChannelOverwrite {
+    pub channel_id: ChannelId,
+    pub role_id: Option<RoleId>,
+    pub user_id: Option<UserId>,
+    /// `Permissions` bits.
+    #[ts(type = "number")]
+    pub allow: i64,
+    /// `Permissions` bits.
+    #[ts(type = "number")]
+    pub deny: i64,
+}
+
+impl ChannelOverwrite {
+    pub fn allow(&self) -> Permissions {
+        Permissions::from_bits_truncate(self.allow)
+    }
+
+    pub fn deny(&self) -> Permissions {
+        Permissions::from_bits_truncate(self.deny)
+    }
+
+    /// Returns the server's overwrites grouped by channel.
+    pub async fn get_server(
+        db: &Db,
+        server_id: ServerId,
+    ) -> Result<HashMap<ChannelId, Vec<Self>>, ServerErr> {
+        let mut overwrites: HashMap<ChannelId, Vec<Self>> = HashMap::new();
+        for overwrite in db.get_server_overwrites(server_id).await? {
+            overwrites
+                .entry(overwrite.channel_id)
+                .or_default()
+                .push(overwrite);
+        }
+        Ok(overwrites)
+    }
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct ChannelOverwritesParams {
+    #[param(required = true)]
+    channel_id: ChannelId,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct OverwriteParams {
+    #[param(required = true)]
+    channel_id: ChannelId,
+    /// Set this or `user_id`.
+    role_id: Option<RoleId>,
+    /// Set this or `role_id`.
+    user_id: Option<UserId>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct SetOverwriteParams {
+    #[param(required = true)]
+    channel_id: ChannelId,
+    /// Set this or `user_id`.
+    role_id: Option<RoleId>,
+    /// Set this or `role_id`.
+    user_id: Option<UserId>,
+    /// `Permissions` bits to allow. None if unset.
+    allow: Option<i64>,
+    /// `Permissions` bits to deny. None if unset.
+    deny: Option<i64>,
+}
+
+fn parse_permissions(bits: i64) -> Result<Permissions, ServerErr> {
+    Permissions::from_bits(bits)
+        .filter(|permissions| Permissions::CHANNEL.contains(*permissions))
+        .ok_or_else(|| {
+            ServerErr::BadRequest(format!("Bits in {bits} can't be overwritten per channel"))
+        })
+}
+
+/// Looks up the channel and checks the user may manage its overwrites for
+/// the given target. Replacing or clearing the target's overwrite takes
+/// back what it allowed and denied, so the user needs those permissions too.
+async fn managed_channel(
+    db: &Db,
+    user_id: UserId,
+    channel_id: ChannelId,
+    role_id: Option<RoleId>,
+    target_user_id: Option<UserId>,
+) -> Result<(Channel, MemberAccess), ServerErr> {
+    let channel = db
+        .get_channel(channel_id)
+        .await?
+        .ok_or(ServerErr::NoChannelId(channel_id))?;
+    let Some(server_id) = channel.server_id else {
+        return Err(ServerErr::BadRequest(format!(
+            "Channel ID {channel_id} is a DM, which has no overwrites"
+        )));
+    };
+    let access =
+        MemberAccess::require_channel(db, &channel, user_id, Permissions::MANAGE_ROLES).await?;
+    match (role_id, target_user_id) {
+        (Some(role_id), None) => {
+            let role = Role::get(db, role_id).await?;
+            if role.server_id != server_id {
+                return Err(ServerErr::BadRequest(format!(
+                    "Role ID {role_id} is not in server ID {server_id}"
+                )));
+            }
+        }
+        (None, Some(target_user_id)) => {
+            if !db.is_member(server_id, target_user_id).await? {
+                return Err(ServerErr::BadRequest(format!(
+                    "User ID {target_user_id} is not a member of server ID {server_id}"
+                )));
+            }
+        }
+        _ => {
+            return Err(ServerErr::BadRequest(
+                "Set exactly one of role_id and user_id".to_string(),
+            ));
+        }
+    }
+    if let Some(existing) = db
+        .get_overwrites(channel_id)
+        .await?
+        .into_iter()
+        .find(|overwrite| overwrite.role_id == role_id && overwrite.user_id == target_user_id)
+    {
+        access.check_grantable(existing.allow() | existing.deny())?;
+    }
+    Ok((channel, access))
+}
+
+/// Overwrites change who can see the channel, so every member of the server
+/// is told, including those who just lost access.
+async fn send_overwrites(db: &Db, send: &Sender, channel: &Channel) -> Result<(), ServerErr> {
+    // DMs have no overwrites.
+    let Some(server_id) = channel.server_id else {
+        return Ok(());
+    };
+    let overwrites = db.get_overwrites(channel.id).await?;
+    if let Err(err) = send.send(Update::ChannelOverwrites {
+        server_id,
+        channel_id: channel.id,
+        overwrites,
+    }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = CHANNEL_OVERWRITES_PATH,
+    params(ChannelOverwritesParams),
+    responses(
+        (status = 200, description = "List a channel's permission overwrites", body = Vec<ChannelOverwrite>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_channel_overwrites(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ChannelOverwritesParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let channel = db
+        .get_channel(query.channel_id)
+        .await?
+        .ok_or(ServerErr::NoChannelId(query.channel_id))?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::VIEW_CHANNEL).await?;
+    let overwrites = db.get_overwrites(channel.id).await?;
+    Ok(Json(overwrites))
+}
+
+#[utoipa::path(
+    post,
+    path = SET_CHANNEL_OVERWRITE_PATH,
+    params(SetOverwriteParams),
+    responses(
+        (status = 200, description = "Set a role's or member's permissions in a channel", body = ChannelOverwrite),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES in the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn set_channel_overwrite(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<SetOverwriteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (channel, access) =
+        managed_channel(&db, user.id, query.channel_id, query.role_id, query.user_id).await?;
+    let allow = parse_permissions(query.allow.unwrap_or(0))?;
+    let deny = parse_permissions(query.deny.unwrap_or(0))?;
+    if allow.intersects(deny) {
+        return Err(ServerErr::BadRequest(format!(
+            "Permissions both allowed and denied: {}",
+            allow & deny
+        )));
+    }
+    access.check_grantable(allow | deny)?;
+    let overwrite = ChannelOverwrite {
+        channel_id: channel.id,
+        role_id: query.role_id,
+        user_id: query.user_id,
+        allow: allow.bits(),
+        deny: deny.bits(),
+    };
+    db.upsert_overwrite(&overwrite).await?;
+    send_overwrites(&db, &send, &channel).await?;
+    Ok(Json(overwrite))
+}
+
+#[utoipa::path(
+    post,
+    path = CLEAR_CHANNEL_OVERWRITE_PATH,
+    params(OverwriteParams),
+    responses(
+        (status = 200, description = "Remove a role's or member's overwrite in a channel", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES in the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn clear_channel_overwrite(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<OverwriteParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (channel, _access) =
+        managed_channel(&db, user.id, query.channel_id, query.role_id, query.user_id).await?;
+    if db
+        .delete_overwrite(channel.id, query.role_id, query.user_id)
+        .await?
+    {
+        send_overwrites(&db, &send, &channel).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use crate::{
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    overwrite::ChannelOverwrite,
+    role::Role,
+    server::ServerId,
+    storage::Db,
+    user::UserId,
+};
+use bitflags::bitflags;
+use std::fmt;
+
+bitflags! {
+    /// What a member is allowed to do in a server. Roles grant these, and a
+    /// member's permissions are the union of their roles.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Permissions: i64 {
+        const VIEW_CHANNEL = 1 << 0;
+        const SEND_MESSAGES = 1 << 1;
+        const MANAGE_MESSAGES = 1 << 2;
+        const MANAGE_CHANNELS = 1 << 3;
+        const MANAGE_ROLES = 1 << 4;
+        const KICK_MEMBERS = 1 << 5;
+        const BAN_MEMBERS = 1 << 6;
+        const CREATE_INVITE = 1 << 7;
+        const MANAGE_SERVER = 1 << 8;
+        const CONNECT = 1 << 9;
+        /// Checked by clients; the server only relays voice signaling.
+        const SPEAK = 1 << 10;
+        /// Grants every permission.
+        const ADMINISTRATOR = 1 << 11;
+        const ADD_REACTIONS = 1 << 12;
+        /// Mention `@everyone`, `@here` and roles.
+        const MENTION_EVERYONE = 1 << 13;
+    }
+}
+
+impl Permissions {
+    /// What the @everyone role of a new server allows.
+    pub const EVERYONE: Self = Self::VIEW_CHANNEL
+        .union(Self::SEND_MESSAGES)
+        .union(Self::CREATE_INVITE)
+        .union(Self::CONNECT)
+        .union(Self::SPEAK)
+        .union(Self::ADD_REACTIONS);
+
+    /// What the @everyone role of a server without an owner allows. Nobody
+    /// else could create channels there, so everyone can, as in servers from
+    /// before roles.
+    pub const OWNERLESS: Self = Self::EVERYONE.union(Self::MANAGE_CHANNELS);
+
+    /// What every recipient of a DM can do there.
+    pub const DM: Self = Self::VIEW_CHANNEL
+        .union(Self::SEND_MESSAGES)
+        .union(Self::ADD_REACTIONS);
+
+    /// The permissions that channel overwrites can change. The rest only
+    /// make sense server-wide.
+    pub const CHANNEL: Self = Self::VIEW_CHANNEL
+        .union(Self::SEND_MESSAGES)
+        .union(Self::MANAGE_MESSAGES)
+        .union(Self::MANAGE_CHANNELS)
+        .union(Self::MANAGE_ROLES)
+        .union(Self::CREATE_INVITE)
+        .union(Self::CONNECT)
+        .union(Self::SPEAK)
+        .union(Self::ADD_REACTIONS)
+        .union(Self::MENTION_EVERYONE);
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
+/// A member's resolved standing in a server, or in one of its channels.
+/// In a DM, a recipient's standing there.
+#[derive(Clone, Debug)]
+pub struct MemberAccess {
+    /// Unset in DMs.
+    pub server_id: Option<ServerId>,
+    pub user_id: UserId,
+    /// Owns the server, or the group DM.
+    pub owner: bool,
+    /// Server-wide, or with a channel's overwrites applied if resolved for
+    /// a channel.
+    pub permissions: Permissions,
+    /// Position of the member's highest role; 0 if they only have @everyone.
+    pub top_position: i32,
+    /// The member's roles, including @everyone.
+    pub roles: Vec<Role>,
+}
+
+impl MemberAccess {
+    /// Resolves what the user can do in the server. Every handler that acts
+    /// on a server goes through this, so the rules live in one place.
+    pub async fn resolve(db: &Db, server_id: ServerId, user_id: UserId) -> Result<Self, ServerErr> {
+        let server = db
+            .get_server(server_id)
+            .await?
+            .ok_or(ServerErr::NoServerId(server_id))?;
+        if !db.is_member(server_id, user_id).await? {
+            return Err(ServerErr::NotMember(server_id));
+        }
+        let roles = db.get_member_roles(server_id, user_id).await?;
+        let owner = server.owner_id == Some(user_id);
+        let mut permissions = roles
+            .iter()
+            .fold(Permissions::empty(), |acc, role| acc | role.permissions());
+        if owner || permissions.contains(Permissions::ADMINISTRATOR) {
+            permissions = Permissions::all();
+        }
+        let top_position = roles.iter().map(|role| role.position).max().unwrap_or(0);
+        Ok(Self {
+            server_id: Some(server_id),
+            user_id,
+            owner,
+            permissions,
+            top_position,
+            roles,
+        })
+    }
+
+    /// Resolves what the user can do in the channel, with its overwrites
+    /// applied. DMs have no overwrites; recipients get the DM permissions.
+    pub async fn resolve_channel(
+        db: &Db,
+        channel: &Channel,
+        user_id: UserId,
+    ) -> Result<Self, ServerErr> {
+        let Some(server_id) = channel.server_id else {
+            if !db.is_recipient(channel.id, user_id).await? {
+                return Err(ServerErr::NotRecipient(channel.id));
+            }
+            return Ok(Self {
+                server_id: None,
+                user_id,
+                owner: channel.owner_id == Some(user_id),
+                permissions: Permissions::DM,
+                top_position: 0,
+                roles: Vec::new(),
+            });
+        };
+        let mut access = Self::resolve(db, server_id, user_id).await?;
+        let overwrites = db.get_overwrites(channel.id).await?;
+        access.permissions = access.channel_permissions(&overwrites);
+        Ok(access)
+    }
+
+    /// Resolves the user's access to the channel and rejects them unless they
+    /// have all of `permissions` there.
+    pub async fn require_channel(
+        db: &Db,
+        channel: &Channel,
+        user_id: UserId,
+        permissions: Permissions,
+    ) -> Result<Self, ServerErr> {
+        let access = Self::resolve_channel(db, channel, user_id).await?;
+        access.check(permissions)?;
+        Ok(access)
+    }
+
+    /// Whether the user can see the channel. Missing channels, and servers
+    /// and DMs the user isn't in, count as not visible rather than errors.
+    pub async fn can_view(
+        db: &Db,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let Some(channel) = db.get_channel(channel_id).await? else {
+            return Ok(false);
+        };
+        match Self::resolve_channel(db, &channel, user_id).await {
+            Ok(access) => Ok(access.permissions.contains(Permissions::VIEW_CHANNEL)),
+            Err(
+                ServerErr::NotMember(_) | ServerErr::NoServerId(_) | ServerErr::NotRecipient(_),
+            ) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// IDs of the server's channels the member can view. None outside a
+    /// server.
+    pub async fn visible_channels(&self, db: &Db) -> Result<Vec<ChannelId>, ServerErr> {
+        let Some(server_id) = self.server_id else {
+            return Ok(Vec::new());
+        };
+        let overwrites = ChannelOverwrite::get_server(db, server_id).await?;
+        let channels = db.get_server_channels(server_id).await?;
+        Ok(channels
+            .into_iter()
+            .filter(|channel| {
+                let overwrites = overwrites.get(&channel.id).map_or(&[][..], Vec::as_slice);
+                self.channel_permissions(overwrites)
+                    .contains(Permissions::VIEW_CHANNEL)
+            })
+            .map(|channel| channel.id)
+            .collect())
+    }
+
+    /// Layers a channel's overwrites over the member's server permissions:
+    /// first @everyone's, then the rest of the member's roles together, then
+    /// the member's own. Denies are applied before allows at each step.
+    pub fn channel_permissions(&self, overwrites: &[ChannelOverwrite]) -> Permissions {
+        if self.permissions.contains(Permissions::ADMINISTRATOR) {
+            return self.permissions;
+        }
+        let apply = |permissions: Permissions, overwrite: &ChannelOverwrite| {
+            permissions
+                .difference(overwrite.deny())
+                .union(overwrite.allow())
+        };
+        let everyone = self.roles.iter().find(|role| role.is_everyone());
+        let mut permissions = self.permissions;
+        if let Some(overwrite) = overwrites.iter().find(|overwrite| {
+            overwrite.role_id.is_some() && overwrite.role_id == everyone.map(|role| role.id)
+        }) {
+            permissions = apply(permissions, overwrite);
+        }
+        let (allow, deny) = overwrites
+            .iter()
+            .filter(|overwrite| {
+                overwrite.role_id.is_some_and(|role_id| {
+                    self.roles
+                        .iter()
+                        .any(|role| role.id == role_id && !role.is_everyone())
+                })
+            })
+            .fold(
+                (Permissions::empty(), Permissions::empty()),
+                |(allow, deny), overwrite| (allow | overwrite.allow(), deny | overwrite.deny()),
+            );
+        permissions = permissions.difference(deny).union(allow);
+        if let Some(overwrite) = overwrites
+            .iter()
+            .find(|overwrite| overwrite.user_id == Some(self.user_id))
+        {
+            permissions = apply(permissions, overwrite);
+        }
+        // Nothing else in a channel is usable without seeing it.
+        if !permissions.contains(Permissions::VIEW_CHANNEL) {
+            permissions = permissions.difference(Permissions::CHANNEL);
+        }
+        permissions
+    }
+
+    /// Resolves the user's access and rejects them unless they have all of
+    /// `permissions`.
+    pub async fn require(
+        db: &Db,
+        server_id: ServerId,
+        user_id: UserId,
+        permissions: Permissions,
+    ) -> Result<Self, ServerErr> {
+        let access = Self::resolve(db, server_id, user_id).await?;
+        access.check(permissions)?;
+        Ok(access)
+    }
+
+    pub fn check(&self, permissions: Permissions) -> Result<(), ServerErr> {
+        let missing = permissions.difference(self.permissions);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(ServerErr::Forbidden(format!(
+                "Missing permissions: {missing}"
+            )))
+        }
+    }
+
+    /// Roles can only be managed by members ranked above them.
+    pub fn check_outranks(&self, position: i32) -> Result<(), ServerErr> {
+        if self.owner || self.top_position > position {
+            Ok(())
+        } else {
+            Err(ServerErr::Forbidden(
+                "Role is not below your highest role".to_string(),
+            ))
+        }
+    }
+
+    /// Members can only hand out permissions they have themselves.
+    pub fn check_grantable(&self, permissions: Permissions) -> Result<(), ServerErr> {
+        let missing = permissions.difference(self.permissions);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(ServerErr::Forbidden(format!(
+                "Can't grant permissions you don't have: {missing}"
+            )))
+        }
+    }
+
+    /// Moderation (kicks, bans) only works on members ranked below you.
+    pub fn check_outranks_member(&self, target: &Self) -> Result<(), ServerErr> {
+        if target.owner || !(self.owner || self.top_position > target.top_position) {
+            Err(ServerErr::Forbidden(
+                "Member is not below your highest role".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
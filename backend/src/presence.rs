@@ -0,0 +1,84 @@
+use crate::{snapshot::Update, user::UserId, Sender};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub enum PresenceStatus {
+    Online,
+    Idle,
+    DoNotDisturb,
+    /// Not connected to the gateway. Can't be chosen.
+    Offline,
+}
+
+/// Who is connected to the gateway, and the status they chose. Users are
+/// online from their first connection until their last one closes.
+#[derive(Clone, Default)]
+pub struct PresenceState {
+    users: Arc<RwLock<HashMap<UserId, Presence>>>,
+}
+
+struct Presence {
+    status: PresenceStatus,
+    connections: usize,
+}
+
+impl PresenceState {
+    pub async fn connect(&self, send: &Sender, user_id: UserId) {
+        let mut users = self.users.write().await;
+        let presence = users.entry(user_id).or_insert(Presence {
+            status: PresenceStatus::Online,
+            connections: 0,
+        });
+        presence.connections += 1;
+        if presence.connections == 1 {
+            send_presence(send, user_id, presence.status);
+        }
+    }
+
+    pub async fn disconnect(&self, send: &Sender, user_id: UserId) {
+        let mut users = self.users.write().await;
+        let Some(presence) = users.get_mut(&user_id) else {
+            return;
+        };
+        presence.connections -= 1;
+        if presence.connections == 0 {
+            users.remove(&user_id);
+            send_presence(send, user_id, PresenceStatus::Offline);
+        }
+    }
+
+    /// Changes the status of a connected user. Going offline is left to
+    /// disconnecting.
+    pub async fn set(&self, send: &Sender, user_id: UserId, status: PresenceStatus) {
+        if status == PresenceStatus::Offline {
+            return;
+        }
+        let mut users = self.users.write().await;
+        if let Some(presence) = users.get_mut(&user_id)
+            && presence.status != status
+        {
+            presence.status = status;
+            send_presence(send, user_id, status);
+        }
+    }
+
+    /// Everyone who isn't offline.
+    pub async fn all(&self) -> HashMap<UserId, PresenceStatus> {
+        let users = self.users.read().await;
+        users
+            .iter()
+            .map(|(user_id, presence)| (*user_id, presence.status))
+            .collect()
+    }
+}
+
+fn send_presence(send: &Sender, user_id: UserId, status: PresenceStatus) {
+    if let Err(err) = send.send(Update::Presence { user_id, status }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    emoji::{Emoji, EmojiId},
+    error::ServerErr,
+    message::{Message, MessageId},
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+/// Longest Unicode emoji accepted, in bytes. Family and flag sequences run
+/// to about 30.
+pub const REACTION_EMOJI_MAX_LEN: usize = 64;
+pub const ADD_REACTION_PATH: &str = "/add-reaction";
+pub const REMOVE_REACTION_PATH: &str = "/remove-reaction";
+
+/// One user's reaction, as sent in updates. Exactly one of `emoji` and
+/// `emoji_id` is set.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Reaction {
+    pub message_id: MessageId,
+    pub channel_id: ChannelId,
+    /// Unset in DMs.
+    pub server_id: Option<ServerId>,
+    pub user_id: UserId,
+    /// A Unicode emoji.
+    pub emoji: Option<String>,
+    /// One of the server's custom emoji.
+    pub emoji_id: Option<EmojiId>,
+}
+
+/// How many users reacted to a message with one emoji.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct ReactionCount {
+    pub emoji: Option<String>,
+    pub emoji_id: Option<EmojiId>,
+    #[ts(type = "number")]
+    pub count: i64,
+    /// Whether the requesting user is one of them.
+    pub me: bool,
+}
+
+impl Reaction {
+    /// Fills in each message's reaction counts as seen by the user.
+    pub async fn attach<'a>(
+        db: &Db,
+        messages: impl IntoIterator<Item = &'a mut Message>,
+        user_id: UserId,
+    ) -> Result<(), ServerErr> {
+        let messages: Vec<&mut Message> = messages.into_iter().collect();
+        let ids: Vec<MessageId> = messages.iter().map(|message| message.id).collect();
+        let mut counts: HashMap<MessageId, Vec<ReactionCount>> = HashMap::new();
+        for (message_id, count) in db.get_reaction_counts(&ids, user_id).await? {
+            counts.entry(message_id).or_default().push(count);
+        }
+        for message in messages {
+            message.reactions = counts.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+}
+
+/// Whether the text is a single emoji, including skin tone, flag, keycap
+/// and joined sequences.
+pub fn is_emoji(text: &str) -> bool {
+    if text.is_empty() || text.len() > REACTION_EMOJI_MAX_LEN {
+        return false;
+    }
+    text.split('\u{200D}').all(|part| {
+        let mut chars = part.chars();
+        let Some(first) = chars.next() else {
+            return false;
+        };
+        let rest: Vec<char> = chars.collect();
+        if matches!(first, '0'..='9' | '#' | '*') {
+            return matches!(rest.as_slice(), ['\u{20E3}'] | ['\u{FE0F}', '\u{20E3}']);
+        }
+        is_pictographic(first) && rest.into_iter().all(is_emoji_modifier)
+    })
+}
+
+fn is_pictographic(c: char) -> bool {
+    matches!(c,
+        '\u{00A9}' | '\u{00AE}' | '\u{203C}' | '\u{2049}' | '\u{2122}' | '\u{2139}'
+        | '\u{2194}'..='\u{2199}' | '\u{21A9}'..='\u{21AA}' | '\u{231A}'..='\u{231B}'
+        | '\u{2328}' | '\u{23CF}' | '\u{23E9}'..='\u{23F3}' | '\u{23F8}'..='\u{23FA}'
+        | '\u{24C2}' | '\u{25AA}'..='\u{25AB}' | '\u{25B6}' | '\u{25C0}'
+        | '\u{25FB}'..='\u{25FE}' | '\u{2600}'..='\u{27BF}' | '\u{2934}'..='\u{2935}'
+        | '\u{2B05}'..='\u{2B07}' | '\u{2B1B}'..='\u{2B1C}' | '\u{2B50}' | '\u{2B55}'
+        | '\u{3030}' | '\u{303D}' | '\u{3297}' | '\u{3299}' | '\u{1F000}'..='\u{1FAFF}')
+}
+
+/// Characters that only change the emoji before them: variation selectors,
+/// skin tones, the second half of a flag and subdivision flag tags.
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c,
+        '\u{FE0E}' | '\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}' | '\u{1F1E6}'..='\u{1F1FF}'
+        | '\u{E0020}'..='\u{E007F}')
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct ReactionParams {
+    #[param(required = true)]
+    message_id: MessageId,
+    /// A Unicode emoji. Set this or `emoji_id`.
+    emoji: Option<String>,
+    /// A custom emoji of the message's server. Set this or `emoji`.
+    emoji_id: Option<EmojiId>,
+    /// Whose reaction to remove. Defaults to your own; removing someone
+    /// else's needs MANAGE_MESSAGES.
+    user_id: Option<UserId>,
+}
+
+/// Looks up the message and its channel and checks the emoji can be used
+/// there.
+async fn reacted_message(
+    db: &Db,
+    params: &ReactionParams,
+) -> Result<(Message, Channel), ServerErr> {
+    let message = Message::get(db, params.message_id).await?;
+    let channel = Channel::get(db, message.channel_id).await?;
+    match (&params.emoji, params.emoji_id) {
+        (Some(emoji), None) => {
+            if !is_emoji(emoji) {
+                return Err(ServerErr::BadRequest(format!("{emoji} is not an emoji")));
+            }
+        }
+        (None, Some(emoji_id)) => {
+            let emoji = Emoji::get(db, emoji_id).await?;
+            if Some(emoji.server_id) != channel.server_id {
+                return Err(ServerErr::BadRequest(format!(
+                    "Emoji ID {emoji_id} is not from channel ID {}'s server",
+                    channel.id
+                )));
+            }
+        }
+        _ => {
+            return Err(ServerErr::BadRequest(
+                "Set exactly one of emoji and emoji_id".to_string(),
+            ));
+        }
+    }
+    Ok((message, channel))
+}
+
+#[utoipa::path(
+    post,
+    path = ADD_REACTION_PATH,
+    params(ReactionParams),
+    responses(
+        (status = 200, description = "React to a message", body = ()),
+        (status = 400, description = "Not an emoji, or a custom emoji from another server", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing ADD_REACTIONS", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn add_reaction(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ReactionParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    if query.user_id.is_some_and(|user_id| user_id != user.id) {
+        return Err(ServerErr::BadRequest(
+            "Can only add your own reactions".to_string(),
+        ));
+    }
+    let (message, channel) = reacted_message(&db, &query).await?;
+    MemberAccess::require_channel(
+        &db,
+        &channel,
+        user.id,
+        Permissions::VIEW_CHANNEL | Permissions::ADD_REACTIONS,
+    )
+    .await?;
+    let added = db
+        .insert_reaction(
+            message.id,
+            user.id,
+            query.emoji.as_deref(),
+            query.emoji_id,
+            Utc::now(),
+        )
+        .await?;
+    if added
+        && let Err(err) = send.send(Update::ReactionAdd(Reaction {
+            message_id: message.id,
+            channel_id: channel.id,
+            server_id: channel.server_id,
+            user_id: user.id,
+            emoji: query.emoji,
+            emoji_id: query.emoji_id,
+        }))
+    {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = REMOVE_REACTION_PATH,
+    params(ReactionParams),
+    responses(
+        (status = 200, description = "Remove a reaction from a message", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Someone else's reaction and missing MANAGE_MESSAGES", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn remove_reaction(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ReactionParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (message, channel) = reacted_message(&db, &query).await?;
+    let user_id = query.user_id.unwrap_or(user.id);
+    let permissions = if user_id == user.id {
+        Permissions::VIEW_CHANNEL
+    } else {
+        Permissions::MANAGE_MESSAGES
+    };
+    MemberAccess::require_channel(&db, &channel, user.id, permissions).await?;
+    let removed = db
+        .delete_reaction(message.id, user_id, query.emoji.as_deref(), query.emoji_id)
+        .await?;
+    if removed
+        && let Err(err) = send.send(Update::ReactionRemove(Reaction {
+            message_id: message.id,
+            channel_id: channel.id,
+            server_id: channel.server_id,
+            user_id,
+            emoji: query.emoji,
+            emoji_id: query.emoji_id,
+        }))
+    {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
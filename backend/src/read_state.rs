@@ -0,0 +1,116 @@
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    message::{Message, MessageId},
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub const ACK_PATH: &str = "/ack";
+
+/// How far a user has read a channel.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct ReadState {
+    pub user_id: UserId,
+    pub channel_id: ChannelId,
+    /// Unset for DMs.
+    pub server_id: Option<ServerId>,
+    /// Unset if the user never read the channel.
+    pub last_read_message_id: Option<MessageId>,
+    /// Messages after the last read one, threads included.
+    #[ts(type = "number")]
+    pub unread_count: i64,
+    /// Unread messages mentioning the user, directly, by role or with
+    /// `@everyone`.
+    #[ts(type = "number")]
+    pub mention_count: i64,
+}
+
+/// Unread totals over a server's channels.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct UnreadCounts {
+    #[ts(type = "number")]
+    pub unread_count: i64,
+    #[ts(type = "number")]
+    pub mention_count: i64,
+}
+
+impl ReadState {
+    /// Counts a new message towards the read states of those it mentions.
+    /// The author has read their own message.
+    pub async fn record_message(db: &Db, message: &Message) -> Result<(), ServerErr> {
+        let mentions = &message.mentions;
+        if mentions.everyone || !mentions.users.is_empty() || !mentions.roles.is_empty() {
+            db.add_mention_counts(message).await?;
+        }
+        db.ack_channel(message.user_id, message.channel_id, message.id)
+            .await?;
+        Ok(())
+    }
+
+    /// The user's read states for the channels, one per channel.
+    pub async fn get_channels(
+        db: &Db,
+        user_id: UserId,
+        channel_ids: &[ChannelId],
+    ) -> Result<Vec<Self>, ServerErr> {
+        db.get_read_states(user_id, channel_ids).await
+    }
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct AckParams {
+    /// The newest message read. Acking an older message than before leaves
+    /// the read state as it was.
+    #[param(required = true)]
+    message_id: MessageId,
+}
+
+#[utoipa::path(
+    post,
+    path = ACK_PATH,
+    params(AckParams),
+    responses(
+        (status = 200, description = "Mark a channel read up to a message", body = ReadState),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn ack(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<AckParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let message = Message::get(&db, query.message_id).await?;
+    let channel = Channel::get(&db, message.channel_id).await?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::VIEW_CHANNEL).await?;
+    db.ack_channel(user.id, channel.id, message.id).await?;
+    let read_state = ReadState::get_channels(&db, user.id, &[channel.id])
+        .await?
+        .pop()
+        .ok_or(ServerErr::NoChannelId(channel.id))?;
+    // Only sent to the user's own connections, so their other tabs and
+    // devices catch up.
+    if let Err(err) = send.send(Update::ReadStateUpdate(read_state.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(Json(read_state))
+}
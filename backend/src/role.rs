@@ -0,0 +1,451 @@
+use crate::{
+    auth::AuthUser,
+    config::Limits,
+    error::ServerErr,
+    member::ServerMember,
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub type RoleId = i32;
+
+pub const ROLE_NAME_MAX_LEN: usize = 32;
+pub const EVERYONE_ROLE_NAME: &str = "@everyone";
+pub const ROLES_PATH: &str = "/roles";
+pub const CREATE_ROLE_PATH: &str = "/create-role";
+pub const UPDATE_ROLE_PATH: &str = "/update-role";
+pub const MOVE_ROLE_PATH: &str = "/move-role";
+pub const DELETE_ROLE_PATH: &str = "/delete-role";
+pub const MEMBER_ROLES_PATH: &str = "/member-roles";
+pub const ADD_MEMBER_ROLE_PATH: &str = "/add-member-role";
+pub const REMOVE_MEMBER_ROLE_PATH: &str = "/remove-member-role";
+
+/// A named set of permissions in a server. Position 0 is the server's
+/// @everyone role; higher positions outrank lower ones.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Role {
+    pub id: RoleId,
+    pub server_id: ServerId,
+    pub name: String,
+    /// `Permissions` bits.
+    #[ts(type = "number")]
+    pub permissions: i64,
+    pub position: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct MemberRole {
+    pub server_id: ServerId,
+    pub user_id: UserId,
+    pub role_id: RoleId,
+}
+
+impl Role {
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from_bits_truncate(self.permissions)
+    }
+
+    pub fn is_everyone(&self) -> bool {
+        self.position == 0
+    }
+
+    /// Creates the @everyone role every new server starts with.
+    pub async fn insert_everyone(
+        db: &Db,
+        server_id: ServerId,
+        permissions: Permissions,
+    ) -> Result<Self, ServerErr> {
+        let name = EVERYONE_ROLE_NAME.to_string();
+        let permissions = permissions.bits();
+        let id = db.insert_role(server_id, &name, permissions, 0).await?;
+        Ok(Self {
+            id,
+            server_id,
+            name,
+            permissions,
+            position: 0,
+        })
+    }
+
+    /// Creates a role just above @everyone.
+    pub async fn insert(
+        db: &Db,
+        limits: &Limits,
+        server_id: ServerId,
+        name: String,
+        permissions: Permissions,
+    ) -> Result<Self, ServerErr> {
+        validate_name(limits, &name)?;
+        let permissions = permissions.bits();
+        let id = db.insert_role(server_id, &name, permissions, 1).await?;
+        Ok(Self {
+            id,
+            server_id,
+            name,
+            permissions,
+            position: 1,
+        })
+    }
+
+    pub async fn get(db: &Db, role_id: RoleId) -> Result<Self, ServerErr> {
+        db.get_role(role_id)
+            .await?
+            .ok_or(ServerErr::NoRoleId(role_id))
+    }
+}
+
+fn validate_name(limits: &Limits, name: &str) -> Result<(), ServerErr> {
+    let len = name.len();
+    if len > limits.role_name_max_len {
+        Err(ServerErr::RoleNameTooLong(len, limits.role_name_max_len))
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_permissions(bits: i64) -> Result<Permissions, ServerErr> {
+    Permissions::from_bits(bits)
+        .ok_or_else(|| ServerErr::BadRequest(format!("Unknown permission bits in {bits}")))
+}
+
+/// Positions shift whenever a role is added, moved or removed, so the whole
+/// list is sent rather than the one role that changed.
+async fn send_roles(db: &Db, send: &Sender, server_id: ServerId) -> Result<(), ServerErr> {
+    let roles = db.get_roles(server_id).await?;
+    if let Err(err) = send.send(Update::Roles { server_id, roles }) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct RolesParams {
+    #[param(required = true)]
+    server_id: ServerId,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct CreateRoleParams {
+    #[param(required = true)]
+    server_id: ServerId,
+    #[param(example = "Moderator", required = true)]
+    name: String,
+    /// `Permissions` bits. No permissions if unset.
+    permissions: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct UpdateRoleParams {
+    #[param(required = true)]
+    role_id: RoleId,
+    name: Option<String>,
+    /// `Permissions` bits.
+    permissions: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct MoveRoleParams {
+    #[param(required = true)]
+    role_id: RoleId,
+    #[param(required = true)]
+    position: i32,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct RoleParams {
+    #[param(required = true)]
+    role_id: RoleId,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct MemberRoleParams {
+    #[param(required = true)]
+    role_id: RoleId,
+    #[param(required = true)]
+    user_id: UserId,
+}
+
+#[utoipa::path(
+    get,
+    path = ROLES_PATH,
+    params(RolesParams),
+    responses(
+        (status = 200, description = "List a server's roles, lowest first", body = Vec<Role>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a member of the server", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_roles(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<RolesParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    ServerMember::check(&db, query.server_id, user.id).await?;
+    let roles = db.get_roles(query.server_id).await?;
+    Ok(Json(roles))
+}
+
+#[utoipa::path(
+    post,
+    path = CREATE_ROLE_PATH,
+    params(CreateRoleParams),
+    responses(
+        (status = 200, description = "Create a role just above @everyone", body = Role),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn create_role(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<CreateRoleParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let access =
+        MemberAccess::require(&db, query.server_id, user.id, Permissions::MANAGE_ROLES).await?;
+    let permissions = parse_permissions(query.permissions.unwrap_or(0))?;
+    access.check_grantable(permissions)?;
+    let role = Role::insert(&db, &limits, query.server_id, query.name, permissions).await?;
+    send_roles(&db, &send, role.server_id).await?;
+    Ok(Json(role))
+}
+
+#[utoipa::path(
+    post,
+    path = UPDATE_ROLE_PATH,
+    params(UpdateRoleParams),
+    responses(
+        (status = 200, description = "Rename a role or change its permissions", body = Role),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES or role is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn update_role(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<UpdateRoleParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let mut role = Role::get(&db, query.role_id).await?;
+    let access =
+        MemberAccess::require(&db, role.server_id, user.id, Permissions::MANAGE_ROLES).await?;
+    access.check_outranks(role.position)?;
+    if let Some(name) = query.name {
+        if role.is_everyone() {
+            return Err(ServerErr::BadRequest(
+                "The @everyone role can't be renamed".to_string(),
+            ));
+        }
+        validate_name(&limits, &name)?;
+        role.name = name;
+    }
+    if let Some(bits) = query.permissions {
+        let permissions = parse_permissions(bits)?;
+        // Only newly added permissions need to be grantable; keeping ones the
+        // role already had is fine.
+        access.check_grantable(permissions.difference(role.permissions()))?;
+        role.permissions = permissions.bits();
+    }
+    db.update_role(role.id, &role.name, role.permissions)
+        .await?;
+    send_roles(&db, &send, role.server_id).await?;
+    Ok(Json(role))
+}
+
+#[utoipa::path(
+    post,
+    path = MOVE_ROLE_PATH,
+    params(MoveRoleParams),
+    responses(
+        (status = 200, description = "Move a role to a new position", body = Vec<Role>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES or role is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn move_role(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MoveRoleParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let role = Role::get(&db, query.role_id).await?;
+    let access =
+        MemberAccess::require(&db, role.server_id, user.id, Permissions::MANAGE_ROLES).await?;
+    if role.is_everyone() {
+        return Err(ServerErr::BadRequest(
+            "The @everyone role can't be moved".to_string(),
+        ));
+    }
+    let top = db.get_roles(role.server_id).await?.len() as i32 - 1;
+    if !(1..=top).contains(&query.position) {
+        return Err(ServerErr::BadRequest(format!(
+            "Position must be between 1 and {top}"
+        )));
+    }
+    access.check_outranks(role.position)?;
+    access.check_outranks(query.position)?;
+    db.move_role(role.server_id, role.id, role.position, query.position)
+        .await?;
+    send_roles(&db, &send, role.server_id).await?;
+    let roles = db.get_roles(role.server_id).await?;
+    Ok(Json(roles))
+}
+
+#[utoipa::path(
+    post,
+    path = DELETE_ROLE_PATH,
+    params(RoleParams),
+    responses(
+        (status = 200, description = "Delete a role", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES or role is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn delete_role(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<RoleParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let role = Role::get(&db, query.role_id).await?;
+    let access =
+        MemberAccess::require(&db, role.server_id, user.id, Permissions::MANAGE_ROLES).await?;
+    if role.is_everyone() {
+        return Err(ServerErr::BadRequest(
+            "The @everyone role can't be deleted".to_string(),
+        ));
+    }
+    access.check_outranks(role.position)?;
+    db.delete_role(role.server_id, role.id, role.position)
+        .await?;
+    send_roles(&db, &send, role.server_id).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = MEMBER_ROLES_PATH,
+    params(RolesParams),
+    responses(
+        (status = 200, description = "List which members have which roles", body = Vec<MemberRole>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not a member of the server", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_member_roles(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<RolesParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    ServerMember::check(&db, query.server_id, user.id).await?;
+    let member_roles = db.get_all_member_roles(query.server_id).await?;
+    Ok(Json(member_roles))
+}
+
+/// Looks up the role and checks the user may hand it to the member.
+async fn assignable_role(
+    db: &Db,
+    user_id: UserId,
+    query: &MemberRoleParams,
+) -> Result<MemberRole, ServerErr> {
+    let role = Role::get(db, query.role_id).await?;
+    let access =
+        MemberAccess::require(db, role.server_id, user_id, Permissions::MANAGE_ROLES).await?;
+    if role.is_everyone() {
+        return Err(ServerErr::BadRequest(
+            "Every member has the @everyone role".to_string(),
+        ));
+    }
+    access.check_outranks(role.position)?;
+    ServerMember::check(db, role.server_id, query.user_id).await?;
+    Ok(MemberRole {
+        server_id: role.server_id,
+        user_id: query.user_id,
+        role_id: role.id,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = ADD_MEMBER_ROLE_PATH,
+    params(MemberRoleParams),
+    responses(
+        (status = 200, description = "Give a member a role", body = MemberRole),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES or role is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn add_member_role(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MemberRoleParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let member_role = assignable_role(&db, user.id, &query).await?;
+    let added = db
+        .insert_member_role(
+            member_role.server_id,
+            member_role.user_id,
+            member_role.role_id,
+        )
+        .await?;
+    if added && let Err(err) = send.send(Update::MemberRoleAdd(member_role.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(Json(member_role))
+}
+
+#[utoipa::path(
+    post,
+    path = REMOVE_MEMBER_ROLE_PATH,
+    params(MemberRoleParams),
+    responses(
+        (status = 200, description = "Take a role from a member", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_ROLES or role is too high", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn remove_member_role(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<MemberRoleParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let member_role = assignable_role(&db, user.id, &query).await?;
+    let removed = db
+        .delete_member_role(
+            member_role.server_id,
+            member_role.user_id,
+            member_role.role_id,
+        )
+        .await?;
+    if removed && let Err(err) = send.send(Update::MemberRoleRemove(member_role)) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(())
+}
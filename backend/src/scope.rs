@@ -0,0 +1,163 @@
+use crate::{
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    member::ServerMember,
+    message::Message,
+    permission::MemberAccess,
+    reaction::Reaction,
+    read_state::ReadState,
+    role::MemberRole,
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    thread::Thread,
+    user::UserId,
+};
+use std::collections::{HashMap, HashSet};
+
+/// What one subscriber to the updates can see: the servers they're a member
+/// of and the channels they can view, DMs included. It's read when they
+/// subscribe and kept up to date from the updates that change it, so most
+/// updates are checked without going to the database.
+pub struct UpdateScope {
+    user_id: UserId,
+    servers: HashSet<ServerId>,
+    /// With the channel's server, which DMs don't have.
+    channels: HashMap<ChannelId, Option<ServerId>>,
+}
+
+impl UpdateScope {
+    pub async fn new(db: &Db, user_id: UserId) -> Result<Self, ServerErr> {
+        let mut scope = Self {
+            user_id,
+            servers: HashSet::new(),
+            channels: HashMap::new(),
+        };
+        for server_id in db.get_member_servers(user_id).await? {
+            scope.load_server(db, server_id).await?;
+        }
+        for dm in db.get_dm_channels(user_id).await? {
+            scope.channels.insert(dm.id, None);
+        }
+        Ok(scope)
+    }
+
+    /// Whether the update is for this subscriber. Updates that change what
+    /// they can see are applied first, so a member who joins a server gets
+    /// its updates from the join on, and one who leaves still hears about
+    /// it. User and presence updates go to everyone, like the users in the
+    /// snapshot.
+    pub async fn admit(&mut self, db: &Db, update: &Update) -> Result<bool, ServerErr> {
+        let me = self.user_id;
+        match update {
+            Update::User(_) | Update::Presence { .. } => Ok(true),
+            Update::Server(server) => {
+                // New servers arrive before their creator's join.
+                if !self.servers.contains(&server.id) {
+                    self.load_server(db, server.id).await?;
+                }
+                Ok(self.servers.contains(&server.id))
+            }
+            Update::MemberJoin(ServerMember {
+                server_id, user_id, ..
+            })
+            | Update::MemberRoleAdd(MemberRole {
+                server_id, user_id, ..
+            })
+            | Update::MemberRoleRemove(MemberRole {
+                server_id, user_id, ..
+            }) if *user_id == me => {
+                self.load_server(db, *server_id).await?;
+                Ok(self.servers.contains(server_id))
+            }
+            Update::MemberLeave { server_id, user_id } if *user_id == me => {
+                self.drop_server(*server_id);
+                Ok(true)
+            }
+            Update::Roles { server_id, .. } | Update::ChannelOverwrites { server_id, .. }
+                if self.servers.contains(server_id) =>
+            {
+                self.load_server(db, *server_id).await?;
+                Ok(true)
+            }
+            Update::MemberJoin(ServerMember { server_id, .. })
+            | Update::MemberLeave { server_id, .. }
+            | Update::MemberRoleAdd(MemberRole { server_id, .. })
+            | Update::MemberRoleRemove(MemberRole { server_id, .. })
+            | Update::Roles { server_id, .. }
+            | Update::ChannelOverwrites { server_id, .. }
+            | Update::Emojis { server_id, .. } => Ok(self.servers.contains(server_id)),
+            Update::Channel(channel) => self.admit_channel(db, channel).await,
+            Update::RecipientAdd {
+                channel_id,
+                user_id,
+            } if *user_id == me => {
+                self.channels.insert(*channel_id, None);
+                Ok(true)
+            }
+            Update::RecipientRemove {
+                channel_id,
+                user_id,
+            } if *user_id == me => {
+                self.channels.remove(channel_id);
+                Ok(true)
+            }
+            Update::ReadStateUpdate(ReadState { user_id, .. }) => Ok(*user_id == me),
+            Update::Message(Message { channel_id, .. })
+            | Update::MessageEdit(Message { channel_id, .. })
+            | Update::MessageDelete { channel_id, .. }
+            | Update::MessageBulkDelete { channel_id, .. }
+            | Update::AttachmentUpdate { channel_id, .. }
+            | Update::ReactionAdd(Reaction { channel_id, .. })
+            | Update::ReactionRemove(Reaction { channel_id, .. })
+            | Update::ThreadCreate(Thread { channel_id, .. })
+            | Update::ThreadUpdate(Thread { channel_id, .. })
+            | Update::Typing { channel_id, .. }
+            | Update::RecipientAdd { channel_id, .. }
+            | Update::RecipientRemove { channel_id, .. }
+            | Update::VoiceJoin { channel_id, .. }
+            | Update::VoiceLeave { channel_id, .. } => Ok(self.channels.contains_key(channel_id)),
+        }
+    }
+
+    /// Channels are new or changed group DMs. New ones are looked up if
+    /// they're in one of the subscriber's servers, or a DM they could be in.
+    async fn admit_channel(&mut self, db: &Db, channel: &Channel) -> Result<bool, ServerErr> {
+        if self.channels.contains_key(&channel.id) {
+            return Ok(true);
+        }
+        if channel
+            .server_id
+            .is_some_and(|server_id| !self.servers.contains(&server_id))
+        {
+            return Ok(false);
+        }
+        let visible = MemberAccess::can_view(db, channel.id, self.user_id).await?;
+        if visible {
+            self.channels.insert(channel.id, channel.server_id);
+        }
+        Ok(visible)
+    }
+
+    /// Reads the subscriber's membership of the server again, along with
+    /// which of its channels they can view.
+    async fn load_server(&mut self, db: &Db, server_id: ServerId) -> Result<(), ServerErr> {
+        self.drop_server(server_id);
+        let access = match MemberAccess::resolve(db, server_id, self.user_id).await {
+            Ok(access) => access,
+            Err(ServerErr::NotMember(_) | ServerErr::NoServerId(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        self.servers.insert(server_id);
+        for channel_id in access.visible_channels(db).await? {
+            self.channels.insert(channel_id, Some(server_id));
+        }
+        Ok(())
+    }
+
+    fn drop_server(&mut self, server_id: ServerId) {
+        self.servers.remove(&server_id);
+        self.channels
+            .retain(|_, channel_server| *channel_server != Some(server_id));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    attachment::Attachment,
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    config::Limits,
+    error::ServerErr,
+    message::{Message, MessageId},
+    permission::{MemberAccess, Permissions},
+    reaction::Reaction,
+    server::ServerId,
+    storage::Db,
+    user::UserId,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub const SEARCH_PAGE_MAX: usize = 25;
+/// Marks where a matched word starts and ends in a snippet.
+pub const SNIPPET_START: &str = "<mark>";
+pub const SNIPPET_END: &str = "</mark>";
+pub const SEARCH_PATH: &str = "/search";
+
+/// A message matching a search.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    pub message: Message,
+    /// The part of the text that matched, with matched words between
+    /// `<mark>` and `</mark>`. The text itself isn't escaped.
+    pub snippet: String,
+}
+
+/// A search as run by storage, once the channels the user can view are
+/// known. All words of `text` must match; the other filters apply if set.
+pub struct MessageSearch {
+    pub text: String,
+    pub channel_ids: Vec<ChannelId>,
+    pub user_id: Option<UserId>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    pub has_reply: Option<bool>,
+    pub mentions: Option<UserId>,
+    /// Only messages with lower IDs, for paging.
+    pub before_id: MessageId,
+    pub limit: i64,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct SearchParams {
+    /// Words the message must all contain.
+    #[param(example = "hello", required = true)]
+    text: String,
+    server_id: Option<ServerId>,
+    channel_id: Option<ChannelId>,
+    /// Only messages by this user.
+    user_id: Option<UserId>,
+    /// Only messages sent after this time.
+    after: Option<DateTime<Utc>>,
+    /// Only messages sent before this time.
+    before: Option<DateTime<Utc>>,
+    /// Only replies if true, only non-replies if false.
+    has_reply: Option<bool>,
+    /// Only messages mentioning this user.
+    mentions: Option<UserId>,
+    /// For the next page, the ID of the last message returned.
+    before_id: Option<MessageId>,
+    /// How many messages to return. Defaults to, and can't exceed, the
+    /// search page limit.
+    limit: Option<usize>,
+}
+
+/// The channels to search: the one asked for, the viewable channels of the
+/// server asked for, or else the viewable channels of every server the user
+/// is in along with their DMs.
+async fn searched_channels(
+    db: &Db,
+    user_id: UserId,
+    params: &SearchParams,
+) -> Result<Vec<ChannelId>, ServerErr> {
+    if let Some(channel_id) = params.channel_id {
+        let channel = Channel::get(db, channel_id).await?;
+        if let Some(server_id) = params.server_id
+            && channel.server_id != Some(server_id)
+        {
+            return Err(ServerErr::BadRequest(format!(
+                "Channel ID {channel_id} is not in server ID {server_id}"
+            )));
+        }
+        MemberAccess::require_channel(db, &channel, user_id, Permissions::VIEW_CHANNEL).await?;
+        return Ok(vec![channel.id]);
+    }
+    let mut channel_ids = Vec::new();
+    let server_ids = match params.server_id {
+        Some(server_id) => vec![server_id],
+        None => {
+            let dms = db.get_dm_channels(user_id).await?;
+            channel_ids.extend(dms.into_iter().map(|dm| dm.id));
+            db.get_member_servers(user_id).await?
+        }
+    };
+    for server_id in server_ids {
+        let access = MemberAccess::resolve(db, server_id, user_id).await?;
+        channel_ids.extend(access.visible_channels(db).await?);
+    }
+    Ok(channel_ids)
+}
+
+#[utoipa::path(
+    get,
+    path = SEARCH_PATH,
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Search the text of messages in channels you can view, newest first", body = Vec<SearchHit>),
+        (status = 400, description = "Empty text, limit out of range, or a channel from another server", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel or not a member of the server", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn search_messages(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<SearchParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let limit = query.limit.unwrap_or(limits.search_page_max);
+    if limit == 0 || limit > limits.search_page_max {
+        return Err(ServerErr::BadRequest(format!(
+            "limit must be 1 to {}",
+            limits.search_page_max
+        )));
+    }
+    let text = query.text.trim();
+    if text.is_empty() || text.len() > limits.message_max_len {
+        return Err(ServerErr::BadRequest(format!(
+            "Search text must be 1 to {} bytes",
+            limits.message_max_len
+        )));
+    }
+    let channel_ids = searched_channels(&db, user.id, &query).await?;
+    let mut hits = db
+        .search_messages(&MessageSearch {
+            text: text.to_string(),
+            channel_ids,
+            user_id: query.user_id,
+            after: query.after,
+            before: query.before,
+            has_reply: query.has_reply,
+            mentions: query.mentions,
+            before_id: query.before_id.unwrap_or(MessageId::MAX),
+            limit: limit as i64,
+        })
+        .await?;
+    Reaction::attach(&db, hits.iter_mut().map(|hit| &mut hit.message), user.id).await?;
+    Attachment::attach(&db, hits.iter_mut().map(|hit| &mut hit.message)).await?;
+    Ok(Json(hits))
+}
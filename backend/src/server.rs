@@ -1,11 +1,14 @@
-use crate::{channel::Channel, error::ServerErr, snapshot::Update, Sender};
+use crate::{
+    auth::AuthUser, channel::Channel, config::Limits, error::ServerErr, member::ServerMember,
+    permission::Permissions, role::Role, snapshot::Update, storage::Db, user::UserId, Sender,
+};
 use axum::{
     extract::{Query, State},
-    response::{sse::Event, IntoResponse},
+    response::IntoResponse,
     Json,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::{query, SqlitePool};
+use sqlx::FromRow;
 use ts_rs::TS;
 use utoipa::{IntoParams, ToSchema};
 
@@ -14,31 +17,39 @@ pub type ServerId = i32;
 pub const SERVER_NAME_MAX_LEN: usize = 32;
 pub const CREATE_SERVER_PATH: &str = "/create-server";
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
 #[ts(export, export_to = "../../frontend/src/bindings/")]
 pub struct Server {
     pub id: ServerId,
     pub name: String,
+    /// Has every permission in the server. Unset for servers created before
+    /// ownership existed and for the seeded server.
+    pub owner_id: Option<UserId>,
 }
 
 impl Server {
-    pub async fn insert(pool: &SqlitePool, name: String) -> Result<Self, ServerErr> {
+    /// Creates the server along with its @everyone role.
+    pub async fn insert(
+        db: &Db,
+        limits: &Limits,
+        name: String,
+        owner_id: Option<UserId>,
+    ) -> Result<Self, ServerErr> {
         let len = name.len();
-        if len > SERVER_NAME_MAX_LEN {
-            Err(ServerErr::ServerNameTooLong(len))
+        if len > limits.server_name_max_len {
+            Err(ServerErr::ServerNameTooLong(
+                len,
+                limits.server_name_max_len,
+            ))
         } else {
-            let id = query!(
-                r#"
-                INSERT INTO servers (name)
-                VALUES ($1)
-                RETURNING id AS "id!: i32"
-                "#,
-                name
-            )
-            .fetch_one(pool)
-            .await?
-            .id;
-            Ok(Self { id, name })
+            let id = db.insert_server(&name, owner_id).await?;
+            let everyone = if owner_id.is_some() {
+                Permissions::EVERYONE
+            } else {
+                Permissions::OWNERLESS
+            };
+            Role::insert_everyone(db, id, everyone).await?;
+            Ok(Self { id, name, owner_id })
         }
     }
 }
@@ -54,23 +65,28 @@ pub struct CreateServerParams {
     path = CREATE_SERVER_PATH,
     params(CreateServerParams),
     responses(
-        (status = 200, description = "Create a new server", body = (Server, Channel)),
+        (status = 200, description = "Create a new server and join it", body = (Server, Channel)),
+        (status = 401, description = "Not logged in", body = String),
         (status = 500, description = "Internal server error", body = String)
     )
 )]
 pub async fn create_server(
-    State(pool): State<SqlitePool>,
+    State(db): State<Db>,
+    State(limits): State<Limits>,
     State(send): State<Sender>,
+    AuthUser(user): AuthUser,
     Query(query): Query<CreateServerParams>,
 ) -> Result<impl IntoResponse, ServerErr> {
-    let server = Server::insert(&pool, query.name).await?;
-    let channel = Channel::insert(&pool, server.id, "Home".to_string()).await?;
-    let event = Event::default().json_data(Update::Server(server.clone()))?;
-    if let Err(err) = send.send(event) {
+    let server = Server::insert(&db, &limits, query.name, Some(user.id)).await?;
+    let channel = Channel::insert(&db, &limits, server.id, "Home".to_string()).await?;
+    let member = ServerMember::insert(&db, server.id, user.id).await?;
+    if let Err(err) = send.send(Update::Server(server.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    if let Err(err) = send.send(Update::MemberJoin(member)) {
         tracing::error!("Error sending event: {err:?}");
     }
-    let event = Event::default().json_data(Update::Channel(channel.clone()))?;
-    if let Err(err) = send.send(event) {
+    if let Err(err) = send.send(Update::Channel(channel.clone())) {
         tracing::error!("Error sending event: {err:?}");
     }
     Ok(Json((server, channel)))
@@ -1,183 +1,391 @@
-use crate::{channel::*, error::ServerErr, message::*, server::*, typing::*, user::*, Sender};
+use crate::{
+    attachment::Attachment, auth::AuthUser, channel::*, config::Limits, emoji::*, error::ServerErr,
+    member::*, message::*, overwrite::*, permission::*, presence::*, reaction::*, read_state::*,
+    role::*, scope::UpdateScope, server::*, storage::Db, thread::*, typing::*, update_log::*,
+    user::*, Sender,
+};
 use axum::{
-    extract::State,
+    extract::{Query, State},
+    http::HeaderMap,
     response::{sse::Event, IntoResponse, Sse},
     Json,
 };
-use chrono::{DateTime, Utc};
-use futures_util::Stream;
+use futures_util::{stream, Stream};
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, SqlitePool};
-use std::collections::{HashMap, HashSet};
-use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+};
 use ts_rs::TS;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 pub const GET_UPDATES_PATH: &str = "/updates";
+pub const SYNC_PATH: &str = "/sync";
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+/// Sent first on a new connection. Its ID is where updates follow from.
+pub const READY_EVENT: &str = "ready";
+/// Sent when updates since the client's `Last-Event-ID` are no longer kept,
+/// so the client has to fetch a new snapshot. Updates follow from its ID.
+pub const RESYNC_EVENT: &str = "resync";
 pub const SNAPSHOT_PATH: &str = "/snapshot";
 pub const SNAPSHOT_DEPTH: i64 = 128;
+pub const SNAPSHOT_CHANNEL_MESSAGES: i64 = 50;
 
-#[derive(Serialize, Deserialize, Clone, TS, ToSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
 #[ts(export, export_to = "../../frontend/src/bindings/")]
 pub enum Update {
     User(User),
     Server(Server),
     Channel(Channel),
     Message(Message),
-    Typing(Typing),
-    VoiceJoin { user_id: UserId, channel_id: i32 },
-    VoiceLeave { user_id: UserId, channel_id: i32 },
+    MessageEdit(Message),
+    MessageDelete {
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    },
+    MessageBulkDelete {
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    },
+    /// An image attachment was processed, filling in its size and thumbnail.
+    AttachmentUpdate {
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        attachment: Attachment,
+    },
+    Typing {
+        channel_id: ChannelId,
+        typing: Typing,
+    },
+    /// A user was added to a group DM.
+    RecipientAdd {
+        channel_id: ChannelId,
+        user_id: UserId,
+    },
+    /// A user left or was removed from a group DM. Also sent to them.
+    RecipientRemove {
+        channel_id: ChannelId,
+        user_id: UserId,
+    },
+    MemberJoin(ServerMember),
+    MemberLeave {
+        server_id: ServerId,
+        user_id: UserId,
+    },
+    Roles {
+        server_id: ServerId,
+        roles: Vec<Role>,
+    },
+    MemberRoleAdd(MemberRole),
+    MemberRoleRemove(MemberRole),
+    ChannelOverwrites {
+        server_id: ServerId,
+        channel_id: ChannelId,
+        overwrites: Vec<ChannelOverwrite>,
+    },
+    ReactionAdd(Reaction),
+    ReactionRemove(Reaction),
+    Emojis {
+        server_id: ServerId,
+        emojis: Vec<Emoji>,
+    },
+    ThreadCreate(Thread),
+    /// The thread was archived, unarchived, locked or unlocked.
+    ThreadUpdate(Thread),
+    /// The user's read state for a channel changed. Only sent to that user.
+    ReadStateUpdate(ReadState),
+    VoiceJoin {
+        user_id: UserId,
+        channel_id: i32,
+    },
+    VoiceLeave {
+        user_id: UserId,
+        channel_id: i32,
+    },
+    /// The user connected to or left the gateway, or changed their status.
+    Presence {
+        user_id: UserId,
+        status: PresenceStatus,
+    },
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct UpdatesParams {
+    /// Resumes after this event, for clients that can't send the
+    /// `Last-Event-ID` header. The header takes precedence.
+    last_event_id: Option<String>,
 }
 
+/// Each update is sent with its position in the stream as the event ID. A
+/// client reconnecting with `Last-Event-ID` is sent what it missed first, or
+/// a `resync` event when that's no longer kept. Only updates in the user's
+/// scope are sent.
 #[utoipa::path(
     get,
     path = GET_UPDATES_PATH,
-    params(),
+    params(UpdatesParams),
     responses(
         (status = 200, description = "Subscribe to SSE updates", body = ()),
+        (status = 401, description = "Not logged in", body = String),
         (status = 500, description = "Internal server error", body = String)
     )
 )]
 pub async fn get_updates(
+    State(db): State<Db>,
     State(send): State<Sender>,
-) -> Sse<impl Stream<Item = Result<Event, BroadcastStreamRecvError>>> {
+    AuthUser(user): AuthUser,
+    Query(query): Query<UpdatesParams>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ServerErr> {
     tracing::info!("New SSE client connected to /updates");
-    let stream: BroadcastStream<_> = send.subscribe().into();
-    Sse::new(stream).keep_alive(Default::default())
+    let last_event_id = headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or(query.last_event_id.as_deref());
+    // Subscribed first, so changes to the scope while it's read come in as
+    // updates.
+    let subscription = Subscription::start(send, last_event_id).await;
+    let scope = UpdateScope::new(&db, user.id).await?;
+    let stream = stream::unfold(
+        (subscription, scope),
+        move |(mut subscription, mut scope)| {
+            let db = db.clone();
+            async move {
+                loop {
+                    let event = match subscription.next().await? {
+                        Delivery::Update(logged) => match scope.admit(&db, &logged.update).await {
+                            Ok(true) => match update_event(&logged) {
+                                Some(event) => event,
+                                None => continue,
+                            },
+                            Ok(false) => continue,
+                            Err(err) => {
+                                tracing::error!("Error checking update visibility: {err:?}");
+                                continue;
+                            }
+                        },
+                        Delivery::Ready(seq) => position_event(READY_EVENT, seq),
+                        Delivery::Resync(seq) => {
+                            // The updates skipped may have changed the scope.
+                            match UpdateScope::new(&db, user.id).await {
+                                Ok(fresh) => scope = fresh,
+                                Err(err) => tracing::error!("Error reading update scope: {err:?}"),
+                            }
+                            position_event(RESYNC_EVENT, seq)
+                        }
+                    };
+                    return Some((Ok(event), (subscription, scope)));
+                }
+            }
+        },
+    );
+    Ok(Sse::new(stream).keep_alive(Default::default()))
+}
+
+fn update_event(logged: &LoggedUpdate) -> Option<Event> {
+    let event = Event::default().id(logged.seq.to_string());
+    match event.json_data(&logged.update) {
+        Ok(event) => Some(event),
+        Err(err) => {
+            tracing::error!("Error serializing update: {err:?}");
+            None
+        }
+    }
+}
+
+/// An event carrying nothing but a position. Browsers skip events without
+/// data, so the position is repeated there.
+fn position_event(name: &str, seq: Seq) -> Event {
+    Event::default()
+        .event(name)
+        .id(seq.to_string())
+        .data(seq.to_string())
 }
 
+/// Everything a client needs on startup. Servers, channels and messages are
+/// limited to the servers the requesting user is a member of, and channels and
+/// messages to the channels they can view. Each channel has its newest
+/// messages, and older ones are paged in from its history. Only active
+/// threads are included. DMs are listed apart from servers, with their
+/// recipients and newest messages.
+/// Message reactions, read states and unread counts are for the requesting
+/// user.
 #[derive(Serialize, Deserialize, Clone, TS, ToSchema)]
 #[ts(export, export_to = "../../frontend/src/bindings/")]
 pub struct Snapshot {
+    /// The last update included. Later ones can be included too, if they
+    /// happened while the snapshot was being read.
+    #[ts(type = "number")]
+    seq: Seq,
     users: HashMap<UserId, User>,
+    joined_servers: HashSet<ServerId>,
     channels: HashMap<ServerId, Vec<Channel>>,
     servers: HashMap<ServerId, Server>,
     messages: HashMap<ServerId, HashMap<ChannelId, Vec<Message>>>,
+    threads: HashMap<ChannelId, Vec<Thread>>,
+    emojis: HashMap<ServerId, Vec<Emoji>>,
+    dms: Vec<Channel>,
+    recipients: HashMap<ChannelId, Vec<UserId>>,
+    dm_messages: HashMap<ChannelId, Vec<Message>>,
+    read_states: HashMap<ChannelId, ReadState>,
+    /// Totals of the read states per server.
+    unreads: HashMap<ServerId, UnreadCounts>,
+    /// Users connected to the gateway. Everyone else is offline.
+    presences: HashMap<UserId, PresenceStatus>,
 }
 
 impl Snapshot {
-    pub async fn new(pool: &SqlitePool) -> Result<Self, ServerErr> {
-        let (users, servers, channels, messages) = tokio::join!(
-            Self::get_users(pool),
-            Self::get_servers(pool),
-            Self::get_channels(pool),
-            Self::get_messages(pool)
+    pub async fn new(
+        db: &Db,
+        limits: &Limits,
+        user_id: UserId,
+        seq: Seq,
+        presences: HashMap<UserId, PresenceStatus>,
+    ) -> Result<Self, ServerErr> {
+        let depth = limits.snapshot_depth;
+        let (users, joined_servers, servers, channels) = tokio::join!(
+            Self::get_users(db, depth),
+            db.get_member_servers(user_id),
+            Self::get_servers(db, user_id),
+            Self::get_channels(db, user_id)
         );
+        let joined_servers: HashSet<ServerId> = joined_servers?.into_iter().collect();
+        let servers = servers?;
+        let mut channels = channels?;
+        let mut visible = HashSet::new();
+        for (server_id, server_channels) in channels.iter_mut() {
+            let access = MemberAccess::resolve(db, *server_id, user_id).await?;
+            let overwrites = ChannelOverwrite::get_server(db, *server_id).await?;
+            server_channels.retain(|channel| {
+                let overwrites = overwrites.get(&channel.id).map_or(&[][..], Vec::as_slice);
+                access
+                    .channel_permissions(overwrites)
+                    .contains(Permissions::VIEW_CHANNEL)
+            });
+            visible.extend(server_channels.iter().map(|channel| channel.id));
+        }
+        let dms = db.get_dm_channels(user_id).await?;
+        let mut recipients = HashMap::new();
+        for dm in &dms {
+            recipients.insert(dm.id, db.get_recipients(dm.id).await?);
+        }
+        let visible_ids: Vec<ChannelId> = visible
+            .iter()
+            .copied()
+            .chain(dms.iter().map(|dm| dm.id))
+            .collect();
+        let mut channel_messages =
+            Self::get_messages(db, &visible_ids, limits.snapshot_channel_messages).await?;
+        Reaction::attach(db, channel_messages.values_mut().flatten(), user_id).await?;
+        Attachment::attach(db, channel_messages.values_mut().flatten()).await?;
+        let mut messages: HashMap<ServerId, HashMap<ChannelId, Vec<Message>>> = HashMap::new();
+        let mut dm_messages = HashMap::new();
+        for (channel_id, list) in channel_messages {
+            match list.first().and_then(|message| message.server_id) {
+                Some(server_id) => {
+                    messages
+                        .entry(server_id)
+                        .or_default()
+                        .insert(channel_id, list);
+                }
+                None => {
+                    dm_messages.insert(channel_id, list);
+                }
+            }
+        }
+        let mut emojis = HashMap::new();
+        for server_id in &joined_servers {
+            emojis.insert(*server_id, db.get_emojis(*server_id).await?);
+        }
+        let read_states: HashMap<ChannelId, ReadState> =
+            ReadState::get_channels(db, user_id, &visible_ids)
+                .await?
+                .into_iter()
+                .map(|read_state| (read_state.channel_id, read_state))
+                .collect();
+        let mut unreads: HashMap<ServerId, UnreadCounts> = HashMap::new();
+        for read_state in read_states.values() {
+            let Some(server_id) = read_state.server_id else {
+                continue;
+            };
+            let counts = unreads.entry(server_id).or_default();
+            counts.unread_count += read_state.unread_count;
+            counts.mention_count += read_state.mention_count;
+        }
+        let mut threads: HashMap<ChannelId, Vec<Thread>> = HashMap::new();
+        for thread in db.get_active_threads(&visible_ids).await? {
+            threads.entry(thread.channel_id).or_default().push(thread);
+        }
         Ok(Self {
+            seq,
             users: users?,
-            channels: channels?,
-            servers: servers?,
-            messages: messages?,
+            joined_servers,
+            channels,
+            servers,
+            messages,
+            threads,
+            emojis,
+            dms,
+            recipients,
+            dm_messages,
+            read_states,
+            unreads,
+            presences,
         })
     }
-    pub async fn get_users(pool: &SqlitePool) -> Result<HashMap<UserId, User>, ServerErr> {
-        let users = query_as!(
-            User,
-            r#"SELECT id AS "id!: i32", name FROM users LIMIT ?1"#,
-            SNAPSHOT_DEPTH
-        )
-        .fetch_all(pool)
-        .await?
-        .into_iter()
-        .map(|user| (user.id, user))
-        .collect();
+    pub async fn get_users(db: &Db, depth: i64) -> Result<HashMap<UserId, User>, ServerErr> {
+        let users = db
+            .get_users(depth)
+            .await?
+            .into_iter()
+            .map(|user| (user.id, user))
+            .collect();
         Ok(users)
     }
-    pub async fn get_servers(pool: &SqlitePool) -> Result<HashMap<ServerId, Server>, ServerErr> {
-        let servers = query_as!(
-            Server,
-            r#"SELECT id AS "id!: i32", name FROM servers LIMIT ?1"#,
-            SNAPSHOT_DEPTH
-        )
-        .fetch_all(pool)
-        .await?
-        .into_iter()
-        .map(|server| (server.id, server))
-        .collect();
+    pub async fn get_servers(
+        db: &Db,
+        user_id: UserId,
+    ) -> Result<HashMap<ServerId, Server>, ServerErr> {
+        let servers = db
+            .get_servers(user_id)
+            .await?
+            .into_iter()
+            .map(|server| (server.id, server))
+            .collect();
         Ok(servers)
     }
     pub async fn get_channels(
-        pool: &SqlitePool,
+        db: &Db,
+        user_id: UserId,
     ) -> Result<HashMap<ServerId, Vec<Channel>>, ServerErr> {
-        let channels = query_as!(
-            Channel,
-            r#"SELECT server_id AS "server_id!: i32", id AS "id!: i32", name FROM channels LIMIT ?1"#,
-            SNAPSHOT_DEPTH
-        )
-        .fetch_all(pool)
-        .await?;
-        let servers: HashSet<ServerId> = channels.iter().map(|channel| channel.server_id).collect();
-        let channels = servers
-            .into_iter()
-            .map(|server_id| {
-                (
-                    server_id,
-                    channels
-                        .iter()
-                        .filter(|channel| channel.server_id == server_id)
-                        .cloned()
-                        .collect(),
-                )
-            })
-            .collect();
+        let mut channels: HashMap<ServerId, Vec<Channel>> = HashMap::new();
+        for channel in db.get_channels(user_id).await? {
+            if let Some(server_id) = channel.server_id {
+                channels.entry(server_id).or_default().push(channel);
+            }
+        }
         Ok(channels)
     }
     pub async fn get_messages(
-        pool: &SqlitePool,
-    ) -> Result<HashMap<ServerId, HashMap<ChannelId, Vec<Message>>>, ServerErr> {
-        let messages = query_as!(
-            Message,
-            r#"
-            SELECT 
-                user_id AS "user_id!: i32", 
-                channel_id AS "channel_id!: i32", 
-                server_id AS "server_id!: i32", 
-                ts AS "ts!: DateTime<Utc>",
-                id,
-                text 
-            FROM messages 
-            ORDER BY ts DESC 
-            LIMIT ?1;
-            "#,
-            SNAPSHOT_DEPTH
-        )
-        .fetch_all(pool)
-        .await?;
-        let servers: HashSet<ServerId> = messages.iter().map(|msg| msg.server_id).collect();
-        let channels: HashMap<ServerId, Vec<ChannelId>> = servers
-            .into_iter()
-            .map(|server_id| {
-                (
-                    server_id,
-                    messages
-                        .iter()
-                        .filter_map(|msg| (msg.server_id == server_id).then_some(msg.channel_id))
-                        .collect(),
-                )
-            })
-            .collect();
-        let messages = channels
+        db: &Db,
+        channel_ids: &[ChannelId],
+        depth: i64,
+    ) -> Result<HashMap<ChannelId, Vec<Message>>, ServerErr> {
+        let mut messages: HashMap<ChannelId, Vec<Message>> = HashMap::new();
+        // Newest first, so reversed to put each channel's oldest first.
+        for message in db
+            .get_latest_messages(channel_ids, depth)
+            .await?
             .into_iter()
-            .map(|(server_id, channels)| {
-                (
-                    server_id,
-                    channels
-                        .into_iter()
-                        .map(|channel_id| {
-                            (
-                                channel_id,
-                                messages
-                                    .iter()
-                                    .filter(|msg| msg.channel_id == channel_id)
-                                    .rev()
-                                    .cloned()
-                                    .collect(),
-                            )
-                        })
-                        .collect(),
-                )
-            })
-            .collect();
+            .rev()
+        {
+            messages
+                .entry(message.channel_id)
+                .or_default()
+                .push(message);
+        }
         Ok(messages)
     }
 }
@@ -188,10 +396,71 @@ impl Snapshot {
     params(),
     responses(
         (status = 200, description = "Get a snapshot of users, servers, channels, and messages", body = Snapshot),
+        (status = 401, description = "Not logged in", body = String),
         (status = 500, description = "Internal server error", body = String)
     )
 )]
-pub async fn get_snapshot(State(pool): State<SqlitePool>) -> Result<impl IntoResponse, ServerErr> {
-    let snapshot = Snapshot::new(&pool).await?;
+pub async fn get_snapshot(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    State(presence): State<PresenceState>,
+    AuthUser(user): AuthUser,
+) -> Result<impl IntoResponse, ServerErr> {
+    // Updates are only sent once they're in the database, so everything up
+    // to here will be read.
+    let seq = send.head().await;
+    let snapshot = Snapshot::new(&db, &limits, user.id, seq, presence.all().await).await?;
     Ok(Json(snapshot))
 }
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct SyncParams {
+    /// The `seq` of a snapshot or sync, or the ID of an update.
+    #[param(required = true)]
+    #[ts(type = "number")]
+    since: Seq,
+}
+
+/// The updates since a snapshot or an earlier sync.
+#[derive(Serialize, Deserialize, Clone, TS, ToSchema)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct SyncUpdates {
+    /// Where these updates leave off, to sync or subscribe from next.
+    #[ts(type = "number")]
+    seq: Seq,
+    /// Oldest first, and only the ones the user can see.
+    updates: Vec<Update>,
+}
+
+#[utoipa::path(
+    get,
+    path = SYNC_PATH,
+    params(SyncParams),
+    responses(
+        (status = 200, description = "Get the updates since a version", body = SyncUpdates),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 410, description = "The updates are no longer kept, fetch a new snapshot", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_sync(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<SyncParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let missed = send
+        .since(query.since)
+        .await
+        .ok_or(ServerErr::SyncGone(query.since))?;
+    let seq = missed.last().map_or(query.since, |logged| logged.seq);
+    let mut scope = UpdateScope::new(&db, user.id).await?;
+    let mut updates = Vec::new();
+    for logged in missed {
+        if scope.admit(&db, &logged.update).await? {
+            updates.push(logged.update.clone());
+        }
+    }
+    Ok(Json(SyncUpdates { seq, updates }))
+}
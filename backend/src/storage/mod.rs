@@ -0,0 +1,413 @@
+use crate::{
+    attachment::{Attachment, AttachmentId},
+    channel::{Channel, ChannelId},
+    emoji::{Emoji, EmojiId},
+    error::ServerErr,
+    invite::Invite,
+    member::ServerMember,
+    mention::Mentions,
+    message::{Message, MessageEdit, MessageId},
+    overwrite::ChannelOverwrite,
+    reaction::ReactionCount,
+    read_state::ReadState,
+    role::{MemberRole, Role, RoleId},
+    search::{MessageSearch, SearchHit},
+    server::{Server, ServerId},
+    thread::{Thread, ThreadId, ThreadMember},
+    user::{User, UserId},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+
+/// Shared handle to whichever storage backend was selected at startup.
+pub type Db = Arc<dyn Storage>;
+
+/// Database operations used by the handlers.
+///
+/// Input validation (lengths, existence checks) happens in the models before
+/// these are called, so implementations only need to run the queries.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn insert_user(&self, name: &str, password_hash: &str) -> Result<UserId, ServerErr>;
+    async fn insert_server(
+        &self,
+        name: &str,
+        owner_id: Option<UserId>,
+    ) -> Result<ServerId, ServerErr>;
+    async fn insert_channel(&self, server_id: ServerId, name: &str)
+        -> Result<ChannelId, ServerErr>;
+    /// Inserts the message and its mentions, ignoring its `id` and reply
+    /// preview, and returns the new ID.
+    async fn insert_message(&self, message: &Message) -> Result<MessageId, ServerErr>;
+
+    /// Replaces the message's text and mentions, keeping the old text in its
+    /// edit history.
+    async fn edit_message(
+        &self,
+        id: MessageId,
+        text: &str,
+        edited_ts: DateTime<Utc>,
+        mentions: &Mentions,
+    ) -> Result<(), ServerErr>;
+    /// Returns false if the message didn't exist.
+    async fn delete_message(&self, id: MessageId) -> Result<bool, ServerErr>;
+    /// Deletes the listed messages that are in the channel and returns the
+    /// IDs of the ones that were.
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        ids: &[MessageId],
+    ) -> Result<Vec<MessageId>, ServerErr>;
+    /// Deletes the newest `limit` messages in the channel that match the
+    /// optional author and time range, and returns their IDs.
+    async fn purge_messages(
+        &self,
+        channel_id: ChannelId,
+        user_id: Option<UserId>,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<MessageId>, ServerErr>;
+    /// Returns the message's previous versions, oldest first.
+    async fn get_message_edits(&self, id: MessageId) -> Result<Vec<MessageEdit>, ServerErr>;
+
+    /// Exactly one of `emoji` and `emoji_id` is set. Returns false if the
+    /// user had already reacted with it.
+    async fn insert_reaction(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        emoji: Option<&str>,
+        emoji_id: Option<EmojiId>,
+        created_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr>;
+    /// Returns false if the user hadn't reacted with the emoji.
+    async fn delete_reaction(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        emoji: Option<&str>,
+        emoji_id: Option<EmojiId>,
+    ) -> Result<bool, ServerErr>;
+    /// Returns the reaction counts of the listed messages, each message's in
+    /// the order they were first used. `me` is whether `user_id` reacted.
+    async fn get_reaction_counts(
+        &self,
+        message_ids: &[MessageId],
+        user_id: UserId,
+    ) -> Result<Vec<(MessageId, ReactionCount)>, ServerErr>;
+
+    /// Inserts the attachment, ignoring its `id`, and returns the new ID.
+    async fn insert_attachment(&self, attachment: &Attachment) -> Result<AttachmentId, ServerErr>;
+    async fn get_attachment(&self, id: AttachmentId) -> Result<Option<Attachment>, ServerErr>;
+    /// Returns the attachments of the listed messages, each message's in the
+    /// order they were uploaded.
+    async fn get_attachments(
+        &self,
+        message_ids: &[MessageId],
+    ) -> Result<Vec<Attachment>, ServerErr>;
+    /// Returns the attachments no image worker has looked at yet, oldest
+    /// first.
+    async fn get_unprocessed_attachments(&self) -> Result<Vec<Attachment>, ServerErr>;
+    /// Records what an image worker found out, the content type, size and
+    /// thumbnail, and marks the attachment processed.
+    async fn update_attachment_image(&self, attachment: &Attachment) -> Result<(), ServerErr>;
+
+    /// Inserts the emoji, ignoring its `id`, and returns the new ID.
+    async fn insert_emoji(&self, emoji: &Emoji) -> Result<EmojiId, ServerErr>;
+    async fn get_emoji(&self, id: EmojiId) -> Result<Option<Emoji>, ServerErr>;
+    async fn get_emojis(&self, server_id: ServerId) -> Result<Vec<Emoji>, ServerErr>;
+    /// Returns false if the emoji didn't exist.
+    async fn delete_emoji(&self, id: EmojiId) -> Result<bool, ServerErr>;
+
+    /// Adds one to the mention count of every member of the message's
+    /// server, or recipient of its DM, that it mentions, other than the
+    /// author.
+    async fn add_mention_counts(&self, message: &Message) -> Result<(), ServerErr>;
+    /// Moves the user's last read message in the channel forward to
+    /// `message_id`, if it's newer, and recounts their unread mentions.
+    async fn ack_channel(
+        &self,
+        user_id: UserId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<(), ServerErr>;
+    /// Returns the user's read state for each of the channels, including
+    /// channels they have never read.
+    async fn get_read_states(
+        &self,
+        user_id: UserId,
+        channel_ids: &[ChannelId],
+    ) -> Result<Vec<ReadState>, ServerErr>;
+
+    async fn user_exists(&self, id: UserId) -> Result<bool, ServerErr>;
+    async fn server_exists(&self, id: ServerId) -> Result<bool, ServerErr>;
+    async fn channel_exists(&self, id: ChannelId) -> Result<bool, ServerErr>;
+    async fn has_servers(&self) -> Result<bool, ServerErr>;
+    async fn get_server(&self, id: ServerId) -> Result<Option<Server>, ServerErr>;
+    async fn get_channel(&self, id: ChannelId) -> Result<Option<Channel>, ServerErr>;
+    async fn get_server_channels(&self, server_id: ServerId) -> Result<Vec<Channel>, ServerErr>;
+    async fn get_message(&self, id: MessageId) -> Result<Option<Message>, ServerErr>;
+    /// Returns up to `limit` of the channel's messages with IDs below `before`,
+    /// newest first. Only messages in `thread_id` are included, or outside
+    /// threads if it's unset.
+    async fn get_messages_before(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        before: MessageId,
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr>;
+    /// Returns matching messages newest first, without reactions.
+    async fn search_messages(&self, search: &MessageSearch) -> Result<Vec<SearchHit>, ServerErr>;
+    /// Returns up to `limit` of the channel's messages with IDs above `after`,
+    /// oldest first, filtered by thread like `get_messages_before`.
+    async fn get_messages_after(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        after: MessageId,
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr>;
+
+    /// Looks up a user that can log in, along with their password hash.
+    async fn get_credentials(&self, name: &str) -> Result<Option<(User, String)>, ServerErr>;
+    async fn insert_session(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        created_ts: DateTime<Utc>,
+        expires_ts: DateTime<Utc>,
+    ) -> Result<(), ServerErr>;
+    /// Resolves an unexpired session to its user.
+    async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<User>, ServerErr>;
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServerErr>;
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, ServerErr>;
+
+    /// Adds the user to the server. Returns false if they were already a member.
+    async fn insert_member(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        joined_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr>;
+    /// Removes the user from the server. Returns false if they weren't a member.
+    async fn delete_member(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr>;
+    async fn is_member(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr>;
+    async fn get_members(&self, server_id: ServerId) -> Result<Vec<ServerMember>, ServerErr>;
+    /// Returns the servers the user is a member of.
+    async fn get_member_servers(&self, user_id: UserId) -> Result<Vec<ServerId>, ServerErr>;
+    async fn member_count(&self, server_id: ServerId) -> Result<i64, ServerErr>;
+
+    async fn insert_ban(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        banned_ts: DateTime<Utc>,
+    ) -> Result<(), ServerErr>;
+    async fn delete_ban(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr>;
+    async fn is_banned(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr>;
+
+    /// Inserts a role at `position`, moving roles at or above it up by one.
+    async fn insert_role(
+        &self,
+        server_id: ServerId,
+        name: &str,
+        permissions: i64,
+        position: i32,
+    ) -> Result<RoleId, ServerErr>;
+    async fn update_role(&self, id: RoleId, name: &str, permissions: i64) -> Result<(), ServerErr>;
+    /// Moves a role from one position to another, shifting the roles between.
+    async fn move_role(
+        &self,
+        server_id: ServerId,
+        id: RoleId,
+        from: i32,
+        to: i32,
+    ) -> Result<(), ServerErr>;
+    /// Deletes a role and closes the gap it leaves in the positions.
+    async fn delete_role(
+        &self,
+        server_id: ServerId,
+        id: RoleId,
+        position: i32,
+    ) -> Result<(), ServerErr>;
+    async fn get_role(&self, id: RoleId) -> Result<Option<Role>, ServerErr>;
+    /// Returns the server's roles, lowest position first.
+    async fn get_roles(&self, server_id: ServerId) -> Result<Vec<Role>, ServerErr>;
+    /// Returns the member's roles, including @everyone.
+    async fn get_member_roles(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+    ) -> Result<Vec<Role>, ServerErr>;
+    async fn get_all_member_roles(&self, server_id: ServerId)
+        -> Result<Vec<MemberRole>, ServerErr>;
+    /// Returns false if the member already had the role.
+    async fn insert_member_role(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool, ServerErr>;
+    /// Returns false if the member didn't have the role.
+    async fn delete_member_role(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool, ServerErr>;
+
+    /// Inserts the overwrite, replacing any the channel already has for the
+    /// same role or user.
+    async fn upsert_overwrite(&self, overwrite: &ChannelOverwrite) -> Result<(), ServerErr>;
+    /// Returns false if the channel had no overwrite for the role or user.
+    async fn delete_overwrite(
+        &self,
+        channel_id: ChannelId,
+        role_id: Option<RoleId>,
+        user_id: Option<UserId>,
+    ) -> Result<bool, ServerErr>;
+    async fn get_overwrites(
+        &self,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ChannelOverwrite>, ServerErr>;
+    /// Returns the overwrites of every channel in the server.
+    async fn get_server_overwrites(
+        &self,
+        server_id: ServerId,
+    ) -> Result<Vec<ChannelOverwrite>, ServerErr>;
+
+    /// Inserts the thread, ignoring its `id`, and returns the new ID.
+    async fn insert_thread(&self, thread: &Thread) -> Result<ThreadId, ServerErr>;
+    async fn get_thread(&self, id: ThreadId) -> Result<Option<Thread>, ServerErr>;
+    /// Returns the channel's archived or active threads, most recently
+    /// active first.
+    async fn get_threads(
+        &self,
+        channel_id: ChannelId,
+        archived: bool,
+    ) -> Result<Vec<Thread>, ServerErr>;
+    /// Returns the threads in the channels that aren't archived.
+    async fn get_active_threads(&self, channel_ids: &[ChannelId])
+        -> Result<Vec<Thread>, ServerErr>;
+    /// Returns false if the thread was already archived.
+    async fn archive_thread(&self, id: ThreadId) -> Result<bool, ServerErr>;
+    /// Records activity in the thread, unarchiving it.
+    async fn touch_thread(&self, id: ThreadId, ts: DateTime<Utc>) -> Result<(), ServerErr>;
+    async fn set_thread_locked(&self, id: ThreadId, locked: bool) -> Result<(), ServerErr>;
+    /// Archives active threads with no activity since `before` and returns
+    /// them.
+    async fn archive_inactive_threads(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Thread>, ServerErr>;
+    /// Returns false if the user was already a member.
+    async fn insert_thread_member(
+        &self,
+        thread_id: ThreadId,
+        user_id: UserId,
+        joined_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr>;
+    /// Returns false if the user wasn't a member.
+    async fn delete_thread_member(
+        &self,
+        thread_id: ThreadId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr>;
+    async fn get_thread_members(&self, thread_id: ThreadId)
+        -> Result<Vec<ThreadMember>, ServerErr>;
+
+    /// Inserts a DM or group DM, ignoring its `id`, along with its
+    /// recipients, and returns the new ID. Fails with a unique violation if
+    /// the two recipients of a 1:1 DM already have one.
+    async fn insert_dm(
+        &self,
+        channel: &Channel,
+        recipients: &[UserId],
+        added_ts: DateTime<Utc>,
+    ) -> Result<ChannelId, ServerErr>;
+    /// Returns the 1:1 DM between the two users, if they have one.
+    async fn get_dm(&self, user_id: UserId, other_id: UserId)
+        -> Result<Option<Channel>, ServerErr>;
+    /// Returns the DMs and group DMs the user is a recipient of.
+    async fn get_dm_channels(&self, user_id: UserId) -> Result<Vec<Channel>, ServerErr>;
+    /// Returns the DM's recipients in the order they were added.
+    async fn get_recipients(&self, channel_id: ChannelId) -> Result<Vec<UserId>, ServerErr>;
+    async fn is_recipient(&self, channel_id: ChannelId, user_id: UserId)
+        -> Result<bool, ServerErr>;
+    /// Returns false if the user was already a recipient.
+    async fn insert_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+        added_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr>;
+    /// Returns false if the user wasn't a recipient.
+    async fn delete_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr>;
+    async fn set_channel_owner(&self, id: ChannelId, owner_id: UserId) -> Result<(), ServerErr>;
+    /// Deletes the channel and everything in it. Returns false if it didn't
+    /// exist.
+    async fn delete_channel(&self, id: ChannelId) -> Result<bool, ServerErr>;
+    /// Whether the two users are members of at least one server in common.
+    async fn shares_server(&self, user_id: UserId, other_id: UserId) -> Result<bool, ServerErr>;
+
+    async fn insert_invite(&self, invite: &Invite) -> Result<(), ServerErr>;
+    async fn get_invite(&self, code: &str) -> Result<Option<Invite>, ServerErr>;
+    async fn get_invites(&self, server_id: ServerId) -> Result<Vec<Invite>, ServerErr>;
+    /// Counts one use of the invite and adds the user to its server, together
+    /// or not at all. Returns false if the invite is expired or used up, or
+    /// the user is already a member.
+    async fn redeem_invite(
+        &self,
+        code: &str,
+        server_id: ServerId,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> Result<bool, ServerErr>;
+    async fn delete_invite(&self, code: &str) -> Result<bool, ServerErr>;
+
+    async fn get_users(&self, limit: i64) -> Result<Vec<User>, ServerErr>;
+    /// Returns the servers the user is a member of.
+    async fn get_servers(&self, user_id: UserId) -> Result<Vec<Server>, ServerErr>;
+    /// Returns every channel of the servers the user is a member of.
+    async fn get_channels(&self, user_id: UserId) -> Result<Vec<Channel>, ServerErr>;
+    /// Returns the newest `limit` messages of each channel, threads included,
+    /// newest first.
+    async fn get_latest_messages(
+        &self,
+        channel_ids: &[ChannelId],
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr>;
+
+    /// Appends an update to the log. `data` is the update as JSON.
+    async fn insert_logged_update(
+        &self,
+        seq: i64,
+        ts: DateTime<Utc>,
+        data: &str,
+    ) -> Result<(), ServerErr>;
+    async fn get_last_logged_update(&self) -> Result<Option<i64>, ServerErr>;
+    /// Returns the sequence numbers and JSON of the logged updates after
+    /// `after`, oldest first.
+    async fn get_logged_updates(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<(i64, String)>, ServerErr>;
+    /// Deletes logged updates up to and including `through`.
+    async fn prune_logged_updates(&self, through: i64) -> Result<(), ServerErr>;
+}
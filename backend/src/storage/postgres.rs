@@ -0,0 +1,1909 @@
+use super::Storage;
+use crate::{
+    attachment::{Attachment, AttachmentId},
+    channel::{Channel, ChannelId, ChannelKind},
+    emoji::{Emoji, EmojiId},
+    error::ServerErr,
+    invite::Invite,
+    member::ServerMember,
+    mention::Mentions,
+    message::{Message, MessageEdit, MessageId, REPLY_EXCERPT_LEN},
+    overwrite::ChannelOverwrite,
+    reaction::ReactionCount,
+    read_state::ReadState,
+    role::{MemberRole, Role, RoleId},
+    search::{MessageSearch, SearchHit, SNIPPET_END, SNIPPET_START},
+    server::{Server, ServerId},
+    thread::{Thread, ThreadId, ThreadMember},
+    user::{User, UserId},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{query, query_as, query_scalar, PgPool, Postgres, Transaction};
+
+/// Adds a row per mentioned user, role and channel.
+async fn insert_mentions(
+    tx: &mut Transaction<'_, Postgres>,
+    message_id: MessageId,
+    mentions: &Mentions,
+) -> Result<(), ServerErr> {
+    query(
+        r#"
+        INSERT INTO message_mentions (message_id, user_id, role_id, channel_id)
+        SELECT $1, unnest($2::INTEGER[]), NULL::INTEGER, NULL::INTEGER
+        UNION ALL SELECT $1, NULL, unnest($3::INTEGER[]), NULL
+        UNION ALL SELECT $1, NULL, NULL, unnest($4::INTEGER[])
+        "#,
+    )
+    .bind(message_id)
+    .bind(&mentions.users)
+    .bind(&mentions.roles)
+    .bind(&mentions.channels)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Postgres (and TimescaleDB) backend.
+///
+/// The compile-time `query!` macros only check against one database, which is
+/// the SQLite dev database, so these queries are checked at runtime instead.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn insert_user(&self, name: &str, password_hash: &str) -> Result<UserId, ServerErr> {
+        let id =
+            query_scalar(r#"INSERT INTO users (name, password_hash) VALUES ($1, $2) RETURNING id"#)
+                .bind(name)
+                .bind(password_hash)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(id)
+    }
+
+    async fn insert_server(
+        &self,
+        name: &str,
+        owner_id: Option<UserId>,
+    ) -> Result<ServerId, ServerErr> {
+        let id =
+            query_scalar(r#"INSERT INTO servers (name, owner_id) VALUES ($1, $2) RETURNING id"#)
+                .bind(name)
+                .bind(owner_id)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(id)
+    }
+
+    async fn insert_channel(
+        &self,
+        server_id: ServerId,
+        name: &str,
+    ) -> Result<ChannelId, ServerErr> {
+        let id =
+            query_scalar(r#"INSERT INTO channels (server_id, name) VALUES ($1, $2) RETURNING id"#)
+                .bind(server_id)
+                .bind(name)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(id)
+    }
+
+    async fn insert_message(&self, message: &Message) -> Result<MessageId, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        let id = query_scalar(
+            r#"
+            INSERT INTO messages (
+                user_id, channel_id, server_id, thread_id, text, ts, reply_to, mention_everyone
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+            "#,
+        )
+        .bind(message.user_id)
+        .bind(message.channel_id)
+        .bind(message.server_id)
+        .bind(message.thread_id)
+        .bind(&message.text)
+        .bind(message.ts)
+        .bind(message.reply_to)
+        .bind(message.mentions.everyone)
+        .fetch_one(&mut *tx)
+        .await?;
+        insert_mentions(&mut tx, id, &message.mentions).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn edit_message(
+        &self,
+        id: MessageId,
+        text: &str,
+        edited_ts: DateTime<Utc>,
+        mentions: &Mentions,
+    ) -> Result<(), ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query(
+            r#"
+            INSERT INTO message_edits (message_id, text, edited_ts)
+            SELECT id, text, $2 FROM messages WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(edited_ts)
+        .execute(&mut *tx)
+        .await?;
+        query(
+            r#"UPDATE messages SET text = $2, edited_ts = $3, mention_everyone = $4 WHERE id = $1"#,
+        )
+        .bind(id)
+        .bind(text)
+        .bind(edited_ts)
+        .bind(mentions.everyone)
+        .execute(&mut *tx)
+        .await?;
+        query(r#"DELETE FROM message_mentions WHERE message_id = $1"#)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        insert_mentions(&mut tx, id, mentions).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, id: MessageId) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM messages WHERE id = $1"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        ids: &[MessageId],
+    ) -> Result<Vec<MessageId>, ServerErr> {
+        let deleted = query_scalar(
+            r#"
+            DELETE FROM messages
+            WHERE channel_id = $1 AND id = ANY($2)
+            RETURNING id
+            "#,
+        )
+        .bind(channel_id)
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(deleted)
+    }
+
+    async fn purge_messages(
+        &self,
+        channel_id: ChannelId,
+        user_id: Option<UserId>,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<MessageId>, ServerErr> {
+        let deleted = query_scalar(
+            r#"
+            DELETE FROM messages
+            WHERE id IN (
+                SELECT id FROM messages
+                WHERE channel_id = $1
+                    AND ($2::INTEGER IS NULL OR user_id = $2)
+                    AND ($3::TIMESTAMPTZ IS NULL OR ts > $3)
+                    AND ($4::TIMESTAMPTZ IS NULL OR ts < $4)
+                ORDER BY id DESC
+                LIMIT $5
+            )
+            RETURNING id
+            "#,
+        )
+        .bind(channel_id)
+        .bind(user_id)
+        .bind(after)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(deleted)
+    }
+
+    async fn get_message_edits(&self, id: MessageId) -> Result<Vec<MessageEdit>, ServerErr> {
+        let edits = query_as(
+            r#"
+            SELECT message_id, text, edited_ts
+            FROM message_edits
+            WHERE message_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(edits)
+    }
+
+    async fn insert_reaction(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        emoji: Option<&str>,
+        emoji_id: Option<EmojiId>,
+        created_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            INSERT INTO reactions (message_id, user_id, emoji, emoji_id, created_ts)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .bind(emoji)
+        .bind(emoji_id)
+        .bind(created_ts)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_reaction(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        emoji: Option<&str>,
+        emoji_id: Option<EmojiId>,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            DELETE FROM reactions
+            WHERE message_id = $1 AND user_id = $2
+                AND emoji IS NOT DISTINCT FROM $3 AND emoji_id IS NOT DISTINCT FROM $4
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .bind(emoji)
+        .bind(emoji_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_reaction_counts(
+        &self,
+        message_ids: &[MessageId],
+        user_id: UserId,
+    ) -> Result<Vec<(MessageId, ReactionCount)>, ServerErr> {
+        let rows: Vec<(MessageId, Option<String>, Option<EmojiId>, i64, bool)> = query_as(
+            r#"
+            SELECT message_id, emoji, emoji_id, COUNT(*), BOOL_OR(user_id = $2)
+            FROM reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji, emoji_id
+            ORDER BY message_id, MIN(created_ts)
+            "#,
+        )
+        .bind(message_ids)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(message_id, emoji, emoji_id, count, me)| {
+                (
+                    message_id,
+                    ReactionCount {
+                        emoji,
+                        emoji_id,
+                        count,
+                        me,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    async fn insert_attachment(&self, attachment: &Attachment) -> Result<AttachmentId, ServerErr> {
+        // Only images have anything for the workers to do.
+        let id = query_scalar(
+            r#"
+            INSERT INTO attachments (message_id, filename, content_type, size, hash, processed)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+        )
+        .bind(attachment.message_id)
+        .bind(&attachment.filename)
+        .bind(&attachment.content_type)
+        .bind(attachment.size)
+        .bind(&attachment.hash)
+        .bind(!attachment.is_image())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_attachment(&self, id: AttachmentId) -> Result<Option<Attachment>, ServerErr> {
+        let attachment = query_as(
+            r#"
+            SELECT
+                id, message_id, filename, content_type, size, hash, width, height, thumbnail_hash
+            FROM attachments
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(attachment)
+    }
+
+    async fn get_attachments(
+        &self,
+        message_ids: &[MessageId],
+    ) -> Result<Vec<Attachment>, ServerErr> {
+        let attachments = query_as(
+            r#"
+            SELECT
+                id, message_id, filename, content_type, size, hash, width, height, thumbnail_hash
+            FROM attachments
+            WHERE message_id = ANY($1)
+            ORDER BY message_id, id
+            "#,
+        )
+        .bind(message_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(attachments)
+    }
+
+    async fn get_unprocessed_attachments(&self) -> Result<Vec<Attachment>, ServerErr> {
+        let attachments = query_as(
+            r#"
+            SELECT
+                id, message_id, filename, content_type, size, hash, width, height, thumbnail_hash
+            FROM attachments
+            WHERE NOT processed
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(attachments)
+    }
+
+    async fn update_attachment_image(&self, attachment: &Attachment) -> Result<(), ServerErr> {
+        query(
+            r#"
+            UPDATE attachments
+            SET content_type = $2, width = $3, height = $4, thumbnail_hash = $5, processed = TRUE
+            WHERE id = $1
+            "#,
+        )
+        .bind(attachment.id)
+        .bind(&attachment.content_type)
+        .bind(attachment.width)
+        .bind(attachment.height)
+        .bind(&attachment.thumbnail_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_emoji(&self, emoji: &Emoji) -> Result<EmojiId, ServerErr> {
+        let id = query_scalar(
+            r#"
+            INSERT INTO emojis (server_id, name, creator_id, created_ts)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+        )
+        .bind(emoji.server_id)
+        .bind(&emoji.name)
+        .bind(emoji.creator_id)
+        .bind(emoji.created_ts)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_emoji(&self, id: EmojiId) -> Result<Option<Emoji>, ServerErr> {
+        let emoji = query_as(
+            r#"
+            SELECT id, server_id, name, creator_id, created_ts
+            FROM emojis
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(emoji)
+    }
+
+    async fn get_emojis(&self, server_id: ServerId) -> Result<Vec<Emoji>, ServerErr> {
+        let emojis = query_as(
+            r#"
+            SELECT id, server_id, name, creator_id, created_ts
+            FROM emojis
+            WHERE server_id = $1
+            ORDER BY name
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(emojis)
+    }
+
+    async fn delete_emoji(&self, id: EmojiId) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM emojis WHERE id = $1"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn add_mention_counts(&self, message: &Message) -> Result<(), ServerErr> {
+        query(
+            r#"
+            INSERT INTO read_states (user_id, channel_id, mention_count)
+            SELECT user_id, $1, 1 FROM (
+                SELECT user_id FROM server_members WHERE server_id = $2
+                UNION SELECT user_id FROM channel_recipients WHERE channel_id = $1
+            ) AS candidates
+            WHERE user_id <> $3 AND (
+                $4
+                OR user_id = ANY($5)
+                OR user_id IN (
+                    SELECT user_id FROM member_roles WHERE server_id = $2 AND role_id = ANY($6)
+                )
+            )
+            ON CONFLICT (user_id, channel_id)
+                DO UPDATE SET mention_count = read_states.mention_count + 1
+            "#,
+        )
+        .bind(message.channel_id)
+        .bind(message.server_id)
+        .bind(message.user_id)
+        .bind(message.mentions.everyone)
+        .bind(&message.mentions.users)
+        .bind(&message.mentions.roles)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn ack_channel(
+        &self,
+        user_id: UserId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<(), ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query(
+            r#"
+            INSERT INTO read_states (user_id, channel_id, last_read_message_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, channel_id) DO UPDATE SET last_read_message_id =
+                GREATEST(read_states.last_read_message_id, EXCLUDED.last_read_message_id)
+            "#,
+        )
+        .bind(user_id)
+        .bind(channel_id)
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await?;
+        query(
+            r#"
+            UPDATE read_states SET mention_count = (
+                SELECT COUNT(*) FROM messages
+                WHERE messages.channel_id = $2
+                    AND messages.id > read_states.last_read_message_id
+                    AND messages.user_id <> $1
+                    AND (messages.mention_everyone OR messages.id IN (
+                        SELECT message_id FROM message_mentions
+                        WHERE user_id = $1
+                            OR role_id IN (SELECT role_id FROM member_roles WHERE user_id = $1)
+                    ))
+            )
+            WHERE user_id = $1 AND channel_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(channel_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_read_states(
+        &self,
+        user_id: UserId,
+        channel_ids: &[ChannelId],
+    ) -> Result<Vec<ReadState>, ServerErr> {
+        let read_states = query_as(
+            r#"
+            SELECT
+                $1 AS user_id,
+                channels.id AS channel_id,
+                channels.server_id,
+                read_states.last_read_message_id,
+                (
+                    SELECT COUNT(*) FROM messages
+                    WHERE messages.channel_id = channels.id
+                        AND messages.id > COALESCE(read_states.last_read_message_id, 0)
+                ) AS unread_count,
+                COALESCE(read_states.mention_count, 0)::BIGINT AS mention_count
+            FROM channels
+            LEFT JOIN read_states
+                ON read_states.channel_id = channels.id AND read_states.user_id = $1
+            WHERE channels.id = ANY($2)
+            ORDER BY channels.id
+            "#,
+        )
+        .bind(user_id)
+        .bind(channel_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(read_states)
+    }
+
+    async fn user_exists(&self, id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)"#)
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists)
+    }
+
+    async fn server_exists(&self, id: ServerId) -> Result<bool, ServerErr> {
+        let exists = query_scalar(r#"SELECT EXISTS(SELECT 1 FROM servers WHERE id = $1)"#)
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists)
+    }
+
+    async fn channel_exists(&self, id: ChannelId) -> Result<bool, ServerErr> {
+        let exists = query_scalar(r#"SELECT EXISTS(SELECT 1 FROM channels WHERE id = $1)"#)
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists)
+    }
+
+    async fn has_servers(&self) -> Result<bool, ServerErr> {
+        let exists = query_scalar(r#"SELECT EXISTS(SELECT 1 FROM servers)"#)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists)
+    }
+
+    async fn get_server(&self, id: ServerId) -> Result<Option<Server>, ServerErr> {
+        let server = query_as(r#"SELECT id, name, owner_id FROM servers WHERE id = $1"#)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(server)
+    }
+
+    async fn get_channel(&self, id: ChannelId) -> Result<Option<Channel>, ServerErr> {
+        let channel =
+            query_as(r#"SELECT server_id, id, name, kind, owner_id FROM channels WHERE id = $1"#)
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(channel)
+    }
+
+    async fn get_server_channels(&self, server_id: ServerId) -> Result<Vec<Channel>, ServerErr> {
+        let channels = query_as(
+            r#"SELECT server_id, id, name, kind, owner_id FROM channels WHERE server_id = $1"#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    async fn get_message(&self, id: MessageId) -> Result<Option<Message>, ServerErr> {
+        let message = query_as(
+            r#"
+            SELECT
+                messages.user_id,
+                messages.channel_id,
+                messages.server_id,
+                messages.thread_id,
+                messages.ts,
+                messages.id,
+                messages.text,
+                messages.edited_ts,
+                messages.reply_to,
+                parent.user_id AS reply_user_id,
+                substr(parent.text, 1, $1) AS reply_excerpt,
+                messages.mention_everyone,
+                ARRAY(
+                    SELECT user_id FROM message_mentions
+                    WHERE message_id = messages.id AND user_id IS NOT NULL
+                ) AS mention_users,
+                ARRAY(
+                    SELECT role_id FROM message_mentions
+                    WHERE message_id = messages.id AND role_id IS NOT NULL
+                ) AS mention_roles,
+                ARRAY(
+                    SELECT channel_id FROM message_mentions
+                    WHERE message_id = messages.id AND channel_id IS NOT NULL
+                ) AS mention_channels
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.id = $2
+            "#,
+        )
+        .bind(REPLY_EXCERPT_LEN)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(message)
+    }
+
+    async fn get_messages_before(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        before: MessageId,
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr> {
+        let messages = query_as(
+            r#"
+            SELECT
+                messages.user_id,
+                messages.channel_id,
+                messages.server_id,
+                messages.thread_id,
+                messages.ts,
+                messages.id,
+                messages.text,
+                messages.edited_ts,
+                messages.reply_to,
+                parent.user_id AS reply_user_id,
+                substr(parent.text, 1, $1) AS reply_excerpt,
+                messages.mention_everyone,
+                ARRAY(
+                    SELECT user_id FROM message_mentions
+                    WHERE message_id = messages.id AND user_id IS NOT NULL
+                ) AS mention_users,
+                ARRAY(
+                    SELECT role_id FROM message_mentions
+                    WHERE message_id = messages.id AND role_id IS NOT NULL
+                ) AS mention_roles,
+                ARRAY(
+                    SELECT channel_id FROM message_mentions
+                    WHERE message_id = messages.id AND channel_id IS NOT NULL
+                ) AS mention_channels
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.channel_id = $2
+                AND messages.thread_id IS NOT DISTINCT FROM $3
+                AND messages.id < $4
+            ORDER BY messages.id DESC
+            LIMIT $5
+            "#,
+        )
+        .bind(REPLY_EXCERPT_LEN)
+        .bind(channel_id)
+        .bind(thread_id)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    async fn search_messages(&self, search: &MessageSearch) -> Result<Vec<SearchHit>, ServerErr> {
+        let options =
+            format!("StartSel={SNIPPET_START}, StopSel={SNIPPET_END}, MaxWords=16, MinWords=8");
+        let hits = query_as(
+            r#"
+            SELECT
+                messages.user_id,
+                messages.channel_id,
+                messages.server_id,
+                messages.thread_id,
+                messages.ts,
+                messages.id,
+                messages.text,
+                messages.edited_ts,
+                messages.reply_to,
+                parent.user_id AS reply_user_id,
+                substr(parent.text, 1, $1) AS reply_excerpt,
+                messages.mention_everyone,
+                ARRAY(
+                    SELECT user_id FROM message_mentions
+                    WHERE message_id = messages.id AND user_id IS NOT NULL
+                ) AS mention_users,
+                ARRAY(
+                    SELECT role_id FROM message_mentions
+                    WHERE message_id = messages.id AND role_id IS NOT NULL
+                ) AS mention_roles,
+                ARRAY(
+                    SELECT channel_id FROM message_mentions
+                    WHERE message_id = messages.id AND channel_id IS NOT NULL
+                ) AS mention_channels,
+                ts_headline('simple', messages.text, plainto_tsquery('simple', $2), $3) AS snippet
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.text_search @@ plainto_tsquery('simple', $2)
+                AND messages.channel_id = ANY($4)
+                AND ($5::INTEGER IS NULL OR messages.user_id = $5)
+                AND ($6::TIMESTAMPTZ IS NULL OR messages.ts > $6)
+                AND ($7::TIMESTAMPTZ IS NULL OR messages.ts < $7)
+                AND ($8::BOOLEAN IS NULL OR (messages.reply_to IS NOT NULL) = $8)
+                AND ($9::INTEGER IS NULL OR messages.id IN (
+                    SELECT message_id FROM message_mentions WHERE user_id = $9
+                ))
+                AND messages.id < $10
+            ORDER BY messages.id DESC
+            LIMIT $11
+            "#,
+        )
+        .bind(REPLY_EXCERPT_LEN)
+        .bind(&search.text)
+        .bind(options)
+        .bind(&search.channel_ids)
+        .bind(search.user_id)
+        .bind(search.after)
+        .bind(search.before)
+        .bind(search.has_reply)
+        .bind(search.mentions)
+        .bind(search.before_id)
+        .bind(search.limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(hits)
+    }
+
+    async fn get_messages_after(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        after: MessageId,
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr> {
+        let messages = query_as(
+            r#"
+            SELECT
+                messages.user_id,
+                messages.channel_id,
+                messages.server_id,
+                messages.thread_id,
+                messages.ts,
+                messages.id,
+                messages.text,
+                messages.edited_ts,
+                messages.reply_to,
+                parent.user_id AS reply_user_id,
+                substr(parent.text, 1, $1) AS reply_excerpt,
+                messages.mention_everyone,
+                ARRAY(
+                    SELECT user_id FROM message_mentions
+                    WHERE message_id = messages.id AND user_id IS NOT NULL
+                ) AS mention_users,
+                ARRAY(
+                    SELECT role_id FROM message_mentions
+                    WHERE message_id = messages.id AND role_id IS NOT NULL
+                ) AS mention_roles,
+                ARRAY(
+                    SELECT channel_id FROM message_mentions
+                    WHERE message_id = messages.id AND channel_id IS NOT NULL
+                ) AS mention_channels
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.channel_id = $2
+                AND messages.thread_id IS NOT DISTINCT FROM $3
+                AND messages.id > $4
+            ORDER BY messages.id
+            LIMIT $5
+            "#,
+        )
+        .bind(REPLY_EXCERPT_LEN)
+        .bind(channel_id)
+        .bind(thread_id)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    async fn get_credentials(&self, name: &str) -> Result<Option<(User, String)>, ServerErr> {
+        let row: Option<(UserId, String, String)> = query_as(
+            r#"
+            SELECT id, name, password_hash
+            FROM users
+            WHERE name = $1 AND password_hash IS NOT NULL
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(id, name, password_hash)| (User { id, name }, password_hash)))
+    }
+
+    async fn insert_session(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        created_ts: DateTime<Utc>,
+        expires_ts: DateTime<Utc>,
+    ) -> Result<(), ServerErr> {
+        query(
+            r#"
+            INSERT INTO sessions (token_hash, user_id, created_ts, expires_ts)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(created_ts)
+        .bind(expires_ts)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<User>, ServerErr> {
+        let user = query_as(
+            r#"
+            SELECT users.id, users.name
+            FROM sessions
+            JOIN users ON users.id = sessions.user_id
+            WHERE sessions.token_hash = $1 AND sessions.expires_ts > $2
+            "#,
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServerErr> {
+        query(r#"DELETE FROM sessions WHERE token_hash = $1"#)
+            .bind(token_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, ServerErr> {
+        let result = query(r#"DELETE FROM sessions WHERE expires_ts <= $1"#)
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_member(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        joined_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            INSERT INTO server_members (server_id, user_id, joined_ts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .bind(joined_ts)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_member(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM server_members WHERE server_id = $1 AND user_id = $2"#)
+            .bind(server_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_member(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)"#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    async fn get_members(&self, server_id: ServerId) -> Result<Vec<ServerMember>, ServerErr> {
+        let members = query_as(
+            r#"
+            SELECT server_id, user_id, joined_ts
+            FROM server_members
+            WHERE server_id = $1
+            ORDER BY joined_ts
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    async fn get_member_servers(&self, user_id: UserId) -> Result<Vec<ServerId>, ServerErr> {
+        let servers = query_scalar(r#"SELECT server_id FROM server_members WHERE user_id = $1"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(servers)
+    }
+
+    async fn member_count(&self, server_id: ServerId) -> Result<i64, ServerErr> {
+        let count = query_scalar(r#"SELECT COUNT(*) FROM server_members WHERE server_id = $1"#)
+            .bind(server_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    async fn insert_ban(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        banned_ts: DateTime<Utc>,
+    ) -> Result<(), ServerErr> {
+        query(
+            r#"
+            INSERT INTO server_bans (server_id, user_id, banned_ts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .bind(banned_ts)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_ban(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM server_bans WHERE server_id = $1 AND user_id = $2"#)
+            .bind(server_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_banned(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM server_bans WHERE server_id = $1 AND user_id = $2)"#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    async fn insert_role(
+        &self,
+        server_id: ServerId,
+        name: &str,
+        permissions: i64,
+        position: i32,
+    ) -> Result<RoleId, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query(
+            r#"UPDATE roles SET position = position + 1 WHERE server_id = $1 AND position >= $2"#,
+        )
+        .bind(server_id)
+        .bind(position)
+        .execute(&mut *tx)
+        .await?;
+        let id = query_scalar(
+            r#"
+            INSERT INTO roles (server_id, name, permissions, position)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+        )
+        .bind(server_id)
+        .bind(name)
+        .bind(permissions)
+        .bind(position)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn update_role(&self, id: RoleId, name: &str, permissions: i64) -> Result<(), ServerErr> {
+        query(r#"UPDATE roles SET name = $2, permissions = $3 WHERE id = $1"#)
+            .bind(id)
+            .bind(name)
+            .bind(permissions)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn move_role(
+        &self,
+        server_id: ServerId,
+        id: RoleId,
+        from: i32,
+        to: i32,
+    ) -> Result<(), ServerErr> {
+        let (low, high) = (from.min(to), from.max(to));
+        let shift = if to > from { -1 } else { 1 };
+        query(
+            r#"
+            UPDATE roles
+            SET position = CASE WHEN id = $2 THEN $3 ELSE position + $4 END
+            WHERE server_id = $1 AND position BETWEEN $5 AND $6
+            "#,
+        )
+        .bind(server_id)
+        .bind(id)
+        .bind(to)
+        .bind(shift)
+        .bind(low)
+        .bind(high)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_role(
+        &self,
+        server_id: ServerId,
+        id: RoleId,
+        position: i32,
+    ) -> Result<(), ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query(r#"DELETE FROM roles WHERE id = $1"#)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        query(r#"UPDATE roles SET position = position - 1 WHERE server_id = $1 AND position > $2"#)
+            .bind(server_id)
+            .bind(position)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_role(&self, id: RoleId) -> Result<Option<Role>, ServerErr> {
+        let role = query_as(
+            r#"SELECT id, server_id, name, permissions, position FROM roles WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(role)
+    }
+
+    async fn get_roles(&self, server_id: ServerId) -> Result<Vec<Role>, ServerErr> {
+        let roles = query_as(
+            r#"
+            SELECT id, server_id, name, permissions, position
+            FROM roles
+            WHERE server_id = $1
+            ORDER BY position
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(roles)
+    }
+
+    async fn get_member_roles(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+    ) -> Result<Vec<Role>, ServerErr> {
+        let roles = query_as(
+            r#"
+            SELECT id, server_id, name, permissions, position
+            FROM roles
+            WHERE server_id = $1 AND (
+                position = 0
+                OR id IN (SELECT role_id FROM member_roles WHERE server_id = $1 AND user_id = $2)
+            )
+            ORDER BY position
+            "#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(roles)
+    }
+
+    async fn get_all_member_roles(
+        &self,
+        server_id: ServerId,
+    ) -> Result<Vec<MemberRole>, ServerErr> {
+        let member_roles = query_as(
+            r#"SELECT server_id, user_id, role_id FROM member_roles WHERE server_id = $1"#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(member_roles)
+    }
+
+    async fn insert_member_role(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            INSERT INTO member_roles (server_id, user_id, role_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .bind(role_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_member_role(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"DELETE FROM member_roles WHERE server_id = $1 AND user_id = $2 AND role_id = $3"#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .bind(role_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn upsert_overwrite(&self, overwrite: &ChannelOverwrite) -> Result<(), ServerErr> {
+        let conflict = if overwrite.role_id.is_some() {
+            "(channel_id, role_id)"
+        } else {
+            "(channel_id, user_id)"
+        };
+        query(&format!(
+            r#"
+            INSERT INTO channel_overwrites (channel_id, role_id, user_id, allow, deny)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT {conflict} DO UPDATE SET allow = $4, deny = $5
+            "#
+        ))
+        .bind(overwrite.channel_id)
+        .bind(overwrite.role_id)
+        .bind(overwrite.user_id)
+        .bind(overwrite.allow)
+        .bind(overwrite.deny)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_overwrite(
+        &self,
+        channel_id: ChannelId,
+        role_id: Option<RoleId>,
+        user_id: Option<UserId>,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            DELETE FROM channel_overwrites
+            WHERE channel_id = $1
+                AND role_id IS NOT DISTINCT FROM $2
+                AND user_id IS NOT DISTINCT FROM $3
+            "#,
+        )
+        .bind(channel_id)
+        .bind(role_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_overwrites(
+        &self,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ChannelOverwrite>, ServerErr> {
+        let overwrites = query_as(
+            r#"
+            SELECT channel_id, role_id, user_id, allow, deny
+            FROM channel_overwrites
+            WHERE channel_id = $1
+            "#,
+        )
+        .bind(channel_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(overwrites)
+    }
+
+    async fn get_server_overwrites(
+        &self,
+        server_id: ServerId,
+    ) -> Result<Vec<ChannelOverwrite>, ServerErr> {
+        let overwrites = query_as(
+            r#"
+            SELECT
+                channel_overwrites.channel_id,
+                channel_overwrites.role_id,
+                channel_overwrites.user_id,
+                channel_overwrites.allow,
+                channel_overwrites.deny
+            FROM channel_overwrites
+            JOIN channels ON channels.id = channel_overwrites.channel_id
+            WHERE channels.server_id = $1
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(overwrites)
+    }
+
+    async fn insert_thread(&self, thread: &Thread) -> Result<ThreadId, ServerErr> {
+        let id = query_scalar(
+            r#"
+            INSERT INTO threads (channel_id, server_id, name, starter_message_id, creator_id, created_ts, last_active_ts, archived, locked)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id
+            "#,
+        )
+        .bind(thread.channel_id)
+        .bind(thread.server_id)
+        .bind(&thread.name)
+        .bind(thread.starter_message_id)
+        .bind(thread.creator_id)
+        .bind(thread.created_ts)
+        .bind(thread.last_active_ts)
+        .bind(thread.archived)
+        .bind(thread.locked)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_thread(&self, id: ThreadId) -> Result<Option<Thread>, ServerErr> {
+        let thread = query_as(
+            r#"
+            SELECT
+                id,
+                channel_id,
+                server_id,
+                name,
+                starter_message_id,
+                creator_id,
+                created_ts,
+                last_active_ts,
+                archived,
+                locked
+            FROM threads
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(thread)
+    }
+
+    async fn get_threads(
+        &self,
+        channel_id: ChannelId,
+        archived: bool,
+    ) -> Result<Vec<Thread>, ServerErr> {
+        let threads = query_as(
+            r#"
+            SELECT
+                id,
+                channel_id,
+                server_id,
+                name,
+                starter_message_id,
+                creator_id,
+                created_ts,
+                last_active_ts,
+                archived,
+                locked
+            FROM threads
+            WHERE channel_id = $1 AND archived = $2
+            ORDER BY last_active_ts DESC
+            "#,
+        )
+        .bind(channel_id)
+        .bind(archived)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(threads)
+    }
+
+    async fn get_active_threads(
+        &self,
+        channel_ids: &[ChannelId],
+    ) -> Result<Vec<Thread>, ServerErr> {
+        let threads = query_as(
+            r#"
+            SELECT
+                id,
+                channel_id,
+                server_id,
+                name,
+                starter_message_id,
+                creator_id,
+                created_ts,
+                last_active_ts,
+                archived,
+                locked
+            FROM threads
+            WHERE NOT archived AND channel_id = ANY($1)
+            ORDER BY last_active_ts DESC
+            "#,
+        )
+        .bind(channel_ids)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(threads)
+    }
+
+    async fn archive_thread(&self, id: ThreadId) -> Result<bool, ServerErr> {
+        let result = query(r#"UPDATE threads SET archived = TRUE WHERE id = $1 AND NOT archived"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn touch_thread(&self, id: ThreadId, ts: DateTime<Utc>) -> Result<(), ServerErr> {
+        query(r#"UPDATE threads SET archived = FALSE, last_active_ts = $2 WHERE id = $1"#)
+            .bind(id)
+            .bind(ts)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_thread_locked(&self, id: ThreadId, locked: bool) -> Result<(), ServerErr> {
+        query(r#"UPDATE threads SET locked = $2 WHERE id = $1"#)
+            .bind(id)
+            .bind(locked)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn archive_inactive_threads(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Thread>, ServerErr> {
+        let threads = query_as(
+            r#"
+            UPDATE threads SET archived = TRUE
+            WHERE NOT archived AND last_active_ts < $1
+            RETURNING
+                id,
+                channel_id,
+                server_id,
+                name,
+                starter_message_id,
+                creator_id,
+                created_ts,
+                last_active_ts,
+                archived,
+                locked
+            "#,
+        )
+        .bind(before)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(threads)
+    }
+
+    async fn insert_thread_member(
+        &self,
+        thread_id: ThreadId,
+        user_id: UserId,
+        joined_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            INSERT INTO thread_members (thread_id, user_id, joined_ts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(thread_id)
+        .bind(user_id)
+        .bind(joined_ts)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_thread_member(
+        &self,
+        thread_id: ThreadId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM thread_members WHERE thread_id = $1 AND user_id = $2"#)
+            .bind(thread_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_thread_members(
+        &self,
+        thread_id: ThreadId,
+    ) -> Result<Vec<ThreadMember>, ServerErr> {
+        let members = query_as(
+            r#"
+            SELECT thread_id, user_id, joined_ts
+            FROM thread_members
+            WHERE thread_id = $1
+            ORDER BY joined_ts
+            "#,
+        )
+        .bind(thread_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    async fn insert_dm(
+        &self,
+        channel: &Channel,
+        recipients: &[UserId],
+        added_ts: DateTime<Utc>,
+    ) -> Result<ChannelId, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        let id: ChannelId = query_scalar(
+            r#"INSERT INTO channels (name, kind, owner_id) VALUES ($1, $2, $3) RETURNING id"#,
+        )
+        .bind(&channel.name)
+        .bind(channel.kind)
+        .bind(channel.owner_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        query(
+            r#"
+            INSERT INTO channel_recipients (channel_id, user_id, added_ts)
+            SELECT $1, user_id, $3 FROM UNNEST($2::INTEGER[]) AS user_id
+            "#,
+        )
+        .bind(id)
+        .bind(recipients)
+        .bind(added_ts)
+        .execute(&mut *tx)
+        .await?;
+        if let (ChannelKind::Dm, &[a, b]) = (channel.kind, recipients) {
+            query(r#"INSERT INTO dm_pairs (user_id, other_id, channel_id) VALUES ($1, $2, $3)"#)
+                .bind(a.min(b))
+                .bind(a.max(b))
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn get_dm(
+        &self,
+        user_id: UserId,
+        other_id: UserId,
+    ) -> Result<Option<Channel>, ServerErr> {
+        let channel = query_as(
+            r#"
+            SELECT server_id, id, name, kind, owner_id
+            FROM channels
+            WHERE id = (
+                SELECT channel_id FROM dm_pairs
+                WHERE user_id = LEAST($1, $2) AND other_id = GREATEST($1, $2)
+            )
+            "#,
+        )
+        .bind(user_id)
+        .bind(other_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(channel)
+    }
+
+    async fn get_dm_channels(&self, user_id: UserId) -> Result<Vec<Channel>, ServerErr> {
+        let channels = query_as(
+            r#"
+            SELECT server_id, id, name, kind, owner_id
+            FROM channels
+            WHERE id IN (SELECT channel_id FROM channel_recipients WHERE user_id = $1)
+            ORDER BY id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    async fn get_recipients(&self, channel_id: ChannelId) -> Result<Vec<UserId>, ServerErr> {
+        let user_ids = query_scalar(
+            r#"
+            SELECT user_id FROM channel_recipients
+            WHERE channel_id = $1
+            ORDER BY added_ts, user_id
+            "#,
+        )
+        .bind(channel_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(user_ids)
+    }
+
+    async fn is_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let exists = query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM channel_recipients WHERE channel_id = $1 AND user_id = $2)"#,
+        )
+        .bind(channel_id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    async fn insert_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+        added_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query(
+            r#"
+            INSERT INTO channel_recipients (channel_id, user_id, added_ts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(channel_id)
+        .bind(user_id)
+        .bind(added_ts)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let result =
+            query(r#"DELETE FROM channel_recipients WHERE channel_id = $1 AND user_id = $2"#)
+                .bind(channel_id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_channel_owner(&self, id: ChannelId, owner_id: UserId) -> Result<(), ServerErr> {
+        query(r#"UPDATE channels SET owner_id = $2 WHERE id = $1"#)
+            .bind(id)
+            .bind(owner_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_channel(&self, id: ChannelId) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM channels WHERE id = $1"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn shares_server(&self, user_id: UserId, other_id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM server_members AS mine
+                JOIN server_members AS theirs ON theirs.server_id = mine.server_id
+                WHERE mine.user_id = $1 AND theirs.user_id = $2
+            )
+            "#,
+        )
+        .bind(user_id)
+        .bind(other_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    async fn insert_invite(&self, invite: &Invite) -> Result<(), ServerErr> {
+        query(
+            r#"
+            INSERT INTO invites (code, server_id, channel_id, creator_id, created_ts, expires_ts, max_uses, uses)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(&invite.code)
+        .bind(invite.server_id)
+        .bind(invite.channel_id)
+        .bind(invite.creator_id)
+        .bind(invite.created_ts)
+        .bind(invite.expires_ts)
+        .bind(invite.max_uses)
+        .bind(invite.uses)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_invite(&self, code: &str) -> Result<Option<Invite>, ServerErr> {
+        let invite = query_as(
+            r#"
+            SELECT code, server_id, channel_id, creator_id, created_ts, expires_ts, max_uses, uses
+            FROM invites
+            WHERE code = $1
+            "#,
+        )
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(invite)
+    }
+
+    async fn get_invites(&self, server_id: ServerId) -> Result<Vec<Invite>, ServerErr> {
+        let invites = query_as(
+            r#"
+            SELECT code, server_id, channel_id, creator_id, created_ts, expires_ts, max_uses, uses
+            FROM invites
+            WHERE server_id = $1
+            ORDER BY created_ts
+            "#,
+        )
+        .bind(server_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invites)
+    }
+
+    async fn redeem_invite(
+        &self,
+        code: &str,
+        server_id: ServerId,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        let used = query(
+            r#"
+            UPDATE invites SET uses = uses + 1
+            WHERE code = $1
+                AND (expires_ts IS NULL OR expires_ts > $2)
+                AND (max_uses IS NULL OR uses < max_uses)
+            "#,
+        )
+        .bind(code)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        if used.rows_affected() == 0 {
+            return Ok(false);
+        }
+        let joined = query(
+            r#"
+            INSERT INTO server_members (server_id, user_id, joined_ts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(server_id)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        if joined.rows_affected() == 0 {
+            return Ok(false);
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete_invite(&self, code: &str) -> Result<bool, ServerErr> {
+        let result = query(r#"DELETE FROM invites WHERE code = $1"#)
+            .bind(code)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_users(&self, limit: i64) -> Result<Vec<User>, ServerErr> {
+        let users = query_as(r#"SELECT id, name FROM users LIMIT $1"#)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(users)
+    }
+
+    async fn get_servers(&self, user_id: UserId) -> Result<Vec<Server>, ServerErr> {
+        let servers = query_as(
+            r#"
+            SELECT id, name, owner_id FROM servers
+            WHERE id IN (SELECT server_id FROM server_members WHERE user_id = $1)
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(servers)
+    }
+
+    async fn get_channels(&self, user_id: UserId) -> Result<Vec<Channel>, ServerErr> {
+        let channels = query_as(
+            r#"
+            SELECT server_id, id, name, kind, owner_id FROM channels
+            WHERE server_id IN (SELECT server_id FROM server_members WHERE user_id = $1)
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    async fn get_latest_messages(
+        &self,
+        channel_ids: &[ChannelId],
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr> {
+        let messages = query_as(
+            r#"
+            WITH latest AS (
+                SELECT id, ROW_NUMBER() OVER (PARTITION BY channel_id ORDER BY id DESC) AS n
+                FROM messages
+                WHERE channel_id = ANY($2)
+            )
+            SELECT
+                messages.user_id,
+                messages.channel_id,
+                messages.server_id,
+                messages.thread_id,
+                messages.ts,
+                messages.id,
+                messages.text,
+                messages.edited_ts,
+                messages.reply_to,
+                parent.user_id AS reply_user_id,
+                substr(parent.text, 1, $1) AS reply_excerpt,
+                messages.mention_everyone,
+                ARRAY(
+                    SELECT user_id FROM message_mentions
+                    WHERE message_id = messages.id AND user_id IS NOT NULL
+                ) AS mention_users,
+                ARRAY(
+                    SELECT role_id FROM message_mentions
+                    WHERE message_id = messages.id AND role_id IS NOT NULL
+                ) AS mention_roles,
+                ARRAY(
+                    SELECT channel_id FROM message_mentions
+                    WHERE message_id = messages.id AND channel_id IS NOT NULL
+                ) AS mention_channels
+            FROM latest
+            JOIN messages ON messages.id = latest.id
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE latest.n <= $3
+            ORDER BY messages.id DESC
+            "#,
+        )
+        .bind(REPLY_EXCERPT_LEN)
+        .bind(channel_ids)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    async fn insert_logged_update(
+        &self,
+        seq: i64,
+        ts: DateTime<Utc>,
+        data: &str,
+    ) -> Result<(), ServerErr> {
+        query(r#"INSERT INTO update_log (seq, ts, data) VALUES ($1, $2, $3)"#)
+            .bind(seq)
+            .bind(ts)
+            .bind(data)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_last_logged_update(&self) -> Result<Option<i64>, ServerErr> {
+        let seq = query_scalar(r#"SELECT MAX(seq) FROM update_log"#)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(seq)
+    }
+
+    async fn get_logged_updates(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<(i64, String)>, ServerErr> {
+        let rows = query_as(
+            r#"
+            SELECT seq, data
+            FROM update_log
+            WHERE seq > $1
+            ORDER BY seq
+            LIMIT $2
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn prune_logged_updates(&self, through: i64) -> Result<(), ServerErr> {
+        query(r#"DELETE FROM update_log WHERE seq <= $1"#)
+            .bind(through)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,2214 @@
+use super::Storage;
+use crate::{
+    attachment::{Attachment, AttachmentId},
+    channel::{Channel, ChannelId, ChannelKind},
+    emoji::{Emoji, EmojiId},
+    error::ServerErr,
+    invite::Invite,
+    member::ServerMember,
+    mention::Mentions,
+    message::{Message, MessageEdit, MessageId, REPLY_EXCERPT_LEN},
+    overwrite::ChannelOverwrite,
+    reaction::ReactionCount,
+    read_state::ReadState,
+    role::{MemberRole, Role, RoleId},
+    search::{MessageSearch, SearchHit, SNIPPET_END, SNIPPET_START},
+    server::{Server, ServerId},
+    thread::{Thread, ThreadId, ThreadMember},
+    user::{User, UserId},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{query, query_as, query_scalar, Sqlite, SqlitePool, Transaction};
+
+/// A `Message` as stored. `query_as!` can't skip fields, so reactions and
+/// attachments are left out here and attached separately.
+struct MessageRow {
+    user_id: UserId,
+    channel_id: ChannelId,
+    server_id: Option<ServerId>,
+    thread_id: Option<ThreadId>,
+    ts: DateTime<Utc>,
+    id: MessageId,
+    text: String,
+    edited_ts: Option<DateTime<Utc>>,
+    reply_to: Option<MessageId>,
+    reply_user_id: Option<UserId>,
+    reply_excerpt: Option<String>,
+    mention_everyone: bool,
+    /// A JSON object of `MentionLists`.
+    mentions: String,
+}
+
+/// The mentioned IDs of a message, gathered into JSON arrays by the query.
+#[derive(Deserialize)]
+struct MentionLists {
+    users: Vec<UserId>,
+    roles: Vec<RoleId>,
+    channels: Vec<ChannelId>,
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        let lists: MentionLists =
+            serde_json::from_str(&row.mentions).expect("mentions are built as JSON");
+        Self {
+            user_id: row.user_id,
+            channel_id: row.channel_id,
+            server_id: row.server_id,
+            thread_id: row.thread_id,
+            ts: row.ts,
+            id: row.id,
+            text: row.text,
+            edited_ts: row.edited_ts,
+            reply_to: row.reply_to,
+            reply_user_id: row.reply_user_id,
+            reply_excerpt: row.reply_excerpt,
+            mentions: Mentions {
+                users: lists.users,
+                roles: lists.roles,
+                channels: lists.channels,
+                everyone: row.mention_everyone,
+            },
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+}
+
+/// A `MessageRow` along with its search snippet.
+struct SearchRow {
+    user_id: UserId,
+    channel_id: ChannelId,
+    server_id: Option<ServerId>,
+    thread_id: Option<ThreadId>,
+    ts: DateTime<Utc>,
+    id: MessageId,
+    text: String,
+    edited_ts: Option<DateTime<Utc>>,
+    reply_to: Option<MessageId>,
+    reply_user_id: Option<UserId>,
+    reply_excerpt: Option<String>,
+    mention_everyone: bool,
+    mentions: String,
+    snippet: String,
+}
+
+impl From<SearchRow> for SearchHit {
+    fn from(row: SearchRow) -> Self {
+        Self {
+            message: Message::from(MessageRow {
+                user_id: row.user_id,
+                channel_id: row.channel_id,
+                server_id: row.server_id,
+                thread_id: row.thread_id,
+                ts: row.ts,
+                id: row.id,
+                text: row.text,
+                edited_ts: row.edited_ts,
+                reply_to: row.reply_to,
+                reply_user_id: row.reply_user_id,
+                reply_excerpt: row.reply_excerpt,
+                mention_everyone: row.mention_everyone,
+                mentions: row.mentions,
+            }),
+            snippet: row.snippet,
+        }
+    }
+}
+
+/// Quotes each word of the search so FTS5 matches them all literally rather
+/// than parsing its query syntax.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Adds a row per mentioned user, role and channel.
+async fn insert_mentions(
+    tx: &mut Transaction<'_, Sqlite>,
+    message_id: MessageId,
+    mentions: &Mentions,
+) -> Result<(), ServerErr> {
+    // SQLite can't bind a list, so the IDs go in as JSON arrays.
+    let users = serde_json::to_string(&mentions.users).expect("IDs serialize to JSON");
+    let roles = serde_json::to_string(&mentions.roles).expect("IDs serialize to JSON");
+    let channels = serde_json::to_string(&mentions.channels).expect("IDs serialize to JSON");
+    query!(
+        r#"
+        INSERT INTO message_mentions (message_id, user_id, role_id, channel_id)
+        SELECT ?1, value, NULL, NULL FROM json_each(?2)
+        UNION ALL SELECT ?1, NULL, value, NULL FROM json_each(?3)
+        UNION ALL SELECT ?1, NULL, NULL, value FROM json_each(?4);
+        "#,
+        message_id,
+        users,
+        roles,
+        channels
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn insert_user(&self, name: &str, password_hash: &str) -> Result<UserId, ServerErr> {
+        let id = query!(
+            r#"
+            INSERT INTO users (name, password_hash)
+            VALUES (?1, ?2)
+            RETURNING id AS "id!: i32";
+            "#,
+            name,
+            password_hash
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .id;
+        Ok(id)
+    }
+
+    async fn insert_server(
+        &self,
+        name: &str,
+        owner_id: Option<UserId>,
+    ) -> Result<ServerId, ServerErr> {
+        let id = query!(
+            r#"
+            INSERT INTO servers (name, owner_id)
+            VALUES ($1, $2)
+            RETURNING id AS "id!: i32"
+            "#,
+            name,
+            owner_id
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .id;
+        Ok(id)
+    }
+
+    async fn insert_channel(
+        &self,
+        server_id: ServerId,
+        name: &str,
+    ) -> Result<ChannelId, ServerErr> {
+        let id = query!(
+            r#"
+            INSERT INTO channels (server_id, name)
+            VALUES (?1, ?2)
+            RETURNING id AS "id!: i32";
+            "#,
+            server_id,
+            name
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .id;
+        Ok(id)
+    }
+
+    async fn insert_message(&self, message: &Message) -> Result<MessageId, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        let id = query!(
+            r#"
+            INSERT INTO messages (
+                user_id, channel_id, server_id, thread_id, text, ts, reply_to, mention_everyone
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id;
+            "#,
+            message.user_id,
+            message.channel_id,
+            message.server_id,
+            message.thread_id,
+            message.text,
+            message.ts,
+            message.reply_to,
+            message.mentions.everyone
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .id;
+        insert_mentions(&mut tx, id, &message.mentions).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn edit_message(
+        &self,
+        id: MessageId,
+        text: &str,
+        edited_ts: DateTime<Utc>,
+        mentions: &Mentions,
+    ) -> Result<(), ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query!(
+            r#"
+            INSERT INTO message_edits (message_id, text, edited_ts)
+            SELECT id, text, ?2 FROM messages WHERE id = ?1;
+            "#,
+            id,
+            edited_ts
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"UPDATE messages SET text = ?2, edited_ts = ?3, mention_everyone = ?4 WHERE id = ?1;"#,
+            id,
+            text,
+            edited_ts,
+            mentions.everyone
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(r#"DELETE FROM message_mentions WHERE message_id = ?1;"#, id)
+            .execute(&mut *tx)
+            .await?;
+        insert_mentions(&mut tx, id, mentions).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, id: MessageId) -> Result<bool, ServerErr> {
+        let result = query!(r#"DELETE FROM messages WHERE id = ?1;"#, id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_messages(
+        &self,
+        channel_id: ChannelId,
+        ids: &[MessageId],
+    ) -> Result<Vec<MessageId>, ServerErr> {
+        // SQLite can't bind a list, so the IDs go in as a JSON array.
+        let ids = serde_json::to_string(ids).expect("IDs serialize to JSON");
+        let deleted = query_scalar!(
+            r#"
+            DELETE FROM messages
+            WHERE channel_id = ?1 AND id IN (SELECT value FROM json_each(?2))
+            RETURNING id AS "id!: i64";
+            "#,
+            channel_id,
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(deleted)
+    }
+
+    async fn purge_messages(
+        &self,
+        channel_id: ChannelId,
+        user_id: Option<UserId>,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<MessageId>, ServerErr> {
+        let deleted = query_scalar!(
+            r#"
+            DELETE FROM messages
+            WHERE id IN (
+                SELECT id FROM messages
+                WHERE channel_id = ?1
+                    AND (?2 IS NULL OR user_id = ?2)
+                    AND (?3 IS NULL OR ts > ?3)
+                    AND (?4 IS NULL OR ts < ?4)
+                ORDER BY id DESC
+                LIMIT ?5
+            )
+            RETURNING id AS "id!: i64";
+            "#,
+            channel_id,
+            user_id,
+            after,
+            before,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(deleted)
+    }
+
+    async fn get_message_edits(&self, id: MessageId) -> Result<Vec<MessageEdit>, ServerErr> {
+        let edits = query_as!(
+            MessageEdit,
+            r#"
+            SELECT
+                message_id,
+                text,
+                edited_ts AS "edited_ts!: DateTime<Utc>"
+            FROM message_edits
+            WHERE message_id = ?1
+            ORDER BY id;
+            "#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(edits)
+    }
+
+    async fn insert_reaction(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        emoji: Option<&str>,
+        emoji_id: Option<EmojiId>,
+        created_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            INSERT INTO reactions (message_id, user_id, emoji, emoji_id, created_ts)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT DO NOTHING;
+            "#,
+            message_id,
+            user_id,
+            emoji,
+            emoji_id,
+            created_ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_reaction(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        emoji: Option<&str>,
+        emoji_id: Option<EmojiId>,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            DELETE FROM reactions
+            WHERE message_id = ?1 AND user_id = ?2 AND emoji IS ?3 AND emoji_id IS ?4;
+            "#,
+            message_id,
+            user_id,
+            emoji,
+            emoji_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_reaction_counts(
+        &self,
+        message_ids: &[MessageId],
+        user_id: UserId,
+    ) -> Result<Vec<(MessageId, ReactionCount)>, ServerErr> {
+        // SQLite can't bind a list, so the IDs go in as a JSON array.
+        let ids = serde_json::to_string(message_ids).expect("IDs serialize to JSON");
+        let rows = query!(
+            r#"
+            SELECT
+                message_id AS "message_id!: i64",
+                emoji,
+                emoji_id AS "emoji_id: i32",
+                COUNT(*) AS "count!: i64",
+                MAX(user_id = ?2) AS "me!: bool"
+            FROM reactions
+            WHERE message_id IN (SELECT value FROM json_each(?1))
+            GROUP BY message_id, emoji, emoji_id
+            ORDER BY message_id, MIN(created_ts);
+            "#,
+            ids,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.message_id,
+                    ReactionCount {
+                        emoji: row.emoji,
+                        emoji_id: row.emoji_id,
+                        count: row.count,
+                        me: row.me,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    async fn insert_attachment(&self, attachment: &Attachment) -> Result<AttachmentId, ServerErr> {
+        // Only images have anything for the workers to do.
+        let processed = !attachment.is_image();
+        let id = query_scalar!(
+            r#"
+            INSERT INTO attachments (message_id, filename, content_type, size, hash, processed)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            RETURNING id AS "id!: i32";
+            "#,
+            attachment.message_id,
+            attachment.filename,
+            attachment.content_type,
+            attachment.size,
+            attachment.hash,
+            processed
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_attachment(&self, id: AttachmentId) -> Result<Option<Attachment>, ServerErr> {
+        let attachment = query_as!(
+            Attachment,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                message_id AS "message_id!: i64",
+                filename,
+                content_type,
+                size AS "size!: i64",
+                hash,
+                width AS "width: i32",
+                height AS "height: i32",
+                thumbnail_hash,
+                NULL AS "thumbnail_url: String"
+            FROM attachments
+            WHERE id = ?1;
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(attachment)
+    }
+
+    async fn get_attachments(
+        &self,
+        message_ids: &[MessageId],
+    ) -> Result<Vec<Attachment>, ServerErr> {
+        // SQLite can't bind a list, so the IDs go in as a JSON array.
+        let ids = serde_json::to_string(message_ids).expect("IDs serialize to JSON");
+        let attachments = query_as!(
+            Attachment,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                message_id AS "message_id!: i64",
+                filename,
+                content_type,
+                size AS "size!: i64",
+                hash,
+                width AS "width: i32",
+                height AS "height: i32",
+                thumbnail_hash,
+                NULL AS "thumbnail_url: String"
+            FROM attachments
+            WHERE message_id IN (SELECT value FROM json_each(?1))
+            ORDER BY message_id, id;
+            "#,
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(attachments)
+    }
+
+    async fn get_unprocessed_attachments(&self) -> Result<Vec<Attachment>, ServerErr> {
+        let attachments = query_as!(
+            Attachment,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                message_id AS "message_id!: i64",
+                filename,
+                content_type,
+                size AS "size!: i64",
+                hash,
+                width AS "width: i32",
+                height AS "height: i32",
+                thumbnail_hash,
+                NULL AS "thumbnail_url: String"
+            FROM attachments
+            WHERE NOT processed
+            ORDER BY id;
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(attachments)
+    }
+
+    async fn update_attachment_image(&self, attachment: &Attachment) -> Result<(), ServerErr> {
+        query!(
+            r#"
+            UPDATE attachments
+            SET content_type = ?2, width = ?3, height = ?4, thumbnail_hash = ?5, processed = TRUE
+            WHERE id = ?1;
+            "#,
+            attachment.id,
+            attachment.content_type,
+            attachment.width,
+            attachment.height,
+            attachment.thumbnail_hash
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_emoji(&self, emoji: &Emoji) -> Result<EmojiId, ServerErr> {
+        let id = query_scalar!(
+            r#"
+            INSERT INTO emojis (server_id, name, creator_id, created_ts)
+            VALUES (?1, ?2, ?3, ?4)
+            RETURNING id AS "id!: i32";
+            "#,
+            emoji.server_id,
+            emoji.name,
+            emoji.creator_id,
+            emoji.created_ts
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_emoji(&self, id: EmojiId) -> Result<Option<Emoji>, ServerErr> {
+        let emoji = query_as!(
+            Emoji,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>"
+            FROM emojis
+            WHERE id = ?1;
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(emoji)
+    }
+
+    async fn get_emojis(&self, server_id: ServerId) -> Result<Vec<Emoji>, ServerErr> {
+        let emojis = query_as!(
+            Emoji,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>"
+            FROM emojis
+            WHERE server_id = ?1
+            ORDER BY name;
+            "#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(emojis)
+    }
+
+    async fn delete_emoji(&self, id: EmojiId) -> Result<bool, ServerErr> {
+        let result = query!(r#"DELETE FROM emojis WHERE id = ?1;"#, id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn add_mention_counts(&self, message: &Message) -> Result<(), ServerErr> {
+        // SQLite can't bind a list, so the IDs go in as JSON arrays.
+        let users = serde_json::to_string(&message.mentions.users).expect("IDs serialize to JSON");
+        let roles = serde_json::to_string(&message.mentions.roles).expect("IDs serialize to JSON");
+        query!(
+            r#"
+            INSERT INTO read_states (user_id, channel_id, mention_count)
+            SELECT user_id, ?1, 1 FROM (
+                SELECT user_id FROM server_members WHERE server_id = ?2
+                UNION SELECT user_id FROM channel_recipients WHERE channel_id = ?1
+            )
+            WHERE user_id <> ?3 AND (
+                ?4
+                OR user_id IN (SELECT value FROM json_each(?5))
+                OR user_id IN (
+                    SELECT user_id FROM member_roles
+                    WHERE server_id = ?2 AND role_id IN (SELECT value FROM json_each(?6))
+                )
+            )
+            ON CONFLICT (user_id, channel_id) DO UPDATE SET mention_count = mention_count + 1;
+            "#,
+            message.channel_id,
+            message.server_id,
+            message.user_id,
+            message.mentions.everyone,
+            users,
+            roles
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn ack_channel(
+        &self,
+        user_id: UserId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<(), ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query!(
+            r#"
+            INSERT INTO read_states (user_id, channel_id, last_read_message_id)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (user_id, channel_id) DO UPDATE SET last_read_message_id =
+                MAX(COALESCE(last_read_message_id, 0), excluded.last_read_message_id);
+            "#,
+            user_id,
+            channel_id,
+            message_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            r#"
+            UPDATE read_states SET mention_count = (
+                SELECT COUNT(*) FROM messages
+                WHERE messages.channel_id = ?2
+                    AND messages.id > read_states.last_read_message_id
+                    AND messages.user_id <> ?1
+                    AND (messages.mention_everyone OR messages.id IN (
+                        SELECT message_id FROM message_mentions
+                        WHERE user_id = ?1
+                            OR role_id IN (SELECT role_id FROM member_roles WHERE user_id = ?1)
+                    ))
+            )
+            WHERE user_id = ?1 AND channel_id = ?2;
+            "#,
+            user_id,
+            channel_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_read_states(
+        &self,
+        user_id: UserId,
+        channel_ids: &[ChannelId],
+    ) -> Result<Vec<ReadState>, ServerErr> {
+        // SQLite can't bind a list, so the IDs go in as a JSON array.
+        let ids = serde_json::to_string(channel_ids).expect("IDs serialize to JSON");
+        let read_states = query_as!(
+            ReadState,
+            r#"
+            SELECT
+                ?1 AS "user_id!: i32",
+                channels.id AS "channel_id!: i32",
+                channels.server_id AS "server_id: i32",
+                read_states.last_read_message_id AS "last_read_message_id: i64",
+                (
+                    SELECT COUNT(*) FROM messages
+                    WHERE messages.channel_id = channels.id
+                        AND messages.id > COALESCE(read_states.last_read_message_id, 0)
+                ) AS "unread_count!: i64",
+                COALESCE(read_states.mention_count, 0) AS "mention_count!: i64"
+            FROM channels
+            LEFT JOIN read_states
+                ON read_states.channel_id = channels.id AND read_states.user_id = ?1
+            WHERE channels.id IN (SELECT value FROM json_each(?2))
+            ORDER BY channels.id;
+            "#,
+            user_id,
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(read_states)
+    }
+
+    async fn user_exists(&self, id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = ?1);"#, id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists == 1)
+    }
+
+    async fn server_exists(&self, id: ServerId) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM servers WHERE id = ?1);"#, id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists == 1)
+    }
+
+    async fn channel_exists(&self, id: ChannelId) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM channels WHERE id = ?1);"#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists == 1)
+    }
+
+    async fn has_servers(&self) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM servers);"#)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists == 1)
+    }
+
+    async fn get_server(&self, id: ServerId) -> Result<Option<Server>, ServerErr> {
+        let server = query_as!(
+            Server,
+            r#"SELECT id AS "id!: i32", name, owner_id AS "owner_id: i32" FROM servers WHERE id = ?1;"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(server)
+    }
+
+    async fn get_channel(&self, id: ChannelId) -> Result<Option<Channel>, ServerErr> {
+        let channel = query_as!(
+            Channel,
+            r#"SELECT server_id AS "server_id: i32", id AS "id!: i32", name, kind AS "kind: ChannelKind", owner_id AS "owner_id: i32" FROM channels WHERE id = ?1;"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(channel)
+    }
+
+    async fn get_server_channels(&self, server_id: ServerId) -> Result<Vec<Channel>, ServerErr> {
+        let channels = query_as!(
+            Channel,
+            r#"SELECT server_id AS "server_id: i32", id AS "id!: i32", name, kind AS "kind: ChannelKind", owner_id AS "owner_id: i32" FROM channels WHERE server_id = ?1;"#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    async fn get_message(&self, id: MessageId) -> Result<Option<Message>, ServerErr> {
+        let message = query_as!(
+            MessageRow,
+            r#"
+            SELECT
+                messages.user_id AS "user_id!: i32",
+                messages.channel_id AS "channel_id!: i32",
+                messages.server_id AS "server_id: i32",
+                messages.thread_id AS "thread_id: i32",
+                messages.ts AS "ts!: DateTime<Utc>",
+                messages.id AS "id!: i64",
+                messages.text AS "text!: String",
+                messages.edited_ts AS "edited_ts: DateTime<Utc>",
+                messages.reply_to,
+                parent.user_id AS "reply_user_id: i32",
+                substr(parent.text, 1, ?1) AS "reply_excerpt: String",
+                messages.mention_everyone AS "mention_everyone!: bool",
+                (
+                    SELECT json_object(
+                        'users', json_group_array(user_id) FILTER (WHERE user_id IS NOT NULL),
+                        'roles', json_group_array(role_id) FILTER (WHERE role_id IS NOT NULL),
+                        'channels', json_group_array(channel_id) FILTER (WHERE channel_id IS NOT NULL)
+                    )
+                    FROM message_mentions
+                    WHERE message_id = messages.id
+                ) AS "mentions!: String"
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.id = ?2;
+            "#,
+            REPLY_EXCERPT_LEN,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(message.map(Message::from))
+    }
+
+    async fn get_messages_before(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        before: MessageId,
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr> {
+        let messages = query_as!(
+            MessageRow,
+            r#"
+            SELECT
+                messages.user_id AS "user_id!: i32",
+                messages.channel_id AS "channel_id!: i32",
+                messages.server_id AS "server_id: i32",
+                messages.thread_id AS "thread_id: i32",
+                messages.ts AS "ts!: DateTime<Utc>",
+                messages.id AS "id!: i64",
+                messages.text AS "text!: String",
+                messages.edited_ts AS "edited_ts: DateTime<Utc>",
+                messages.reply_to,
+                parent.user_id AS "reply_user_id: i32",
+                substr(parent.text, 1, ?1) AS "reply_excerpt: String",
+                messages.mention_everyone AS "mention_everyone!: bool",
+                (
+                    SELECT json_object(
+                        'users', json_group_array(user_id) FILTER (WHERE user_id IS NOT NULL),
+                        'roles', json_group_array(role_id) FILTER (WHERE role_id IS NOT NULL),
+                        'channels', json_group_array(channel_id) FILTER (WHERE channel_id IS NOT NULL)
+                    )
+                    FROM message_mentions
+                    WHERE message_id = messages.id
+                ) AS "mentions!: String"
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.channel_id = ?2 AND messages.thread_id IS ?3 AND messages.id < ?4
+            ORDER BY messages.id DESC
+            LIMIT ?5;
+            "#,
+            REPLY_EXCERPT_LEN,
+            channel_id,
+            thread_id,
+            before,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages.into_iter().map(Message::from).collect())
+    }
+
+    async fn search_messages(&self, search: &MessageSearch) -> Result<Vec<SearchHit>, ServerErr> {
+        let text = fts_query(&search.text);
+        // SQLite can't bind a list, so the IDs go in as a JSON array.
+        let channel_ids =
+            serde_json::to_string(&search.channel_ids).expect("IDs serialize to JSON");
+        let rows = query_as!(
+            SearchRow,
+            r#"
+            SELECT
+                messages.user_id AS "user_id!: i32",
+                messages.channel_id AS "channel_id!: i32",
+                messages.server_id AS "server_id: i32",
+                messages.thread_id AS "thread_id: i32",
+                messages.ts AS "ts!: DateTime<Utc>",
+                messages.id AS "id!: i64",
+                messages.text AS "text!: String",
+                messages.edited_ts AS "edited_ts: DateTime<Utc>",
+                messages.reply_to,
+                parent.user_id AS "reply_user_id: i32",
+                substr(parent.text, 1, ?1) AS "reply_excerpt: String",
+                messages.mention_everyone AS "mention_everyone!: bool",
+                (
+                    SELECT json_object(
+                        'users', json_group_array(user_id) FILTER (WHERE user_id IS NOT NULL),
+                        'roles', json_group_array(role_id) FILTER (WHERE role_id IS NOT NULL),
+                        'channels', json_group_array(channel_id) FILTER (WHERE channel_id IS NOT NULL)
+                    )
+                    FROM message_mentions
+                    WHERE message_id = messages.id
+                ) AS "mentions!: String",
+                snippet(messages_fts, 0, ?2, ?3, '…', 16) AS "snippet!: String"
+            FROM messages_fts
+            JOIN messages ON messages.id = messages_fts.rowid
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages_fts MATCH ?4
+                AND messages.channel_id IN (SELECT value FROM json_each(?5))
+                AND (?6 IS NULL OR messages.user_id = ?6)
+                AND (?7 IS NULL OR messages.ts > ?7)
+                AND (?8 IS NULL OR messages.ts < ?8)
+                AND (?9 IS NULL OR (messages.reply_to IS NOT NULL) = ?9)
+                AND (?10 IS NULL OR messages.id IN (
+                    SELECT message_id FROM message_mentions WHERE user_id = ?10
+                ))
+                AND messages.id < ?11
+            ORDER BY messages.id DESC
+            LIMIT ?12;
+            "#,
+            REPLY_EXCERPT_LEN,
+            SNIPPET_START,
+            SNIPPET_END,
+            text,
+            channel_ids,
+            search.user_id,
+            search.after,
+            search.before,
+            search.has_reply,
+            search.mentions,
+            search.before_id,
+            search.limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(SearchHit::from).collect())
+    }
+
+    async fn get_messages_after(
+        &self,
+        channel_id: ChannelId,
+        thread_id: Option<ThreadId>,
+        after: MessageId,
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr> {
+        let messages = query_as!(
+            MessageRow,
+            r#"
+            SELECT
+                messages.user_id AS "user_id!: i32",
+                messages.channel_id AS "channel_id!: i32",
+                messages.server_id AS "server_id: i32",
+                messages.thread_id AS "thread_id: i32",
+                messages.ts AS "ts!: DateTime<Utc>",
+                messages.id AS "id!: i64",
+                messages.text AS "text!: String",
+                messages.edited_ts AS "edited_ts: DateTime<Utc>",
+                messages.reply_to,
+                parent.user_id AS "reply_user_id: i32",
+                substr(parent.text, 1, ?1) AS "reply_excerpt: String",
+                messages.mention_everyone AS "mention_everyone!: bool",
+                (
+                    SELECT json_object(
+                        'users', json_group_array(user_id) FILTER (WHERE user_id IS NOT NULL),
+                        'roles', json_group_array(role_id) FILTER (WHERE role_id IS NOT NULL),
+                        'channels', json_group_array(channel_id) FILTER (WHERE channel_id IS NOT NULL)
+                    )
+                    FROM message_mentions
+                    WHERE message_id = messages.id
+                ) AS "mentions!: String"
+            FROM messages
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE messages.channel_id = ?2 AND messages.thread_id IS ?3 AND messages.id > ?4
+            ORDER BY messages.id
+            LIMIT ?5;
+            "#,
+            REPLY_EXCERPT_LEN,
+            channel_id,
+            thread_id,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages.into_iter().map(Message::from).collect())
+    }
+
+    async fn get_credentials(&self, name: &str) -> Result<Option<(User, String)>, ServerErr> {
+        let row = query!(
+            r#"
+            SELECT id AS "id!: i32", name, password_hash AS "password_hash!"
+            FROM users
+            WHERE name = ?1 AND password_hash IS NOT NULL;
+            "#,
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| {
+            (
+                User {
+                    id: row.id,
+                    name: row.name,
+                },
+                row.password_hash,
+            )
+        }))
+    }
+
+    async fn insert_session(
+        &self,
+        token_hash: &str,
+        user_id: UserId,
+        created_ts: DateTime<Utc>,
+        expires_ts: DateTime<Utc>,
+    ) -> Result<(), ServerErr> {
+        query!(
+            r#"
+            INSERT INTO sessions (token_hash, user_id, created_ts, expires_ts)
+            VALUES (?1, ?2, ?3, ?4);
+            "#,
+            token_hash,
+            user_id,
+            created_ts,
+            expires_ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_session_user(
+        &self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<User>, ServerErr> {
+        let user = query_as!(
+            User,
+            r#"
+            SELECT users.id AS "id!: i32", users.name
+            FROM sessions
+            JOIN users ON users.id = sessions.user_id
+            WHERE sessions.token_hash = ?1 AND sessions.expires_ts > ?2;
+            "#,
+            token_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), ServerErr> {
+        query!(r#"DELETE FROM sessions WHERE token_hash = ?1;"#, token_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, ServerErr> {
+        let result = query!(r#"DELETE FROM sessions WHERE expires_ts <= ?1;"#, now)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_member(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        joined_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            INSERT INTO server_members (server_id, user_id, joined_ts)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING;
+            "#,
+            server_id,
+            user_id,
+            joined_ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_member(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"DELETE FROM server_members WHERE server_id = ?1 AND user_id = ?2;"#,
+            server_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_member(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = ?1 AND user_id = ?2);"#,
+            server_id,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists == 1)
+    }
+
+    async fn get_members(&self, server_id: ServerId) -> Result<Vec<ServerMember>, ServerErr> {
+        let members = query_as!(
+            ServerMember,
+            r#"
+            SELECT
+                server_id AS "server_id!: i32",
+                user_id AS "user_id!: i32",
+                joined_ts AS "joined_ts!: DateTime<Utc>"
+            FROM server_members
+            WHERE server_id = ?1
+            ORDER BY joined_ts;
+            "#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    async fn get_member_servers(&self, user_id: UserId) -> Result<Vec<ServerId>, ServerErr> {
+        let servers = query_scalar!(
+            r#"SELECT server_id AS "server_id!: i32" FROM server_members WHERE user_id = ?1;"#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(servers)
+    }
+
+    async fn member_count(&self, server_id: ServerId) -> Result<i64, ServerErr> {
+        let count = query_scalar!(
+            r#"SELECT COUNT(*) FROM server_members WHERE server_id = ?1;"#,
+            server_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
+    async fn insert_ban(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        banned_ts: DateTime<Utc>,
+    ) -> Result<(), ServerErr> {
+        query!(
+            r#"
+            INSERT INTO server_bans (server_id, user_id, banned_ts)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING;
+            "#,
+            server_id,
+            user_id,
+            banned_ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_ban(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"DELETE FROM server_bans WHERE server_id = ?1 AND user_id = ?2;"#,
+            server_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_banned(&self, server_id: ServerId, user_id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM server_bans WHERE server_id = ?1 AND user_id = ?2);"#,
+            server_id,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists == 1)
+    }
+
+    async fn insert_role(
+        &self,
+        server_id: ServerId,
+        name: &str,
+        permissions: i64,
+        position: i32,
+    ) -> Result<RoleId, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query!(
+            r#"UPDATE roles SET position = position + 1 WHERE server_id = ?1 AND position >= ?2;"#,
+            server_id,
+            position
+        )
+        .execute(&mut *tx)
+        .await?;
+        let id = query!(
+            r#"
+            INSERT INTO roles (server_id, name, permissions, position)
+            VALUES (?1, ?2, ?3, ?4)
+            RETURNING id AS "id!: i32";
+            "#,
+            server_id,
+            name,
+            permissions,
+            position
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .id;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn update_role(&self, id: RoleId, name: &str, permissions: i64) -> Result<(), ServerErr> {
+        query!(
+            r#"UPDATE roles SET name = ?2, permissions = ?3 WHERE id = ?1;"#,
+            id,
+            name,
+            permissions
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn move_role(
+        &self,
+        server_id: ServerId,
+        id: RoleId,
+        from: i32,
+        to: i32,
+    ) -> Result<(), ServerErr> {
+        let (low, high) = (from.min(to), from.max(to));
+        let shift = if to > from { -1 } else { 1 };
+        query!(
+            r#"
+            UPDATE roles
+            SET position = CASE WHEN id = ?2 THEN ?3 ELSE position + ?4 END
+            WHERE server_id = ?1 AND position BETWEEN ?5 AND ?6;
+            "#,
+            server_id,
+            id,
+            to,
+            shift,
+            low,
+            high
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_role(
+        &self,
+        server_id: ServerId,
+        id: RoleId,
+        position: i32,
+    ) -> Result<(), ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        query!(r#"DELETE FROM roles WHERE id = ?1;"#, id)
+            .execute(&mut *tx)
+            .await?;
+        query!(
+            r#"UPDATE roles SET position = position - 1 WHERE server_id = ?1 AND position > ?2;"#,
+            server_id,
+            position
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_role(&self, id: RoleId) -> Result<Option<Role>, ServerErr> {
+        let role = query_as!(
+            Role,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                permissions,
+                position AS "position!: i32"
+            FROM roles
+            WHERE id = ?1;
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(role)
+    }
+
+    async fn get_roles(&self, server_id: ServerId) -> Result<Vec<Role>, ServerErr> {
+        let roles = query_as!(
+            Role,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                permissions,
+                position AS "position!: i32"
+            FROM roles
+            WHERE server_id = ?1
+            ORDER BY position;
+            "#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(roles)
+    }
+
+    async fn get_member_roles(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+    ) -> Result<Vec<Role>, ServerErr> {
+        let roles = query_as!(
+            Role,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                permissions,
+                position AS "position!: i32"
+            FROM roles
+            WHERE server_id = ?1 AND (
+                position = 0
+                OR id IN (SELECT role_id FROM member_roles WHERE server_id = ?1 AND user_id = ?2)
+            )
+            ORDER BY position;
+            "#,
+            server_id,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(roles)
+    }
+
+    async fn get_all_member_roles(
+        &self,
+        server_id: ServerId,
+    ) -> Result<Vec<MemberRole>, ServerErr> {
+        let member_roles = query_as!(
+            MemberRole,
+            r#"
+            SELECT
+                server_id AS "server_id!: i32",
+                user_id AS "user_id!: i32",
+                role_id AS "role_id!: i32"
+            FROM member_roles
+            WHERE server_id = ?1;
+            "#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(member_roles)
+    }
+
+    async fn insert_member_role(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            INSERT INTO member_roles (server_id, user_id, role_id)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING;
+            "#,
+            server_id,
+            user_id,
+            role_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_member_role(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"DELETE FROM member_roles WHERE server_id = ?1 AND user_id = ?2 AND role_id = ?3;"#,
+            server_id,
+            user_id,
+            role_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn upsert_overwrite(&self, overwrite: &ChannelOverwrite) -> Result<(), ServerErr> {
+        if overwrite.role_id.is_some() {
+            query!(
+                r#"
+                INSERT INTO channel_overwrites (channel_id, role_id, user_id, allow, deny)
+                VALUES (?1, ?2, NULL, ?3, ?4)
+                ON CONFLICT (channel_id, role_id) DO UPDATE SET allow = ?3, deny = ?4;
+                "#,
+                overwrite.channel_id,
+                overwrite.role_id,
+                overwrite.allow,
+                overwrite.deny
+            )
+            .execute(&self.pool)
+            .await?;
+        } else {
+            query!(
+                r#"
+                INSERT INTO channel_overwrites (channel_id, role_id, user_id, allow, deny)
+                VALUES (?1, NULL, ?2, ?3, ?4)
+                ON CONFLICT (channel_id, user_id) DO UPDATE SET allow = ?3, deny = ?4;
+                "#,
+                overwrite.channel_id,
+                overwrite.user_id,
+                overwrite.allow,
+                overwrite.deny
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_overwrite(
+        &self,
+        channel_id: ChannelId,
+        role_id: Option<RoleId>,
+        user_id: Option<UserId>,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            DELETE FROM channel_overwrites
+            WHERE channel_id = ?1 AND role_id IS ?2 AND user_id IS ?3;
+            "#,
+            channel_id,
+            role_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_overwrites(
+        &self,
+        channel_id: ChannelId,
+    ) -> Result<Vec<ChannelOverwrite>, ServerErr> {
+        let overwrites = query_as!(
+            ChannelOverwrite,
+            r#"
+            SELECT
+                channel_id AS "channel_id!: i32",
+                role_id AS "role_id: i32",
+                user_id AS "user_id: i32",
+                allow,
+                deny
+            FROM channel_overwrites
+            WHERE channel_id = ?1;
+            "#,
+            channel_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(overwrites)
+    }
+
+    async fn get_server_overwrites(
+        &self,
+        server_id: ServerId,
+    ) -> Result<Vec<ChannelOverwrite>, ServerErr> {
+        let overwrites = query_as!(
+            ChannelOverwrite,
+            r#"
+            SELECT
+                channel_overwrites.channel_id AS "channel_id!: i32",
+                channel_overwrites.role_id AS "role_id: i32",
+                channel_overwrites.user_id AS "user_id: i32",
+                channel_overwrites.allow,
+                channel_overwrites.deny
+            FROM channel_overwrites
+            JOIN channels ON channels.id = channel_overwrites.channel_id
+            WHERE channels.server_id = ?1;
+            "#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(overwrites)
+    }
+
+    async fn insert_thread(&self, thread: &Thread) -> Result<ThreadId, ServerErr> {
+        let id = query_scalar!(
+            r#"
+            INSERT INTO threads (channel_id, server_id, name, starter_message_id, creator_id, created_ts, last_active_ts, archived, locked)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            RETURNING id AS "id!: i32";
+            "#,
+            thread.channel_id,
+            thread.server_id,
+            thread.name,
+            thread.starter_message_id,
+            thread.creator_id,
+            thread.created_ts,
+            thread.last_active_ts,
+            thread.archived,
+            thread.locked
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_thread(&self, id: ThreadId) -> Result<Option<Thread>, ServerErr> {
+        let thread = query_as!(
+            Thread,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                channel_id AS "channel_id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                starter_message_id,
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>",
+                last_active_ts AS "last_active_ts!: DateTime<Utc>",
+                archived,
+                locked
+            FROM threads
+            WHERE id = ?1;
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(thread)
+    }
+
+    async fn get_threads(
+        &self,
+        channel_id: ChannelId,
+        archived: bool,
+    ) -> Result<Vec<Thread>, ServerErr> {
+        let threads = query_as!(
+            Thread,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                channel_id AS "channel_id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                starter_message_id,
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>",
+                last_active_ts AS "last_active_ts!: DateTime<Utc>",
+                archived,
+                locked
+            FROM threads
+            WHERE channel_id = ?1 AND archived = ?2
+            ORDER BY last_active_ts DESC;
+            "#,
+            channel_id,
+            archived
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(threads)
+    }
+
+    async fn get_active_threads(
+        &self,
+        channel_ids: &[ChannelId],
+    ) -> Result<Vec<Thread>, ServerErr> {
+        let ids = serde_json::to_string(channel_ids).expect("IDs serialize to JSON");
+        let threads = query_as!(
+            Thread,
+            r#"
+            SELECT
+                id AS "id!: i32",
+                channel_id AS "channel_id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                starter_message_id,
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>",
+                last_active_ts AS "last_active_ts!: DateTime<Utc>",
+                archived,
+                locked
+            FROM threads
+            WHERE NOT archived AND channel_id IN (SELECT value FROM json_each(?1))
+            ORDER BY last_active_ts DESC;
+            "#,
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(threads)
+    }
+
+    async fn archive_thread(&self, id: ThreadId) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"UPDATE threads SET archived = TRUE WHERE id = ?1 AND NOT archived;"#,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn touch_thread(&self, id: ThreadId, ts: DateTime<Utc>) -> Result<(), ServerErr> {
+        query!(
+            r#"UPDATE threads SET archived = FALSE, last_active_ts = ?2 WHERE id = ?1;"#,
+            id,
+            ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_thread_locked(&self, id: ThreadId, locked: bool) -> Result<(), ServerErr> {
+        query!(
+            r#"UPDATE threads SET locked = ?2 WHERE id = ?1;"#,
+            id,
+            locked
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn archive_inactive_threads(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Thread>, ServerErr> {
+        let threads = query_as!(
+            Thread,
+            r#"
+            UPDATE threads SET archived = TRUE
+            WHERE NOT archived AND last_active_ts < ?1
+            RETURNING
+                id AS "id!: i32",
+                channel_id AS "channel_id!: i32",
+                server_id AS "server_id!: i32",
+                name,
+                starter_message_id,
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>",
+                last_active_ts AS "last_active_ts!: DateTime<Utc>",
+                archived,
+                locked;
+            "#,
+            before
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(threads)
+    }
+
+    async fn insert_thread_member(
+        &self,
+        thread_id: ThreadId,
+        user_id: UserId,
+        joined_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            INSERT INTO thread_members (thread_id, user_id, joined_ts)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING;
+            "#,
+            thread_id,
+            user_id,
+            joined_ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_thread_member(
+        &self,
+        thread_id: ThreadId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"DELETE FROM thread_members WHERE thread_id = ?1 AND user_id = ?2;"#,
+            thread_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_thread_members(
+        &self,
+        thread_id: ThreadId,
+    ) -> Result<Vec<ThreadMember>, ServerErr> {
+        let members = query_as!(
+            ThreadMember,
+            r#"
+            SELECT
+                thread_id AS "thread_id!: i32",
+                user_id AS "user_id!: i32",
+                joined_ts AS "joined_ts!: DateTime<Utc>"
+            FROM thread_members
+            WHERE thread_id = ?1
+            ORDER BY joined_ts;
+            "#,
+            thread_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    async fn insert_dm(
+        &self,
+        channel: &Channel,
+        recipients: &[UserId],
+        added_ts: DateTime<Utc>,
+    ) -> Result<ChannelId, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        let id = query!(
+            r#"
+            INSERT INTO channels (name, kind, owner_id)
+            VALUES (?1, ?2, ?3)
+            RETURNING id AS "id!: i32";
+            "#,
+            channel.name,
+            channel.kind,
+            channel.owner_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .id;
+        for user_id in recipients {
+            query!(
+                r#"
+                INSERT INTO channel_recipients (channel_id, user_id, added_ts)
+                VALUES (?1, ?2, ?3);
+                "#,
+                id,
+                user_id,
+                added_ts
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if let (ChannelKind::Dm, &[a, b]) = (channel.kind, recipients) {
+            let (user_id, other_id) = (a.min(b), a.max(b));
+            query!(
+                r#"INSERT INTO dm_pairs (user_id, other_id, channel_id) VALUES (?1, ?2, ?3);"#,
+                user_id,
+                other_id,
+                id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    async fn get_dm(
+        &self,
+        user_id: UserId,
+        other_id: UserId,
+    ) -> Result<Option<Channel>, ServerErr> {
+        let channel = query_as!(
+            Channel,
+            r#"
+            SELECT server_id AS "server_id: i32", id AS "id!: i32", name, kind AS "kind: ChannelKind", owner_id AS "owner_id: i32"
+            FROM channels
+            WHERE id = (
+                SELECT channel_id FROM dm_pairs WHERE user_id = MIN(?1, ?2) AND other_id = MAX(?1, ?2)
+            );
+            "#,
+            user_id,
+            other_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(channel)
+    }
+
+    async fn get_dm_channels(&self, user_id: UserId) -> Result<Vec<Channel>, ServerErr> {
+        let channels = query_as!(
+            Channel,
+            r#"
+            SELECT server_id AS "server_id: i32", id AS "id!: i32", name, kind AS "kind: ChannelKind", owner_id AS "owner_id: i32"
+            FROM channels
+            WHERE id IN (SELECT channel_id FROM channel_recipients WHERE user_id = ?1)
+            ORDER BY id;
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    async fn get_recipients(&self, channel_id: ChannelId) -> Result<Vec<UserId>, ServerErr> {
+        let user_ids = query_scalar!(
+            r#"
+            SELECT user_id AS "user_id!: i32" FROM channel_recipients
+            WHERE channel_id = ?1
+            ORDER BY added_ts, user_id;
+            "#,
+            channel_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(user_ids)
+    }
+
+    async fn is_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM channel_recipients WHERE channel_id = ?1 AND user_id = ?2);"#,
+            channel_id,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists == 1)
+    }
+
+    async fn insert_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+        added_ts: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"
+            INSERT INTO channel_recipients (channel_id, user_id, added_ts)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING;
+            "#,
+            channel_id,
+            user_id,
+            added_ts
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_recipient(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<bool, ServerErr> {
+        let result = query!(
+            r#"DELETE FROM channel_recipients WHERE channel_id = ?1 AND user_id = ?2;"#,
+            channel_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_channel_owner(&self, id: ChannelId, owner_id: UserId) -> Result<(), ServerErr> {
+        query!(
+            r#"UPDATE channels SET owner_id = ?2 WHERE id = ?1;"#,
+            id,
+            owner_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_channel(&self, id: ChannelId) -> Result<bool, ServerErr> {
+        let result = query!(r#"DELETE FROM channels WHERE id = ?1;"#, id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn shares_server(&self, user_id: UserId, other_id: UserId) -> Result<bool, ServerErr> {
+        let exists = query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM server_members AS mine
+                JOIN server_members AS theirs ON theirs.server_id = mine.server_id
+                WHERE mine.user_id = ?1 AND theirs.user_id = ?2
+            );
+            "#,
+            user_id,
+            other_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists == 1)
+    }
+
+    async fn insert_invite(&self, invite: &Invite) -> Result<(), ServerErr> {
+        query!(
+            r#"
+            INSERT INTO invites (code, server_id, channel_id, creator_id, created_ts, expires_ts, max_uses, uses)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);
+            "#,
+            invite.code,
+            invite.server_id,
+            invite.channel_id,
+            invite.creator_id,
+            invite.created_ts,
+            invite.expires_ts,
+            invite.max_uses,
+            invite.uses
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_invite(&self, code: &str) -> Result<Option<Invite>, ServerErr> {
+        let invite = query_as!(
+            Invite,
+            r#"
+            SELECT
+                code,
+                server_id AS "server_id!: i32",
+                channel_id AS "channel_id: i32",
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>",
+                expires_ts AS "expires_ts: DateTime<Utc>",
+                max_uses AS "max_uses: i32",
+                uses AS "uses!: i32"
+            FROM invites
+            WHERE code = ?1;
+            "#,
+            code
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(invite)
+    }
+
+    async fn get_invites(&self, server_id: ServerId) -> Result<Vec<Invite>, ServerErr> {
+        let invites = query_as!(
+            Invite,
+            r#"
+            SELECT
+                code,
+                server_id AS "server_id!: i32",
+                channel_id AS "channel_id: i32",
+                creator_id AS "creator_id: i32",
+                created_ts AS "created_ts!: DateTime<Utc>",
+                expires_ts AS "expires_ts: DateTime<Utc>",
+                max_uses AS "max_uses: i32",
+                uses AS "uses!: i32"
+            FROM invites
+            WHERE server_id = ?1
+            ORDER BY created_ts;
+            "#,
+            server_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invites)
+    }
+
+    async fn redeem_invite(
+        &self,
+        code: &str,
+        server_id: ServerId,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> Result<bool, ServerErr> {
+        let mut tx = self.pool.begin().await?;
+        let used = query!(
+            r#"
+            UPDATE invites SET uses = uses + 1
+            WHERE code = ?1
+                AND (expires_ts IS NULL OR expires_ts > ?2)
+                AND (max_uses IS NULL OR uses < max_uses);
+            "#,
+            code,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+        if used.rows_affected() == 0 {
+            return Ok(false);
+        }
+        let joined = query!(
+            r#"
+            INSERT INTO server_members (server_id, user_id, joined_ts)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING;
+            "#,
+            server_id,
+            user_id,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+        if joined.rows_affected() == 0 {
+            return Ok(false);
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete_invite(&self, code: &str) -> Result<bool, ServerErr> {
+        let result = query!(r#"DELETE FROM invites WHERE code = ?1;"#, code)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_users(&self, limit: i64) -> Result<Vec<User>, ServerErr> {
+        let users = query_as!(
+            User,
+            r#"SELECT id AS "id!: i32", name FROM users LIMIT ?1"#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+
+    async fn get_servers(&self, user_id: UserId) -> Result<Vec<Server>, ServerErr> {
+        let servers = query_as!(
+            Server,
+            r#"
+            SELECT id AS "id!: i32", name, owner_id AS "owner_id: i32" FROM servers
+            WHERE id IN (SELECT server_id FROM server_members WHERE user_id = ?1)
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(servers)
+    }
+
+    async fn get_channels(&self, user_id: UserId) -> Result<Vec<Channel>, ServerErr> {
+        let channels = query_as!(
+            Channel,
+            r#"
+            SELECT server_id AS "server_id: i32", id AS "id!: i32", name, kind AS "kind: ChannelKind", owner_id AS "owner_id: i32"
+            FROM channels
+            WHERE server_id IN (SELECT server_id FROM server_members WHERE user_id = ?1)
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(channels)
+    }
+
+    async fn get_latest_messages(
+        &self,
+        channel_ids: &[ChannelId],
+        limit: i64,
+    ) -> Result<Vec<Message>, ServerErr> {
+        // SQLite can't bind a list, so the IDs go in as a JSON array.
+        let ids = serde_json::to_string(channel_ids).expect("IDs serialize to JSON");
+        let messages = query_as!(
+            MessageRow,
+            r#"
+            WITH latest AS (
+                SELECT id, ROW_NUMBER() OVER (PARTITION BY channel_id ORDER BY id DESC) AS n
+                FROM messages
+                WHERE channel_id IN (SELECT value FROM json_each(?2))
+            )
+            SELECT
+                messages.user_id AS "user_id!: i32",
+                messages.channel_id AS "channel_id!: i32",
+                messages.server_id AS "server_id: i32",
+                messages.thread_id AS "thread_id: i32",
+                messages.ts AS "ts!: DateTime<Utc>",
+                messages.id AS "id!: i64",
+                messages.text AS "text!: String",
+                messages.edited_ts AS "edited_ts: DateTime<Utc>",
+                messages.reply_to,
+                parent.user_id AS "reply_user_id: i32",
+                substr(parent.text, 1, ?1) AS "reply_excerpt: String",
+                messages.mention_everyone AS "mention_everyone!: bool",
+                (
+                    SELECT json_object(
+                        'users', json_group_array(user_id) FILTER (WHERE user_id IS NOT NULL),
+                        'roles', json_group_array(role_id) FILTER (WHERE role_id IS NOT NULL),
+                        'channels', json_group_array(channel_id) FILTER (WHERE channel_id IS NOT NULL)
+                    )
+                    FROM message_mentions
+                    WHERE message_id = messages.id
+                ) AS "mentions!: String"
+            FROM latest
+            JOIN messages ON messages.id = latest.id
+            LEFT JOIN messages AS parent ON parent.id = messages.reply_to
+            WHERE latest.n <= ?3
+            ORDER BY messages.id DESC;
+            "#,
+            REPLY_EXCERPT_LEN,
+            ids,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages.into_iter().map(Message::from).collect())
+    }
+
+    async fn insert_logged_update(
+        &self,
+        seq: i64,
+        ts: DateTime<Utc>,
+        data: &str,
+    ) -> Result<(), ServerErr> {
+        query!(
+            r#"INSERT INTO update_log (seq, ts, data) VALUES (?1, ?2, ?3);"#,
+            seq,
+            ts,
+            data
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_last_logged_update(&self) -> Result<Option<i64>, ServerErr> {
+        let seq = query_scalar!(r#"SELECT MAX(seq) AS "seq: i64" FROM update_log;"#)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(seq)
+    }
+
+    async fn get_logged_updates(
+        &self,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<(i64, String)>, ServerErr> {
+        let rows = query!(
+            r#"
+            SELECT seq AS "seq!: i64", data
+            FROM update_log
+            WHERE seq > ?1
+            ORDER BY seq
+            LIMIT ?2;
+            "#,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|row| (row.seq, row.data)).collect())
+    }
+
+    async fn prune_logged_updates(&self, through: i64) -> Result<(), ServerErr> {
+        query!(r#"DELETE FROM update_log WHERE seq <= ?1;"#, through)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
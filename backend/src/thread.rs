@@ -0,0 +1,428 @@
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    config::{Limits, ThreadConfig},
+    error::ServerErr,
+    message::{Message, MessageId},
+    permission::{MemberAccess, Permissions},
+    server::ServerId,
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+pub type ThreadId = i32;
+
+pub const THREAD_NAME_MAX_LEN: usize = 32;
+pub const THREAD_AUTO_ARCHIVE_SECS: i64 = 24 * 60 * 60;
+pub const THREAD_ARCHIVE_CHECK_SECS: u64 = 60;
+pub const CREATE_THREAD_PATH: &str = "/create-thread";
+pub const THREADS_PATH: &str = "/threads";
+pub const ARCHIVE_THREAD_PATH: &str = "/archive-thread";
+pub const LOCK_THREAD_PATH: &str = "/lock-thread";
+pub const THREAD_MEMBERS_PATH: &str = "/thread-members";
+pub const JOIN_THREAD_PATH: &str = "/join-thread";
+pub const LEAVE_THREAD_PATH: &str = "/leave-thread";
+
+/// A conversation inside a channel. Its messages are channel messages with
+/// `thread_id` set, so the channel's permissions apply to them.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct Thread {
+    pub id: ThreadId,
+    pub channel_id: ChannelId,
+    pub server_id: ServerId,
+    pub name: String,
+    /// The channel message the thread was started from, if any. Unset if it
+    /// was deleted.
+    pub starter_message_id: Option<MessageId>,
+    /// Unset if the creator's account was deleted.
+    pub creator_id: Option<UserId>,
+    pub created_ts: DateTime<Utc>,
+    /// When the thread was created, last posted in, or unarchived.
+    pub last_active_ts: DateTime<Utc>,
+    /// Archived threads are left out of the snapshot. Posting in one
+    /// unarchives it.
+    pub archived: bool,
+    /// Only members with MANAGE_MESSAGES can post in or unarchive a locked
+    /// thread.
+    pub locked: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
+#[ts(export, export_to = "../../frontend/src/bindings/")]
+pub struct ThreadMember {
+    pub thread_id: ThreadId,
+    pub user_id: UserId,
+    pub joined_ts: DateTime<Utc>,
+}
+
+impl Thread {
+    pub async fn insert(
+        db: &Db,
+        limits: &Limits,
+        creator_id: UserId,
+        params: CreateThreadParams,
+    ) -> Result<Self, ServerErr> {
+        let len = params.name.len();
+        if len > limits.thread_name_max_len {
+            return Err(ServerErr::ThreadNameTooLong(
+                len,
+                limits.thread_name_max_len,
+            ));
+        }
+        let channel = Channel::get(db, params.channel_id).await?;
+        MemberAccess::require_channel(
+            db,
+            &channel,
+            creator_id,
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+        )
+        .await?;
+        let Some(server_id) = channel.server_id else {
+            return Err(ServerErr::BadRequest(format!(
+                "Channel ID {} is a DM, which can't have threads",
+                channel.id
+            )));
+        };
+        if let Some(message_id) = params.starter_message_id {
+            let message = Message::get(db, message_id).await?;
+            if message.channel_id != channel.id || message.thread_id.is_some() {
+                return Err(ServerErr::BadRequest(format!(
+                    "Message ID {message_id} is not in channel ID {} outside a thread",
+                    channel.id
+                )));
+            }
+        }
+        let created_ts = Utc::now();
+        let mut thread = Self {
+            id: 0,
+            channel_id: channel.id,
+            server_id,
+            name: params.name,
+            starter_message_id: params.starter_message_id,
+            creator_id: Some(creator_id),
+            created_ts,
+            last_active_ts: created_ts,
+            archived: false,
+            locked: false,
+        };
+        thread.id = match db.insert_thread(&thread).await {
+            Ok(id) => id,
+            Err(ServerErr::SqlxErr(err))
+                if err
+                    .as_database_error()
+                    .is_some_and(|err| err.is_unique_violation()) =>
+            {
+                return Err(ServerErr::BadRequest(format!(
+                    "Message ID {} already has a thread",
+                    params.starter_message_id.unwrap_or_default()
+                )));
+            }
+            Err(err) => return Err(err),
+        };
+        db.insert_thread_member(thread.id, creator_id, created_ts)
+            .await?;
+        Ok(thread)
+    }
+
+    pub async fn get(db: &Db, thread_id: ThreadId) -> Result<Self, ServerErr> {
+        db.get_thread(thread_id)
+            .await?
+            .ok_or(ServerErr::NoThreadId(thread_id))
+    }
+
+    /// Rejects posting unless the thread is unlocked or the member can
+    /// manage messages.
+    pub fn check_postable(&self, access: &MemberAccess) -> Result<(), ServerErr> {
+        if self.locked && !access.permissions.contains(Permissions::MANAGE_MESSAGES) {
+            Err(ServerErr::ThreadLocked(self.id))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records a message posted in the thread: the thread stays active and
+    /// the author becomes a member. Returns the thread if it was archived
+    /// and is now active again.
+    pub async fn record_message(db: &Db, message: &Message) -> Result<Option<Self>, ServerErr> {
+        let Some(thread_id) = message.thread_id else {
+            return Ok(None);
+        };
+        let mut thread = Self::get(db, thread_id).await?;
+        db.touch_thread(thread.id, message.ts).await?;
+        db.insert_thread_member(thread.id, message.user_id, message.ts)
+            .await?;
+        let unarchived = thread.archived;
+        thread.archived = false;
+        thread.last_active_ts = message.ts;
+        Ok(unarchived.then_some(thread))
+    }
+}
+
+fn send_thread_update(send: &Sender, thread: Thread) {
+    if let Err(err) = send.send(Update::ThreadUpdate(thread)) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+}
+
+/// Archives threads that have gone quiet, every `archive_check_secs`.
+pub fn spawn_auto_archive(db: Db, send: Sender, config: ThreadConfig) {
+    tokio::spawn(async move {
+        let period = std::time::Duration::from_secs(config.archive_check_secs);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let before = Utc::now() - Duration::seconds(config.auto_archive_secs);
+            match db.archive_inactive_threads(before).await {
+                Ok(threads) => {
+                    for thread in threads {
+                        send_thread_update(&send, thread);
+                    }
+                }
+                Err(err) => tracing::error!("Error archiving inactive threads: {err:?}"),
+            }
+        }
+    });
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct CreateThreadParams {
+    #[param(example = "My Thread Name", required = true)]
+    name: String,
+    #[param(required = true)]
+    channel_id: ChannelId,
+    /// A message in the channel to start the thread from.
+    starter_message_id: Option<MessageId>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct ThreadsParams {
+    #[param(required = true)]
+    channel_id: ChannelId,
+    /// List archived threads instead of active ones.
+    archived: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct ThreadParams {
+    #[param(required = true)]
+    thread_id: ThreadId,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct ArchiveThreadParams {
+    #[param(required = true)]
+    thread_id: ThreadId,
+    /// False to unarchive.
+    #[param(required = true)]
+    archived: bool,
+}
+
+#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+pub struct LockThreadParams {
+    #[param(required = true)]
+    thread_id: ThreadId,
+    /// False to unlock.
+    #[param(required = true)]
+    locked: bool,
+}
+
+/// Looks up the thread and its channel and checks the user can view it.
+async fn viewable_thread(
+    db: &Db,
+    user_id: UserId,
+    thread_id: ThreadId,
+) -> Result<(Thread, MemberAccess), ServerErr> {
+    let thread = Thread::get(db, thread_id).await?;
+    let channel = Channel::get(db, thread.channel_id).await?;
+    let access =
+        MemberAccess::require_channel(db, &channel, user_id, Permissions::VIEW_CHANNEL).await?;
+    Ok((thread, access))
+}
+
+#[utoipa::path(
+    post,
+    path = CREATE_THREAD_PATH,
+    params(CreateThreadParams),
+    responses(
+        (status = 200, description = "Start a thread in a channel", body = Thread),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing SEND_MESSAGES", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn create_thread(
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<CreateThreadParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let thread = Thread::insert(&db, &limits, user.id, query).await?;
+    if let Err(err) = send.send(Update::ThreadCreate(thread.clone())) {
+        tracing::error!("Error sending event: {err:?}");
+    }
+    Ok(Json(thread))
+}
+
+#[utoipa::path(
+    get,
+    path = THREADS_PATH,
+    params(ThreadsParams),
+    responses(
+        (status = 200, description = "List a channel's active or archived threads", body = Vec<Thread>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_threads(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ThreadsParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let channel = Channel::get(&db, query.channel_id).await?;
+    MemberAccess::require_channel(&db, &channel, user.id, Permissions::VIEW_CHANNEL).await?;
+    let threads = db
+        .get_threads(channel.id, query.archived.unwrap_or(false))
+        .await?;
+    Ok(Json(threads))
+}
+
+#[utoipa::path(
+    post,
+    path = ARCHIVE_THREAD_PATH,
+    params(ArchiveThreadParams),
+    responses(
+        (status = 200, description = "Archive or unarchive a thread", body = Thread),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not the creator and missing MANAGE_MESSAGES", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn archive_thread(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ArchiveThreadParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (mut thread, access) = viewable_thread(&db, user.id, query.thread_id).await?;
+    // Creators can archive their own threads, but only moderators can reopen
+    // a locked one.
+    if thread.creator_id != Some(user.id) || (thread.locked && !query.archived) {
+        access.check(Permissions::MANAGE_MESSAGES)?;
+    }
+    if thread.archived == query.archived {
+        return Ok(Json(thread));
+    }
+    if query.archived {
+        db.archive_thread(thread.id).await?;
+    } else {
+        thread.last_active_ts = Utc::now();
+        db.touch_thread(thread.id, thread.last_active_ts).await?;
+    }
+    thread.archived = query.archived;
+    send_thread_update(&send, thread.clone());
+    Ok(Json(thread))
+}
+
+#[utoipa::path(
+    post,
+    path = LOCK_THREAD_PATH,
+    params(LockThreadParams),
+    responses(
+        (status = 200, description = "Lock or unlock a thread", body = Thread),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing MANAGE_MESSAGES", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn lock_thread(
+    State(db): State<Db>,
+    State(send): State<Sender>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<LockThreadParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (mut thread, access) = viewable_thread(&db, user.id, query.thread_id).await?;
+    access.check(Permissions::MANAGE_MESSAGES)?;
+    if thread.locked != query.locked {
+        db.set_thread_locked(thread.id, query.locked).await?;
+        thread.locked = query.locked;
+        send_thread_update(&send, thread.clone());
+    }
+    Ok(Json(thread))
+}
+
+#[utoipa::path(
+    get,
+    path = THREAD_MEMBERS_PATH,
+    params(ThreadParams),
+    responses(
+        (status = 200, description = "List the members of a thread", body = Vec<ThreadMember>),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn get_thread_members(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ThreadParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (thread, _access) = viewable_thread(&db, user.id, query.thread_id).await?;
+    let members = db.get_thread_members(thread.id).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    post,
+    path = JOIN_THREAD_PATH,
+    params(ThreadParams),
+    responses(
+        (status = 200, description = "Join a thread", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Can't view the channel", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn join_thread(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ThreadParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let (thread, _access) = viewable_thread(&db, user.id, query.thread_id).await?;
+    db.insert_thread_member(thread.id, user.id, Utc::now())
+        .await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = LEAVE_THREAD_PATH,
+    params(ThreadParams),
+    responses(
+        (status = 200, description = "Leave a thread", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 500, description = "Internal server error", body = String)
+    )
+)]
+pub async fn leave_thread(
+    State(db): State<Db>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ThreadParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    db.delete_thread_member(query.thread_id, user.id).await?;
+    Ok(())
+}
@@ -1,7 +1,16 @@
-use crate::{error::ServerErr, snapshot::Update, user::UserId, Sender};
+use crate::{
+    auth::AuthUser,
+    channel::{Channel, ChannelId},
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
 use axum::{
     extract::{Query, State},
-    response::{sse::Event, IntoResponse},
+    response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
@@ -20,7 +29,9 @@ pub enum Typing {
 pub struct TypingParams {
     #[param(required = true)]
     typing: bool,
-    user_id: i32,
+    /// Only those who can view the channel are told.
+    #[param(required = true)]
+    channel_id: ChannelId,
 }
 
 #[utoipa::path(
@@ -29,20 +40,41 @@ pub struct TypingParams {
     params(TypingParams),
     responses(
         (status = 200, description = "Typing indicator", body = ()),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Missing SEND_MESSAGES", body = String),
         (status = 500, description = "Internal server error", body = String)
     )
 )]
 pub async fn typing(
+    State(db): State<Db>,
     State(send): State<Sender>,
+    AuthUser(user): AuthUser,
     Query(query): Query<TypingParams>,
 ) -> Result<impl IntoResponse, ServerErr> {
-    let typing = if query.typing {
-        Typing::Start(query.user_id)
+    send_typing(&db, &send, user.id, query.channel_id, query.typing).await
+}
+
+/// Tells those who can view the channel that the user started or stopped
+/// typing there. Also used by the gateway.
+pub async fn send_typing(
+    db: &Db,
+    send: &Sender,
+    user_id: UserId,
+    channel_id: ChannelId,
+    typing: bool,
+) -> Result<(), ServerErr> {
+    let channel = Channel::get(db, channel_id).await?;
+    let permissions = Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES;
+    MemberAccess::require_channel(db, &channel, user_id, permissions).await?;
+    let typing = if typing {
+        Typing::Start(user_id)
     } else {
-        Typing::Stop(query.user_id)
+        Typing::Stop(user_id)
     };
-    let event = Event::default().json_data(Update::Typing(typing))?;
-    if let Err(err) = send.send(event) {
+    if let Err(err) = send.send(Update::Typing {
+        channel_id: channel.id,
+        typing,
+    }) {
         tracing::error!("Error sending event: {err:?}");
     }
     Ok(())
@@ -0,0 +1,285 @@
+use crate::{config::UpdateConfig, error::ServerErr, snapshot::Update, storage::Db};
+use chrono::Utc;
+use std::{collections::VecDeque, sync::Arc};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc::{self, error::SendError},
+    Mutex,
+};
+
+pub const UPDATE_REPLAY_LEN: usize = 1024;
+pub const UPDATE_LOG_LEN: usize = 10_000;
+/// How many updates go by between trimming the database log.
+const PRUNE_EVERY: Seq = 256;
+
+/// Position in the stream of updates. Each update gets the next one.
+pub type Seq = u64;
+
+/// An update with its place in the stream.
+#[derive(Debug)]
+pub struct LoggedUpdate {
+    pub seq: Seq,
+    pub update: Update,
+}
+
+/// Every update goes through here. Updates are numbered in the order they're
+/// sent and the latest `replay_len` are kept in memory, so clients that
+/// reconnect or fall behind can catch up. With `log` on they're also written
+/// to the database before anyone receives them, which lets clients catch up
+/// from further back and across restarts.
+#[derive(Clone)]
+pub struct UpdateLog {
+    queue: mpsc::UnboundedSender<Update>,
+    /// Only sent to while `shared` is locked, so subscribing and reading the
+    /// head happen together.
+    send: broadcast::Sender<Arc<LoggedUpdate>>,
+    shared: Arc<Mutex<Shared>>,
+    /// Set when the database log is on.
+    db: Option<Db>,
+    log_len: usize,
+}
+
+struct Shared {
+    /// The last number handed out.
+    head: Seq,
+    recent: VecDeque<Arc<LoggedUpdate>>,
+}
+
+impl UpdateLog {
+    /// Starts numbering after the last logged update. Without a log nothing
+    /// from before now can be caught up on, so numbering starts past anything
+    /// an earlier run could have reached, from the time in microseconds.
+    pub async fn start(
+        db: &Db,
+        config: &UpdateConfig,
+        max_broadcast: usize,
+    ) -> Result<Self, ServerErr> {
+        let now = Utc::now().timestamp_micros() as Seq;
+        let (head, db) = if config.log {
+            let last = db.get_last_logged_update().await?;
+            (last.map_or(now, |seq| seq as Seq), Some(db.clone()))
+        } else {
+            (now, None)
+        };
+        let (send, _recv) = broadcast::channel(max_broadcast);
+        let (queue, updates) = mpsc::unbounded_channel();
+        let log = Self {
+            queue,
+            send,
+            shared: Arc::new(Mutex::new(Shared {
+                head,
+                recent: VecDeque::with_capacity(config.replay_len),
+            })),
+            db,
+            log_len: config.log_len,
+        };
+        tokio::spawn(log.clone().run(updates, config.replay_len));
+        Ok(log)
+    }
+
+    /// Numbers, logs and broadcasts the updates one at a time, so they go out
+    /// in the order they were sent.
+    async fn run(self, mut updates: mpsc::UnboundedReceiver<Update>, replay_len: usize) {
+        while let Some(update) = updates.recv().await {
+            // Only this task moves `head`.
+            let seq = self.shared.lock().await.head + 1;
+            if let Some(db) = &self.db {
+                self.write(db, seq, &update).await;
+            }
+            let logged = Arc::new(LoggedUpdate { seq, update });
+            let mut shared = self.shared.lock().await;
+            shared.head = seq;
+            if shared.recent.len() == replay_len {
+                shared.recent.pop_front();
+            }
+            shared.recent.push_back(logged.clone());
+            // Having nobody connected isn't an error, the update is still
+            // kept for replay.
+            let _ = self.send.send(logged);
+        }
+    }
+
+    /// Logs the update. If that fails it's still sent, and anyone who needs
+    /// it from the log later has to resync.
+    async fn write(&self, db: &Db, seq: Seq, update: &Update) {
+        match serde_json::to_string(update) {
+            Ok(data) => {
+                if let Err(err) = db.insert_logged_update(seq as i64, Utc::now(), &data).await {
+                    tracing::error!("Error logging update {seq}: {err:?}");
+                }
+            }
+            Err(err) => tracing::error!("Error serializing update {seq}: {err:?}"),
+        }
+        if seq.is_multiple_of(PRUNE_EVERY)
+            && let Err(err) = db
+                .prune_logged_updates(seq as i64 - self.log_len as i64)
+                .await
+        {
+            tracing::error!("Error pruning update log: {err:?}");
+        }
+    }
+
+    /// Queues the update to be numbered and sent to everyone subscribed.
+    pub fn send(&self, update: Update) -> Result<(), Box<SendError<Update>>> {
+        self.queue.send(update).map_err(Box::new)
+    }
+
+    /// Subscribes to the updates after the returned position.
+    pub async fn subscribe(&self) -> (broadcast::Receiver<Arc<LoggedUpdate>>, Seq) {
+        let shared = self.shared.lock().await;
+        (self.send.subscribe(), shared.head)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.send.receiver_count()
+    }
+
+    pub async fn head(&self) -> Seq {
+        self.shared.lock().await.head
+    }
+
+    /// Returns every update after `after`, oldest first, or `None` if some of
+    /// them are no longer kept and the client has to resync.
+    pub async fn since(&self, after: Seq) -> Option<Vec<Arc<LoggedUpdate>>> {
+        let (head, recent) = {
+            let shared = self.shared.lock().await;
+            let recent: Vec<_> = shared
+                .recent
+                .iter()
+                .filter(|logged| logged.seq > after)
+                .cloned()
+                .collect();
+            (shared.head, recent)
+        };
+        if after > head {
+            return None;
+        }
+        let wanted = (head - after) as usize;
+        if recent.len() == wanted {
+            return Some(recent);
+        }
+        if wanted > self.log_len {
+            return None;
+        }
+        let db = self.db.as_ref()?;
+        let rows = match db.get_logged_updates(after as i64, wanted as i64).await {
+            Ok(rows) => rows,
+            Err(err) => {
+                tracing::error!("Error reading update log: {err:?}");
+                return None;
+            }
+        };
+        // Anything missing from the log, or written after `head`, means the
+        // rows don't line up with the ones in memory.
+        let mut missed = Vec::with_capacity(wanted);
+        for (seq, data) in rows {
+            let seq = seq as Seq;
+            if seq != after + missed.len() as Seq + 1 {
+                return None;
+            }
+            let update = serde_json::from_str(&data).ok()?;
+            missed.push(Arc::new(LoggedUpdate { seq, update }));
+        }
+        let logged = after + missed.len() as Seq;
+        missed.extend(recent.into_iter().filter(|update| update.seq > logged));
+        (missed.len() == wanted).then_some(missed)
+    }
+}
+
+/// What a subscriber is sent next.
+pub enum Delivery {
+    Update(Arc<LoggedUpdate>),
+    /// Updates follow from this position, sent first to new subscribers.
+    Ready(Seq),
+    /// Some updates the subscriber needed are no longer kept. It has to fetch
+    /// a new snapshot, and updates follow from this position.
+    Resync(Seq),
+}
+
+/// One subscriber's place in the stream. Catches up on whatever the
+/// subscriber missed, from the last position it was sent or when it falls
+/// too far behind, and skips anything it was already sent.
+pub struct Subscription {
+    recv: broadcast::Receiver<Arc<LoggedUpdate>>,
+    log: UpdateLog,
+    /// The last position the subscriber was sent.
+    last: Seq,
+    pending: VecDeque<Delivery>,
+}
+
+impl Subscription {
+    /// Subscribes from `last_event_id` if it's set, and otherwise from now.
+    /// Positions that can't be parsed need a resync.
+    pub async fn start(log: UpdateLog, last_event_id: Option<&str>) -> Self {
+        match last_event_id.map(|id| id.trim().parse::<Seq>()) {
+            None => Self::new(log).await,
+            Some(Ok(after)) => Self::resume(log, after).await,
+            Some(Err(_)) => {
+                let mut subscription = Self::subscribe(log).await;
+                subscription.resync().await;
+                subscription
+            }
+        }
+    }
+
+    /// Subscribes from now, starting with `Ready`.
+    pub async fn new(log: UpdateLog) -> Self {
+        let mut subscription = Self::subscribe(log).await;
+        subscription
+            .pending
+            .push_back(Delivery::Ready(subscription.last));
+        subscription
+    }
+
+    /// Subscribes from after `after`, starting with what was missed since.
+    pub async fn resume(log: UpdateLog, after: Seq) -> Self {
+        let mut subscription = Self::subscribe(log).await;
+        subscription.last = after;
+        subscription.catch_up().await;
+        subscription
+    }
+
+    async fn subscribe(log: UpdateLog) -> Self {
+        let (recv, head) = log.subscribe().await;
+        Self {
+            recv,
+            log,
+            last: head,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Waits for the next delivery. Returns `None` once the log shuts down.
+    pub async fn next(&mut self) -> Option<Delivery> {
+        loop {
+            if let Some(delivery) = self.pending.pop_front() {
+                if let Delivery::Update(update) = &delivery {
+                    if update.seq <= self.last {
+                        continue;
+                    }
+                    self.last = update.seq;
+                }
+                return Some(delivery);
+            }
+            match self.recv.recv().await {
+                Ok(update) => self.pending.push_back(Delivery::Update(update)),
+                Err(RecvError::Lagged(_)) => self.catch_up().await,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    async fn catch_up(&mut self) {
+        match self.log.since(self.last).await {
+            Some(missed) => self
+                .pending
+                .extend(missed.into_iter().map(Delivery::Update)),
+            None => self.resync().await,
+        }
+    }
+
+    async fn resync(&mut self) {
+        self.last = self.log.head().await;
+        self.pending.push_back(Delivery::Resync(self.last));
+    }
+}
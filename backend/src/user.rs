@@ -1,20 +1,23 @@
-use crate::{error::ServerErr, snapshot::Update, Sender};
-use axum::{
-    extract::{Query, State},
-    response::{sse::Event, IntoResponse},
-    Json,
+use crate::{
+    auth::{hash_password, validate_password, Session},
+    config::{AuthConfig, Limits},
+    error::ServerErr,
+    snapshot::Update,
+    storage::Db,
+    Sender,
 };
+use axum::{extract::State, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, SqlitePool};
+use sqlx::FromRow;
 use ts_rs::TS;
-use utoipa::{IntoParams, ToSchema};
+use utoipa::ToSchema;
 
 pub type UserId = i32;
 
 pub const USERNAME_MAX_LEN: usize = 32;
 pub const CREATE_USER_PATH: &str = "/create-user";
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, ToSchema, FromRow)]
 #[ts(export, export_to = "../../frontend/src/bindings/")]
 pub struct User {
     pub id: UserId,
@@ -22,51 +25,61 @@ pub struct User {
 }
 
 impl User {
-    pub async fn insert(pool: &SqlitePool, name: String) -> Result<Self, ServerErr> {
+    /// Registers a new account with a password.
+    pub async fn insert(
+        db: &Db,
+        limits: &Limits,
+        name: String,
+        password: String,
+    ) -> Result<Self, ServerErr> {
         let len = name.len();
-        if len > USERNAME_MAX_LEN {
-            Err(ServerErr::UsernameTooLong(len))
-        } else {
-            let id = query!(
-                r#"
-                INSERT INTO users (name)
-                VALUES (?1)
-                RETURNING id AS "id!: i32";
-                "#,
-                name
-            )
-            .fetch_one(pool)
-            .await?
-            .id;
-            Ok(Self { id, name })
+        if len > limits.username_max_len {
+            return Err(ServerErr::UsernameTooLong(len, limits.username_max_len));
+        }
+        validate_password(&password)?;
+        let password_hash = hash_password(password).await?;
+        match db.insert_user(&name, &password_hash).await {
+            Ok(id) => Ok(Self { id, name }),
+            Err(ServerErr::SqlxErr(err))
+                if err
+                    .as_database_error()
+                    .is_some_and(|err| err.is_unique_violation()) =>
+            {
+                Err(ServerErr::UsernameTaken(name))
+            }
+            Err(err) => Err(err),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, TS, IntoParams, Clone)]
+#[derive(Serialize, Deserialize, TS, ToSchema, Clone)]
 pub struct CreateUserParams {
-    #[param(example = "My User Name", required = true)]
+    #[schema(example = "My User Name")]
     name: String,
+    password: String,
 }
 
 #[utoipa::path(
     post,
     path = CREATE_USER_PATH,
-    params(CreateUserParams),
+    request_body = CreateUserParams,
     responses(
-        (status = 200, description = "Create a new user", body = User),
+        (status = 200, description = "Register a new user and log in", body = Session),
+        (status = 409, description = "Username is taken", body = String),
         (status = 500, description = "Internal user error", body = String)
     )
 )]
 pub async fn create_user(
-    State(pool): State<SqlitePool>,
+    State(db): State<Db>,
+    State(limits): State<Limits>,
+    State(auth): State<AuthConfig>,
     State(send): State<Sender>,
-    Query(query): Query<CreateUserParams>,
+    Json(params): Json<CreateUserParams>,
 ) -> Result<impl IntoResponse, ServerErr> {
-    let user = User::insert(&pool, query.name).await?;
-    let event = Event::default().json_data(Update::User(user.clone()))?;
-    if let Err(err) = send.send(event) {
+    let user = User::insert(&db, &limits, params.name, params.password).await?;
+    if let Err(err) = send.send(Update::User(user.clone())) {
         tracing::error!("Error sending event: {err:?}");
     }
-    Ok(Json(user))
+    let session = Session::create(&db, &auth, user).await?;
+    Ok(session.respond(&auth))
 }
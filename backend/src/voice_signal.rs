@@ -1,7 +1,16 @@
-use crate::{snapshot::Update, user::UserId, Sender};
+use crate::{
+    auth::AuthUser,
+    encoding::{Encoder, Encoding, EncodingParams},
+    error::ServerErr,
+    permission::{MemberAccess, Permissions},
+    snapshot::Update,
+    storage::Db,
+    user::UserId,
+    Sender,
+};
 use axum::{
-    extract::{ws::WebSocket, State, WebSocketUpgrade},
-    response::{sse::Event, IntoResponse},
+    extract::{ws::WebSocket, Query, State, WebSocketUpgrade},
+    response::IntoResponse,
 };
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
@@ -52,6 +61,18 @@ pub enum VoiceSignal {
     },
 }
 
+impl VoiceSignal {
+    /// The user this signal claims to come from.
+    pub fn sender(&self) -> UserId {
+        match self {
+            Self::Join { user_id, .. } | Self::Leave { user_id, .. } => *user_id,
+            Self::Offer { from, .. }
+            | Self::Answer { from, .. }
+            | Self::IceCandidate { from, .. } => *from,
+        }
+    }
+}
+
 impl VoiceState {
     pub async fn add_user(&self, channel_id: i32, user_id: UserId) {
         let mut channels = self.channels.write().await;
@@ -69,22 +90,51 @@ impl VoiceState {
     }
 }
 
+/// Voice channels are joined through a text channel for now, so connecting
+/// needs CONNECT in that channel.
+async fn check_connect(db: &Db, channel_id: i32, user_id: UserId) -> Result<(), ServerErr> {
+    let channel = db
+        .get_channel(channel_id)
+        .await?
+        .ok_or(ServerErr::NoChannelId(channel_id))?;
+    MemberAccess::require_channel(db, &channel, user_id, Permissions::CONNECT).await?;
+    Ok(())
+}
+
 pub async fn voice_ws(
     ws: WebSocketUpgrade,
+    State(db): State<Db>,
     State(voice_sender): State<VoiceSender>,
     State(update_sender): State<Sender>,
     State(voice_state): State<VoiceState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| {
-        handle_voice_socket(socket, voice_sender, update_sender, voice_state)
-    })
+    AuthUser(user): AuthUser,
+    Query(params): Query<EncodingParams>,
+) -> Result<impl IntoResponse, ServerErr> {
+    let encoder = Encoder::new(params)?;
+    Ok(ws.on_upgrade(move |socket| {
+        handle_voice_socket(
+            socket,
+            db,
+            user.id,
+            voice_sender,
+            update_sender,
+            voice_state,
+            params.encoding,
+            encoder,
+        )
+    }))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_voice_socket(
     socket: WebSocket,
+    db: Db,
+    user_id: UserId,
     voice_sender: VoiceSender,
     update_sender: Sender,
     voice_state: VoiceState,
+    encoding: Encoding,
+    mut encoder: Encoder,
 ) {
     let (mut ws_sender, mut ws_receiver) = socket.split();
     let mut rx = voice_sender.subscribe();
@@ -92,14 +142,15 @@ async fn handle_voice_socket(
     // Spawn task to send broadcasts to this client
     let mut send_task = tokio::spawn(async move {
         while let Ok(signal) = rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&signal) {
-                if ws_sender
-                    .send(axum::extract::ws::Message::Text(json.into()))
-                    .await
-                    .is_err()
-                {
-                    break;
+            let message = match encoder.encode(&signal) {
+                Ok(message) => message,
+                Err(err) => {
+                    tracing::error!("Error encoding voice signal: {err:?}");
+                    continue;
                 }
+            };
+            if ws_sender.send(message).await.is_err() {
+                break;
             }
         }
     });
@@ -110,44 +161,42 @@ async fn handle_voice_socket(
     let state = voice_state.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = ws_receiver.next().await {
-            if let axum::extract::ws::Message::Text(text) = msg {
-                if let Ok(signal) = serde_json::from_str::<VoiceSignal>(&text) {
-                    // Track voice state and send SSE updates
-                    match &signal {
-                        VoiceSignal::Join {
-                            user_id,
-                            channel_id,
-                        } => {
-                            state.add_user(*channel_id, *user_id).await;
-                            let event = Event::default()
-                                .json_data(Update::VoiceJoin {
-                                    user_id: *user_id,
-                                    channel_id: *channel_id,
-                                })
-                                .ok();
-                            if let Some(evt) = event {
-                                let _ = update_tx.send(evt);
-                            }
-                        }
-                        VoiceSignal::Leave {
-                            user_id,
-                            channel_id,
-                        } => {
-                            state.remove_user(*channel_id, *user_id).await;
-                            let event = Event::default()
-                                .json_data(Update::VoiceLeave {
-                                    user_id: *user_id,
-                                    channel_id: *channel_id,
-                                })
-                                .ok();
-                            if let Some(evt) = event {
-                                let _ = update_tx.send(evt);
-                            }
-                        }
-                        _ => {}
+            if let Some(Ok(signal)) = encoding.decode::<VoiceSignal>(&msg) {
+                if signal.sender() != user_id {
+                    tracing::warn!("Dropping voice signal from {user_id} sent as another user");
+                    continue;
+                }
+                if let VoiceSignal::Join { channel_id, .. } = &signal
+                    && let Err(err) = check_connect(&db, *channel_id, user_id).await
+                {
+                    tracing::warn!("Rejecting voice join from {user_id}: {err}");
+                    continue;
+                }
+                // Track voice state and send SSE updates
+                match &signal {
+                    VoiceSignal::Join {
+                        user_id,
+                        channel_id,
+                    } => {
+                        state.add_user(*channel_id, *user_id).await;
+                        let _ = update_tx.send(Update::VoiceJoin {
+                            user_id: *user_id,
+                            channel_id: *channel_id,
+                        });
+                    }
+                    VoiceSignal::Leave {
+                        user_id,
+                        channel_id,
+                    } => {
+                        state.remove_user(*channel_id, *user_id).await;
+                        let _ = update_tx.send(Update::VoiceLeave {
+                            user_id: *user_id,
+                            channel_id: *channel_id,
+                        });
                     }
-                    let _ = tx.send(signal);
+                    _ => {}
                 }
+                let _ = tx.send(signal);
             }
         }
     });
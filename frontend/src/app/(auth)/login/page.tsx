@@ -1,22 +1,31 @@
 'use client';
-import { User } from '@/bindings/User';
+import { Session } from '@/bindings/Session';
 import { useRouter } from 'next/navigation';
 import { useApp } from '@/state/app-state';
+import { useState } from 'react';
 
 export default function Login() {
   const { setUserId } = useApp();
   const r = useRouter();
 
-  async function create_user(name: string) {
-		const res = await fetch(`/create-user?name=${encodeURIComponent(name)}`, {
+  const [error, setError] = useState<string | null>(null);
+
+  async function authenticate(path: '/login' | '/create-user', name: string, password: string) {
+		const res = await fetch(path, {
       method: 'POST',
-      headers: { accept: 'application/json' },
+      headers: { accept: 'application/json', 'content-type': 'application/json' },
+      body: JSON.stringify({ name, password }),
     });
 		try {
-			const parsed: User = await res.json();
-			setUserId(parsed.id);
+			if (!res.ok) {
+				setError(await res.json());
+				return;
+			}
+			const parsed: Session = await res.json();
+			setUserId(parsed.user.id);
 		} catch {
 			console.error(`Unable to parse server response: ${res}`);
+			return;
 		}
     r.replace(`/`);
   }
@@ -29,7 +38,9 @@ export default function Login() {
           e.preventDefault();
           const data = new FormData(e.currentTarget as HTMLFormElement);
           const name = (data.get('name') as string)?.trim();
-          if (name) create_user(name);
+          const password = data.get('password') as string;
+          const register = (e.nativeEvent as SubmitEvent).submitter?.getAttribute('value') === 'register';
+          if (name && password) authenticate(register ? '/create-user' : '/login', name, password);
         }}
       >
         <h1 className="text-2xl font-bold text-white text-center">Welcome!</h1>
@@ -37,7 +48,13 @@ export default function Login() {
           <label className="text-xs font-semibold text-[#b5bac1] uppercase">Username</label>
           <input name="name" className="px-3 py-2.5 rounded bg-[#1e1f22] border border-[#1e1f22] focus:border-[#00a8fc] outline-none text-white" placeholder="Enter your username" autoFocus />
         </div>
-        <button className="bg-[#5865f2] hover:bg-[#4752c4] text-white rounded py-2.5 font-medium transition-colors">Continue</button>
+        <div className="grid gap-2">
+          <label className="text-xs font-semibold text-[#b5bac1] uppercase">Password</label>
+          <input name="password" type="password" className="px-3 py-2.5 rounded bg-[#1e1f22] border border-[#1e1f22] focus:border-[#00a8fc] outline-none text-white" placeholder="Enter your password" />
+        </div>
+        {error && <div className="text-sm text-[#f23f43]">{error}</div>}
+        <button value="login" className="bg-[#5865f2] hover:bg-[#4752c4] text-white rounded py-2.5 font-medium transition-colors">Log In</button>
+        <button value="register" className="bg-[#4e5058] hover:bg-[#6d6f78] text-white rounded py-2.5 font-medium transition-colors">Register</button>
       </form>
     </main>
   );
@@ -22,7 +22,7 @@ export default function Home() {
 			if (server && channel) {
 				r.replace(`/ui?server_id=${server.id}&channel_id=${channel.id}`);
 			} else {
-				r.replace(`/404.html`);
+				r.replace(`/ui`);
 			}
 
     }
@@ -3,12 +3,14 @@
 import { Suspense, useState } from 'react';
 import Link from 'next/link';
 import { useRouter, useSearchParams } from 'next/navigation';
-import { useApp } from '@/state/app-state';
+import { useApp, dmName } from '@/state/app-state';
 import { Modal } from '@/components/Modal';
-import { ServerPill, PlusButton } from '@/components/UI';
+import { ServerPill, PlusButton, MentionBadge } from '@/components/UI';
 import { VoiceChannel } from '@/components/VoiceChannel';
 import { Server } from '@/bindings/Server';
 import { Channel } from '@/bindings/Channel';
+import { ServerMember } from '@/bindings/ServerMember';
+import { Invite } from '@/bindings/Invite';
 
 export default function AppLayout({ children }: { children: React.ReactNode }) {
 	const { userId, snapshot } = useApp();
@@ -92,12 +94,29 @@ export function UiSidebar() {
 		setOpenNewServer(false);
   }
 
+  async function redeem_invite(code: string) {
+    const res = await fetch(`/redeem-invite?code=${encodeURIComponent(code)}`, {
+      method: 'POST',
+      headers: { accept: 'application/json' },
+    });
+    try {
+      const parsed: ServerMember = await res.json();
+			console.log(parsed);
+    } catch {
+      console.error(`Unable to parse server response`, res);
+    }
+		setOpenNewServer(false);
+  }
+
 	if (!snapshot) return (<div>No snapshot found</div>);
 	if (!userId) return (<div>No user id found</div>);
+	const dm_mentions = snapshot.dms.reduce((sum, c) => sum + (snapshot.read_states[c.id]?.mention_count ?? 0), 0);
 
 	return (
 		<aside className="bg-[#1e1f22] grid grid-rows-[auto_2px_1fr_auto] gap-2 p-3">
-			<Link href="/" className="w-12 h-12 rounded-2xl bg-[#313338] hover:bg-[#5865f2] hover:rounded-xl transition-all duration-200 grid place-items-center">
+			{/* Direct messages */}
+			<Link href="/ui" className="relative w-12 h-12 rounded-2xl bg-[#313338] hover:bg-[#5865f2] hover:rounded-xl transition-all duration-200 grid place-items-center">
+				{!!dm_mentions && <MentionBadge count={dm_mentions} className="absolute -bottom-1 -right-1"/>}
 				<svg className="w-6 h-6 text-[#b5bac1]" fill="currentColor" viewBox="0 0 24 24">
 					<path d="M12 2L2 7v10c0 5.55 3.84 10.74 9 12 5.16-1.26 9-6.45 9-12V7l-10-5z"/>
 				</svg>
@@ -105,7 +124,8 @@ export function UiSidebar() {
 			<div className="w-full h-[2px] bg-[#35363c] rounded-full"></div>
 			<div className="grid gap-2 content-start">
 				{Object.values(snapshot.servers).filter(s => s !== undefined).map(s => (
-					<ServerPill key={s.id} href={`/ui?server_id=${s.id}`} label={s.name} active={s.id === server_id}/>
+					<ServerPill key={s.id} href={`/ui?server_id=${s.id}`} label={s.name} active={s.id === server_id}
+						unread={!!snapshot.unreads[s.id]?.unread_count} mentions={snapshot.unreads[s.id]?.mention_count}/>
 				))}
 				<PlusButton onClick={() => setOpenNewServer(true)} />
 			</div>
@@ -128,6 +148,96 @@ export function UiSidebar() {
 					<input name="name" className="px-3 py-2 rounded bg-[#1e1f22] border border-[#1e1f22] focus:border-[#00a8fc] outline-none text-white" placeholder="Server name" autoFocus />
 					<button className="bg-[#5865f2] hover:bg-[#4752c4] text-white rounded px-4 py-2 font-medium transition-colors">Create</button>
 				</form>
+				<form className="grid gap-4 mt-6" onSubmit={(e) => {
+					e.preventDefault();
+					const code = (new FormData(e.currentTarget).get('code') as string)?.trim();
+					if (!code) return;
+					redeem_invite(code);
+				}}>
+					<h2 className="font-semibold text-white text-xl">Join Server</h2>
+					<input name="code" className="px-3 py-2 rounded bg-[#1e1f22] border border-[#1e1f22] focus:border-[#00a8fc] outline-none text-white" placeholder="Invite code" />
+					<button className="bg-[#4e5058] hover:bg-[#6d6f78] text-white rounded px-4 py-2 font-medium transition-colors">Join</button>
+				</form>
+			</Modal>
+		</aside>
+	);
+}
+
+// The channel list when no server is open.
+export function UiDmList() {
+	const { userId, snapshot } = useApp();
+	const params = useSearchParams();
+	const channel_id = parseInt(params.get('channel_id') ?? '');
+	const [openNewDm, setOpenNewDm] = useState(false);
+	const r = useRouter();
+
+	// One name opens a 1:1 DM, more create a group DM.
+	async function open_dm(names: string[]) {
+		const user_ids = names.map(name => Object.values(snapshot?.users ?? {}).find(u => u?.name === name)?.id);
+		if (user_ids.some(id => id === undefined)) {
+			console.error(`Unknown user in ${names.join(', ')}`);
+			return;
+		}
+		const res = user_ids.length === 1
+			? await fetch(`/open-dm?user_id=${user_ids[0]}`, { method: 'POST', headers: { accept: 'application/json' } })
+			: await fetch(`/create-group-dm`, {
+				method: 'POST',
+				headers: { accept: 'application/json', 'content-type': 'application/json' },
+				body: JSON.stringify({ user_ids }),
+			});
+		if (!res.ok) {
+			console.error(`Unable to open DM`, res);
+			return;
+		}
+		const channel: Channel = await res.json();
+		setOpenNewDm(false);
+		r.push(`/ui?channel_id=${channel.id}`);
+	}
+
+	async function leave_dm(channel: Channel) {
+		const res = await fetch(`/remove-recipient?channel_id=${channel.id}&user_id=${userId}`, { method: 'POST' });
+		if (res.ok && channel.id === channel_id) r.replace('/ui');
+	}
+
+	if (!snapshot) return <aside className="bg-[#2b2d31]"/>;
+
+	return (
+		<aside className="bg-[#2b2d31] grid grid-rows-[48px_1fr]">
+			<div className="border-b border-[#1e1f22] px-4 grid grid-cols-[1fr_auto] items-center gap-2 shadow-sm">
+				<h1 className="font-semibold text-white text-base truncate">Direct Messages</h1>
+				<button onClick={() => setOpenNewDm(true)} className="text-[#949ba4] hover:text-[#dbdee1] transition-colors">
+					<svg className="w-4 h-4" fill="currentColor" viewBox="0 0 24 24">
+						<path d="M19 13h-6v6h-2v-6H5v-2h6V5h2v6h6v2z"/>
+					</svg>
+				</button>
+			</div>
+			<div className="overflow-auto px-2 py-3 grid gap-0.5 content-start">
+				{snapshot.dms.map(c => {
+					const read_state = snapshot.read_states[c.id];
+					const unread = !!read_state?.unread_count && channel_id !== c.id;
+					return (
+					<Link key={c.id} href={`/ui?channel_id=${c.id}`}
+						className={`px-2 py-1.5 rounded mx-1 grid grid-cols-[1fr_auto_auto] items-center gap-1.5 ${channel_id === c.id ? 'bg-[#404249] text-white' : unread ? 'text-white hover:bg-[#35373c]' : 'text-[#949ba4] hover:bg-[#35373c] hover:text-[#dbdee1]'}`}>
+						<span className={`text-[15px] truncate ${unread ? 'font-semibold' : 'font-medium'}`}>{dmName(snapshot, c, userId)}</span>
+						{read_state?.mention_count ? <MentionBadge count={read_state.mention_count}/> : <span/>}
+						{c.kind === 'GroupDm'
+							? <button className="text-xs hover:text-[#f23f43]" onClick={(e) => { e.preventDefault(); leave_dm(c); }}>Leave</button>
+							: <span/>}
+					</Link>
+					);
+				})}
+			</div>
+			<Modal open={openNewDm} onClose={() => setOpenNewDm(false)}>
+				<form className="grid gap-4" onSubmit={(e) => {
+					e.preventDefault();
+					const names = (new FormData(e.currentTarget).get('names') as string).split(',').map(n => n.trim()).filter(n => n);
+					if (names.length === 0) return;
+					open_dm(names);
+				}}>
+					<h2 className="font-semibold text-white text-xl">New Message</h2>
+					<input name="names" className="px-3 py-2 rounded bg-[#1e1f22] border border-[#1e1f22] focus:border-[#00a8fc] outline-none text-white" placeholder="Usernames, comma separated" autoFocus />
+					<button className="bg-[#5865f2] hover:bg-[#4752c4] text-white rounded px-4 py-2 font-medium transition-colors">Open</button>
+				</form>
 			</Modal>
 		</aside>
 	);
@@ -139,6 +249,7 @@ export function UiChannelList() {
 	const server_id = parseInt(params.get('server_id') ?? '');
 	const channel_id = parseInt(params.get('channel_id') ?? '');
 	const [openNewChannel, setOpenNewChannel] = useState(false);
+	const [invite, setInvite] = useState<Invite | null>(null);
 	const r = useRouter();
 
   async function create_channel(name: string, server_id: number) {
@@ -155,17 +266,37 @@ export function UiChannelList() {
 		setOpenNewChannel(false);
   }
 
+  async function create_invite(server_id: number) {
+    const res = await fetch(`/create-invite?server_id=${server_id}`, {
+      method: 'POST',
+      headers: { accept: 'application/json' },
+    });
+    try {
+      const parsed: Invite = await res.json();
+			setInvite(parsed);
+    } catch {
+      console.error(`Unable to parse server response`, res);
+    }
+  }
+
+  async function leave_server(server_id: number) {
+    const res = await fetch(`/leave-server?server_id=${server_id}`, { method: 'POST' });
+		if (res.ok) r.replace('/');
+  }
+
+	if (!params.has('server_id')) return <UiDmList/>;
 	const server: Server | undefined = snapshot?.servers[server_id];
 	const channels: Channel[] | undefined = snapshot?.channels[server_id];
 	if (!server || !channels) {
-		r.replace('/');
-		return null;
+		return <aside className="bg-[#2b2d31]"/>;
 	}
 
 	return (
 		<aside className="bg-[#2b2d31] grid grid-rows-[48px_1fr]">
-			<div className="border-b border-[#1e1f22] px-4 grid items-center shadow-sm">
+			<div className="border-b border-[#1e1f22] px-4 grid grid-cols-[1fr_auto_auto] items-center gap-2 shadow-sm">
 				<h1 className="font-semibold text-white text-base truncate">{server.name}</h1>
+				<button onClick={() => create_invite(server_id)} className="text-xs text-[#949ba4] hover:text-[#dbdee1] transition-colors">Invite</button>
+				<button onClick={() => leave_server(server_id)} className="text-xs text-[#949ba4] hover:text-[#f23f43] transition-colors">Leave</button>
 			</div>
 			<div className="overflow-auto px-2 py-3">
 				{/* Voice Channels Section */}
@@ -186,17 +317,28 @@ export function UiChannelList() {
 					</button>
 				</div>
 				<div className="grid gap-0.5">
-					{channels.map(c => (
+					{channels.map(c => {
+						const read_state = snapshot?.read_states[c.id];
+						const unread = !!read_state?.unread_count && channel_id !== c.id;
+						return (
 						<Link key={c.id} href={`/ui?server_id=${server_id}&channel_id=${c.id}`}
-							className={`px-2 py-1.5 rounded mx-1 grid grid-cols-[auto_1fr] items-center gap-1.5 ${channel_id === c.id ? 'bg-[#404249] text-white' : 'text-[#949ba4] hover:bg-[#35373c] hover:text-[#dbdee1]'}`}>
+							className={`px-2 py-1.5 rounded mx-1 grid grid-cols-[auto_1fr_auto] items-center gap-1.5 ${channel_id === c.id ? 'bg-[#404249] text-white' : unread ? 'text-white hover:bg-[#35373c]' : 'text-[#949ba4] hover:bg-[#35373c] hover:text-[#dbdee1]'}`}>
 							<svg className="w-5 h-5 text-[#80848e]" fill="currentColor" viewBox="0 0 24 24">
 								<path d="M5.88657 21C5.57547 21 5.3399 20.7189 5.39427 20.4126L6.00001 17H2.59511C2.28449 17 2.04905 16.7198 2.10259 16.4138L2.27759 15.4138C2.31946 15.1746 2.52722 15 2.77011 15H6.35001L7.41001 9H4.00511C3.69449 9 3.45905 8.71977 3.51259 8.41381L3.68759 7.41381C3.72946 7.17456 3.93722 7 4.18011 7H7.76001L8.39677 3.41262C8.43914 3.17391 8.64664 3 8.88907 3H9.87344C10.1845 3 10.4201 3.28107 10.3657 3.58738L9.76001 7H15.76L16.3968 3.41262C16.4391 3.17391 16.6466 3 16.8891 3H17.8734C18.1845 3 18.4201 3.28107 18.3657 3.58738L17.76 7H21.1649C21.4755 7 21.711 7.28023 21.6574 7.58619L21.4824 8.58619C21.4406 8.82544 21.2328 9 20.9899 9H17.41L16.35 15H19.7549C20.0655 15 20.301 15.2802 20.2474 15.5862L20.0724 16.5862C20.0306 16.8254 19.8228 17 19.5799 17H16L15.3632 20.5874C15.3209 20.8261 15.1134 21 14.8709 21H13.8866C13.5755 21 13.3399 20.7189 13.3943 20.4126L14 17H8.00001L7.36325 20.5874C7.32088 20.8261 7.11337 21 6.87094 21H5.88657ZM9.41045 9L8.35045 15H14.3504L15.4104 9H9.41045Z"/>
 							</svg>
-							<span className="text-[15px] font-medium">{c.name}</span>
+							<span className={`text-[15px] ${unread ? 'font-semibold' : 'font-medium'}`}>{c.name}</span>
+							{read_state?.mention_count ? <MentionBadge count={read_state.mention_count}/> : <span/>}
 						</Link>
-					))}
+						);
+					})}
 				</div>
 			</div>
+			<Modal open={invite !== null} onClose={() => setInvite(null)}>
+				<div className="grid gap-4">
+					<h2 className="font-semibold text-white text-xl">Invite to {server.name}</h2>
+					<input readOnly value={invite?.code ?? ''} onFocus={(e) => e.currentTarget.select()} className="px-3 py-2 rounded bg-[#1e1f22] text-white font-mono" />
+				</div>
+			</Modal>
 			<Modal open={openNewChannel} onClose={() => setOpenNewChannel(false)}>
 				<form className="grid gap-4" onSubmit={(e) => {
 					e.preventDefault();
@@ -1,10 +1,16 @@
 'use client';
 
 import { Suspense } from 'react';
-import { useApp } from '@/state/app-state';
+import Link from 'next/link';
+import { useApp, channelMessages, setChannelMessages, dmName } from '@/state/app-state';
 import { useRouter, useSearchParams } from 'next/navigation';
-import { useRef } from 'react';
+import { useEffect, useRef, useState } from 'react';
 import { Message } from '@/bindings/Message';
+import { Attachment } from '@/bindings/Attachment';
+import { ReactionCount } from '@/bindings/ReactionCount';
+import { Thread } from '@/bindings/Thread';
+import { SearchHit } from '@/bindings/SearchHit';
+import { Snapshot } from '@/bindings/Snapshot';
 
 export default function ChannelPage() {
   return (
@@ -19,51 +25,192 @@ export default function ChannelPage() {
   );
 }
 
+// Splits a search snippet on its <mark> tags so matches can be highlighted
+// without rendering the message text as HTML.
+function Snippet({ snippet }: { snippet: string }) {
+	return (
+		<>
+			{snippet.split(/(<mark>.*?<\/mark>)/).map((part, i) => part.startsWith('<mark>') && part.endsWith('</mark>')
+				? <mark key={i} className="bg-[#5865f2] text-white">{part.slice(6, -7)}</mark>
+				: <span key={i}>{part}</span>)}
+		</>
+	);
+}
+
+// Images, video and audio play inline, anything else is a download link.
+// Images show their thumbnail once the server has made one.
+function AttachmentView({ attachment }: { attachment: Attachment }) {
+	const url = `/attachments/${attachment.id}`;
+	const type = attachment.content_type;
+	if (type.startsWith('image/') && type !== 'image/svg+xml') {
+		return (
+			<a href={url} target="_blank">
+				<img
+					className="max-w-sm max-h-80 rounded"
+					src={attachment.thumbnail_url ?? url}
+					width={attachment.width ?? undefined}
+					height={attachment.height ?? undefined}
+					alt={attachment.filename}
+				/>
+			</a>
+		);
+	}
+	if (type.startsWith('video/')) return <video className="max-w-sm max-h-80 rounded" src={url} controls/>;
+	if (type.startsWith('audio/')) return <audio src={url} controls/>;
+	return (
+		<a className="bg-[#2b2d31] rounded px-3 py-2 text-[#00a8fc] text-sm hover:underline" href={url}>
+			📎 {attachment.filename} <span className="text-[#949ba4] text-xs">({Math.ceil(attachment.size / 1024)} KB)</span>
+		</a>
+	);
+}
+
+// Shows resolved mentions by name. Tokens the server didn't resolve stay as
+// they were typed.
+function MessageText({ message, snapshot }: { message: Message, snapshot: Snapshot }) {
+	const { mentions } = message;
+	const channels = Object.values(snapshot.channels).flat();
+	return (
+		<>
+			{message.text.split(/(<@&?\d+>|<#\d+>|@everyone|@here)/).map((part, i) => {
+				const id = parseInt(part.replace(/\D/g, ''));
+				let label: string | null = null;
+				if (part.startsWith('<@&')) {
+					if (mentions.roles.includes(id)) label = '@role';
+				} else if (part.startsWith('<@')) {
+					if (mentions.users.includes(id)) label = `@${snapshot.users[id]?.name ?? 'unknown user'}`;
+				} else if (part.startsWith('<#')) {
+					if (mentions.channels.includes(id)) label = `#${channels.find(c => c.id === id)?.name ?? 'unknown channel'}`;
+				} else if (part.startsWith('@') && mentions.everyone) {
+					label = part;
+				}
+				return label === null
+					? <span key={i}>{part}</span>
+					: <span key={i} className="bg-[#3c4270] text-[#c9cdfb] rounded px-0.5">{label}</span>;
+			})}
+		</>
+	);
+}
+
 export function UiHeader() {
-	const { snapshot } = useApp();
+	const { userId, snapshot } = useApp();
 	const r = useRouter();
   const params = useSearchParams();
+	const [hits, setHits] = useState<SearchHit[] | null>(null);
 
-	if (!snapshot) {
-		r.replace('/');
-		return null;
+	async function search(text: string) {
+		if (!text.trim()) {
+			setHits(null);
+			return;
+		}
+		// DMs search just the open DM.
+		const scope = server_id === null ? `channel_id=${channel_id}` : `server_id=${server_id}`;
+		const res = await fetch(`/search?${scope}&text=${encodeURIComponent(text)}`, {
+			headers: { accept: 'application/json' },
+		});
+		if (!res.ok) {
+			console.error(`Unable to search`, res);
+			return;
+		}
+		setHits(await res.json());
 	}
 
-  const server_id = parseInt(params.get('server_id') ?? '');
-	if (!server_id) {
-		r.replace('/');
-		return null;
-	}
-	const channel = snapshot.channels[server_id]?.[0];
-	if (!channel) {
+	if (!snapshot) {
 		r.replace('/');
 		return null;
 	}
 
+	const server_param = params.get('server_id');
+	const server_id = server_param === null ? null : parseInt(server_param);
+	const channel_id = parseInt(params.get('channel_id') ?? '');
+	const channel = server_id === null
+		? snapshot.dms.find(c => c.id === channel_id)
+		: snapshot.channels[server_id]?.[0];
+	if (!channel) return null;
+
 	return (
-		<header className="border-b border-[#1e1f22] px-4 h-12 grid grid-cols-[auto_1fr] items-center gap-2 shadow-sm">
+		<header className="relative border-b border-[#1e1f22] px-4 h-12 grid grid-cols-[auto_1fr_auto] items-center gap-2 shadow-sm">
 			<svg className="w-5 h-5 text-[#80848e]" fill="currentColor" viewBox="0 0 24 24">
 				<path d="M5.88657 21C5.57547 21 5.3399 20.7189 5.39427 20.4126L6.00001 17H2.59511C2.28449 17 2.04905 16.7198 2.10259 16.4138L2.27759 15.4138C2.31946 15.1746 2.52722 15 2.77011 15H6.35001L7.41001 9H4.00511C3.69449 9 3.45905 8.71977 3.51259 8.41381L3.68759 7.41381C3.72946 7.17456 3.93722 7 4.18011 7H7.76001L8.39677 3.41262C8.43914 3.17391 8.64664 3 8.88907 3H9.87344C10.1845 3 10.4201 3.28107 10.3657 3.58738L9.76001 7H15.76L16.3968 3.41262C16.4391 3.17391 16.6466 3 16.8891 3H17.8734C18.1845 3 18.4201 3.28107 18.3657 3.58738L17.76 7H21.1649C21.4755 7 21.711 7.28023 21.6574 7.58619L21.4824 8.58619C21.4406 8.82544 21.2328 9 20.9899 9H17.41L16.35 15H19.7549C20.0655 15 20.301 15.2802 20.2474 15.5862L20.0724 16.5862C20.0306 16.8254 19.8228 17 19.5799 17H16L15.3632 20.5874C15.3209 20.8261 15.1134 21 14.8709 21H13.8866C13.5755 21 13.3399 20.7189 13.3943 20.4126L14 17H8.00001L7.36325 20.5874C7.32088 20.8261 7.11337 21 6.87094 21H5.88657ZM9.41045 9L8.35045 15H14.3504L15.4104 9H9.41045Z"/>
 			</svg>
-			<h1 className="font-semibold text-white">{channel.name}</h1>
+			<h1 className="font-semibold text-white">{server_id === null ? dmName(snapshot, channel, userId) : channel.name}</h1>
+			<input
+				className="bg-[#1e1f22] text-[#dbdee1] text-sm rounded px-2 py-1 outline-none w-48"
+				placeholder="Search"
+				onKeyDown={(e) => { if (e.key === 'Enter') search(e.currentTarget.value); }}
+			/>
+			{hits !== null && (
+				<div className="absolute right-4 top-12 z-10 w-96 max-h-96 overflow-auto bg-[#2b2d31] rounded shadow-lg p-2 grid gap-2">
+					{hits.length === 0 && <div className="text-[#949ba4] text-sm">No results</div>}
+					{hits.map(hit => (
+						<Link
+							key={hit.message.id}
+							className="text-sm text-[#dbdee1] hover:bg-[#35373c] rounded p-1"
+							href={`/ui?${hit.message.server_id === null ? '' : `server_id=${hit.message.server_id}&`}channel_id=${hit.message.channel_id}${hit.message.thread_id === null ? '' : `&thread_id=${hit.message.thread_id}`}`}
+							onClick={() => setHits(null)}
+						>
+							<div className="text-[#949ba4] text-xs">{snapshot.users[hit.message.user_id]?.name ?? 'unknown user'} · {new Date(hit.message.ts).toLocaleString()}</div>
+							<Snippet snippet={hit.snippet}/>
+						</Link>
+					))}
+				</div>
+			)}
 		</header>
 	);
 }
 
 export function UiList() {
-  const { userId, snapshot, typingUsers } = useApp();
+  const { userId, snapshot, setSnapshot, typingUsers } = useApp();
+  const r = useRouter();
   const params = useSearchParams();
-  const server_id = parseInt(params.get('server_id') ?? '');
+  // DMs have no server.
+  const server_param = params.get('server_id');
+  const server_id = server_param === null ? null : parseInt(server_param);
   const channel_id = parseInt(params.get('channel_id') ?? '');
-  const list: Message[] = snapshot?.messages[server_id]?.[channel_id] ?? [];
+  const thread_param = params.get('thread_id');
+  const thread_id = thread_param === null ? null : parseInt(thread_param);
+  const channel_url = server_id === null ? `/ui?channel_id=${channel_id}` : `/ui?server_id=${server_id}&channel_id=${channel_id}`;
+  const messages: Message[] = snapshot === null ? [] : channelMessages(snapshot, server_id, channel_id);
+  // Thread messages are channel messages too, so the channel view skips them.
+  const list: Message[] = messages.filter(m => m.thread_id === thread_id);
+  const threads: Thread[] = snapshot?.threads[channel_id] ?? [];
+  const thread = threads.find(t => t.id === thread_id);
   const inputRef = useRef<HTMLInputElement>(null);
+  const filesRef = useRef<HTMLInputElement>(null);
+  const [fileCount, setFileCount] = useState(0);
+  const [replyTo, setReplyTo] = useState<Message | null>(null);
   const typingTimeoutRef = useRef<NodeJS.Timeout | null>(null);
+  const [historyDone, setHistoryDone] = useState<string | null>(null);
+  const history_key = `${channel_id}:${thread_id}`;
+  // Thread messages count as unread too, so viewing the channel reads them.
+  const newest = messages.at(-1)?.id ?? null;
+  const last_read = snapshot?.read_states[channel_id]?.last_read_message_id ?? null;
+
+  useEffect(() => {
+    if (newest === null || (last_read !== null && Number(last_read) >= Number(newest))) return;
+    fetch(`/ack?message_id=${newest}`, { method: 'POST' }).then((res) => {
+      if (!res.ok) console.error(`Unable to mark channel read`, res);
+    });
+  }, [newest, last_read]);
 
-  async function create_message(text: string, server_id: number, channel_id: number) {
-    const res = await fetch(`/create-message?user_id=${userId}&server_id=${server_id}&channel_id=${channel_id}&text=${encodeURIComponent(text)}`, {
+  async function create_message(text: string, server_id: number | null, channel_id: number, thread_id: number | null, reply_to: bigint | null, files: File[]) {
+    const reply = reply_to === null ? '' : `&reply_to=${reply_to}`;
+    const in_thread = thread_id === null ? '' : `&thread_id=${thread_id}`;
+    const in_server = server_id === null ? '' : `server_id=${server_id}&`;
+    const query = `${in_server}channel_id=${channel_id}&text=${encodeURIComponent(text)}${in_thread}${reply}`;
+    let body: FormData | undefined;
+    if (files.length > 0) {
+      body = new FormData();
+      for (const file of files) body.append('files', file);
+    }
+    const res = await fetch(`/${body ? 'create-message-with-attachments' : 'create-message'}?${query}`, {
       method: 'POST',
       headers: { accept: 'application/json' },
+      body,
     });
+    if (!res.ok) {
+      console.error(`Unable to send message`, res);
+      return;
+    }
     try {
       const parsed: Message = await res.json();
       console.log(parsed);
@@ -72,9 +219,88 @@ export function UiList() {
     }
   }
 
+  async function edit_message(message: Message) {
+    const text = window.prompt('Edit message', message.text)?.trim();
+    if (!text || text === message.text) return;
+    const res = await fetch(`/edit-message?message_id=${message.id}&text=${encodeURIComponent(text)}`, {
+      method: 'PATCH',
+      headers: { accept: 'application/json' },
+    });
+    if (!res.ok) {
+      console.error(`Unable to edit message`, res);
+    }
+  }
+
+  async function delete_message(message: Message) {
+    if (!window.confirm('Delete this message?')) return;
+    const res = await fetch(`/delete-message?message_id=${message.id}`, { method: 'POST' });
+    if (!res.ok) {
+      console.error(`Unable to delete message`, res);
+    }
+  }
+
+  async function start_thread(message: Message | null) {
+    const name = window.prompt('Thread name', message?.text.slice(0, 32) ?? '')?.trim();
+    if (!name) return;
+    const starter = message === null ? '' : `&starter_message_id=${message.id}`;
+    const res = await fetch(`/create-thread?channel_id=${channel_id}&name=${encodeURIComponent(name)}${starter}`, {
+      method: 'POST',
+      headers: { accept: 'application/json' },
+    });
+    if (!res.ok) {
+      console.error(`Unable to create thread`, res);
+      return;
+    }
+    const created: Thread = await res.json();
+    r.push(`${channel_url}&thread_id=${created.id}`);
+  }
+
+  async function toggle_reaction(message: Message, reaction: Pick<ReactionCount, 'emoji' | 'emoji_id'>, remove: boolean) {
+    const emoji = reaction.emoji_id === null ? `emoji=${encodeURIComponent(reaction.emoji ?? '')}` : `emoji_id=${reaction.emoji_id}`;
+    const res = await fetch(`/${remove ? 'remove' : 'add'}-reaction?message_id=${message.id}&${emoji}`, { method: 'POST' });
+    if (!res.ok) {
+      console.error(`Unable to update reaction`, res);
+    }
+  }
+
+  function add_reaction(message: Message) {
+    const text = window.prompt('React with an emoji, or :name: for a server emoji')?.trim();
+    if (!text) return;
+    const custom = (server_id === null ? [] : snapshot?.emojis[server_id] ?? []).find(e => `:${e.name}:` === text);
+    toggle_reaction(message, custom ? { emoji: null, emoji_id: custom.id } : { emoji: text, emoji_id: null }, false);
+  }
+
+  function emoji_label(reaction: ReactionCount): string {
+    if (reaction.emoji !== null) return reaction.emoji;
+    const name = (server_id === null ? [] : snapshot?.emojis[server_id] ?? []).find(e => e.id === reaction.emoji_id)?.name;
+    return `:${name ?? 'unknown'}:`;
+  }
+
+  async function load_older() {
+    const in_thread = thread_id === null ? '' : `&thread_id=${thread_id}`;
+    const before = list.length === 0 ? '' : `&before=${list[0].id}`;
+    const res = await fetch(`/channels/${channel_id}/messages?${before}${in_thread}`, {
+      headers: { accept: 'application/json' },
+    });
+    if (!res.ok) {
+      console.error(`Unable to load messages`, res);
+      return;
+    }
+    const older: Message[] = await res.json();
+    if (older.length === 0) {
+      setHistoryDone(history_key);
+      return;
+    }
+    // Updates may have come in while loading, so merge into the latest state.
+    setSnapshot(prev => prev === null ? null : setChannelMessages(prev, server_id, channel_id,
+      [...older, ...channelMessages(prev, server_id, channel_id)]
+        .filter((m, i, all) => all.findIndex(o => o.id === m.id) === i)
+        .sort((a, b) => Number(a.id) - Number(b.id))));
+  }
+
   async function sendTyping(isTyping: boolean) {
     if (!userId) return;
-    await fetch(`/typing?typing=${isTyping}&user_id=${userId}`, {
+    await fetch(`/typing?channel_id=${channel_id}&typing=${isTyping}`, {
       method: 'POST',
     });
   }
@@ -92,24 +318,85 @@ export function UiList() {
     }, 3000);
   }
 
+	const dm = snapshot?.dms.find(c => c.id === channel_id);
+	if (!snapshot || (server_id === null ? !dm : !snapshot.servers[server_id])) return null;
+
 	return (
 		<>
 			<div className="overflow-auto px-4 py-4 grid gap-4 content-start">
+				{dm ? null : thread_id === null ? (
+					<div className="text-[#949ba4] text-xs grid grid-flow-col justify-start gap-3">
+						{threads.map(t => (
+							<Link key={t.id} className="hover:underline" href={`${channel_url}&thread_id=${t.id}`}>🧵 {t.name}</Link>
+						))}
+						<button className="hover:underline" onClick={() => start_thread(null)}>New thread</button>
+					</div>
+				) : (
+					<div className="text-[#949ba4] text-xs grid grid-flow-col justify-start gap-3">
+						<Link className="hover:underline" href={channel_url}>← Back to channel</Link>
+						<span className="text-white">🧵 {thread?.name ?? 'Archived thread'}{thread?.locked && ' (locked)'}</span>
+					</div>
+				)}
+				{historyDone !== history_key && (
+					<button className="text-[#949ba4] text-xs hover:underline justify-self-center" onClick={load_older}>Load older messages</button>
+				)}
 				{list.map(m => (
-					<div key={m.id} className="grid grid-cols-[40px_1fr] gap-4 hover:bg-[#2e3035] -mx-2 px-2 py-1">
+					<div key={m.id} className={`grid grid-cols-[40px_1fr] gap-4 hover:bg-[#2e3035] -mx-2 px-2 py-1 ${userId !== null && (m.mentions.everyone || m.mentions.users.includes(userId)) ? 'bg-[#444037] border-l-2 border-[#f0b232]' : ''}`}>
 						<div className="w-10 h-10 rounded-full bg-[#5865f2] grid place-items-center text-white font-semibold">
 							{(snapshot?.users?.[m.user_id]?.name ?? 'Unknown User').slice(0, 2).toUpperCase()}
 						</div>
 						<div className="grid gap-1">
-							<div className="grid grid-cols-[auto_auto] gap-2 items-baseline">
+							{m.reply_to !== null && (
+								<div className="text-[#949ba4] text-xs truncate">
+									{m.reply_excerpt === null
+										? 'Original message was deleted'
+										: `↪ ${snapshot?.users?.[m.reply_user_id ?? -1]?.name ?? 'unknown user'}: ${m.reply_excerpt}`}
+								</div>
+							)}
+							<div className="grid grid-flow-col justify-start gap-2 items-baseline">
 								<span className="font-medium text-white">{snapshot?.users?.[m.user_id]?.name ?? 'unknown user'}</span>
 								<span className="text-[#949ba4] text-xs">{new Date(m.ts).toLocaleTimeString()}</span>
+								<button className="text-[#949ba4] text-xs hover:underline" onClick={() => { setReplyTo(m); inputRef.current?.focus(); }}>Reply</button>
+								<button className="text-[#949ba4] text-xs hover:underline" onClick={() => add_reaction(m)}>React</button>
+								{!dm && thread_id === null && !threads.some(t => t.starter_message_id === m.id) && (
+									<button className="text-[#949ba4] text-xs hover:underline" onClick={() => start_thread(m)}>Thread</button>
+								)}
+								{m.user_id === userId && (
+									<button className="text-[#949ba4] text-xs hover:underline" onClick={() => edit_message(m)}>Edit</button>
+								)}
+								{m.user_id === userId && (
+									<button className="text-[#949ba4] text-xs hover:underline" onClick={() => delete_message(m)}>Delete</button>
+								)}
+							</div>
+							<div className="text-[#dbdee1]">
+								<MessageText message={m} snapshot={snapshot}/>
+								{m.edited_ts && <span className="text-[#949ba4] text-xs ml-1">(edited)</span>}
 							</div>
-							<div className="text-[#dbdee1]">{m.text}</div>
+							{m.attachments.length > 0 && (
+								<div className="grid gap-1 justify-items-start">
+									{m.attachments.map(a => <AttachmentView key={a.id} attachment={a}/>)}
+								</div>
+							)}
+							{m.reactions.length > 0 && (
+								<div className="grid grid-flow-col justify-start gap-1">
+									{m.reactions.map(r => (
+										<button
+											key={r.emoji ?? `custom-${r.emoji_id}`}
+											className={`px-2 rounded-lg text-xs text-[#dbdee1] border ${r.me ? 'bg-[#373a6b] border-[#5865f2]' : 'bg-[#2b2d31] border-transparent'}`}
+											onClick={() => toggle_reaction(m, r, r.me)}
+										>
+											{emoji_label(r)} {r.count}
+										</button>
+									))}
+								</div>
+							)}
+							{threads.filter(t => t.starter_message_id === m.id).map(t => (
+								<Link key={t.id} className="text-[#00a8fc] text-xs hover:underline" href={`${channel_url}&thread_id=${t.id}`}>🧵 {t.name}</Link>
+							))}
 						</div>
 					</div>
 				))}
-				{Array.from(typingUsers).filter(id => id !== userId).map(id => (
+				{Array.from(typingUsers.get(channel_id) ?? []).filter(id => id !== userId).map(id => (
 					<div key={`typing-${id}`} className="grid grid-cols-[40px_1fr] gap-4 -mx-2 px-2 py-1">
 						<div className="w-10 h-10 rounded-full bg-[#5865f2] grid place-items-center text-white font-semibold">
 							{(snapshot?.users?.[id]?.name ?? 'Unknown User').slice(0, 2).toUpperCase()}
@@ -127,23 +414,43 @@ export function UiList() {
         className="px-4 pb-6 pt-0"
         onSubmit={(e) => {
           e.preventDefault();
-          const text = inputRef.current?.value?.trim();
-          if (!text) return;
+          const text = inputRef.current?.value?.trim() ?? '';
+          const files = Array.from(filesRef.current?.files ?? []);
+          if (!text && files.length === 0) return;
 					if (typingTimeoutRef.current) {
 						clearTimeout(typingTimeoutRef.current);
 						typingTimeoutRef.current = null;
 						sendTyping(false);
 					}
-					create_message(text, server_id, channel_id);
+					create_message(text, server_id, channel_id, thread_id, replyTo?.id ?? null, files);
+					setReplyTo(null);
 					if (inputRef.current) inputRef.current.value = '';
+					if (filesRef.current) filesRef.current.value = '';
+					setFileCount(0);
         }}
       >
+				{replyTo && (
+					<div className="text-[#949ba4] text-xs pb-1 grid grid-cols-[1fr_auto]">
+						<span className="truncate">Replying to {snapshot?.users?.[replyTo.user_id]?.name ?? 'unknown user'}</span>
+						<button type="button" className="hover:underline" onClick={() => setReplyTo(null)}>Cancel</button>
+					</div>
+				)}
+				<div className="grid grid-cols-[auto_1fr] gap-2 items-center">
+				<label className="w-10 h-10 rounded-full bg-[#383a40] text-[#b5bac1] grid place-items-center cursor-pointer hover:text-white" title="Attach files">
+					{fileCount > 0 ? fileCount : '+'}
+					<input ref={filesRef} type="file" multiple className="hidden" onChange={(e) => setFileCount(e.currentTarget.files?.length ?? 0)}/>
+				</label>
         <input
 					ref={inputRef}
 					className="w-full px-4 py-3 rounded-lg bg-[#383a40] text-white placeholder-[#6d6f78] outline-none"
-					placeholder={`Message #${snapshot?.channels[server_id]?.find(c => c.id == channel_id)?.name ?? 'unknown channel'}`}
+					placeholder={dm
+						? `Message @${dmName(snapshot, dm, userId)}`
+						: thread_id === null
+						? `Message #${snapshot.channels[server_id ?? -1]?.find(c => c.id == channel_id)?.name ?? 'unknown channel'}`
+						: `Message 🧵${thread?.name ?? 'thread'}`}
 					onChange={handleInputChange}
 				/>
+				</div>
       </form>
 		</>
 	)
@@ -0,0 +1,27 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A file sent with a message.
+ */
+export type Attachment = { id: number, message_id: bigint, filename: string, content_type: string, 
+/**
+ * In bytes.
+ */
+size: number, 
+/**
+ * SHA-256 of the content, hex encoded.
+ */
+hash: string, 
+/**
+ * Pixel size, for images once they've been processed.
+ */
+width: number | null, height: number | null, 
+/**
+ * SHA-256 of the image's thumbnail, once it has one.
+ */
+thumbnail_hash: string | null, 
+/**
+ * Where to get the thumbnail. Filled in from `thumbnail_hash` rather
+ * than stored.
+ */
+thumbnail_url: string | null, };
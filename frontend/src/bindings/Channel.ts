@@ -1,3 +1,12 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ChannelKind } from "./ChannelKind";
 
-export type Channel = { server_id: number, id: number, name: string, };
+export type Channel = { 
+/**
+ * Unset for DMs.
+ */
+server_id: number | null, id: number, name: string, kind: ChannelKind, 
+/**
+ * Who can remove others from a group DM. Unset for other channels.
+ */
+owner_id: number | null, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Server channels are text channels; DMs belong to their recipients
+ * instead of a server.
+ */
+export type ChannelKind = "Text" | "Dm" | "GroupDm";
@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Permissions allowed or denied in one channel for a role or a single
+ * member, on top of what their roles grant server-wide. Exactly one of
+ * `role_id` and `user_id` is set.
+ */
+export type ChannelOverwrite = { channel_id: number, role_id: number | null, user_id: number | null, 
+/**
+ * `Permissions` bits.
+ */
+allow: number, 
+/**
+ * `Permissions` bits.
+ */
+deny: number, };
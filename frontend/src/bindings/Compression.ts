@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Compression of everything the server sends on a connection, chosen with
+ * `?compress=`. The connection shares one compression stream, flushed after
+ * each event, so every event is one binary frame and the client keeps one
+ * decompressor for the whole connection.
+ */
+export type Compression = "zlib-stream" | "zstd-stream";
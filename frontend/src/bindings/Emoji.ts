@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A custom emoji members of its server can react with.
+ */
+export type Emoji = { id: number, server_id: number, 
+/**
+ * Letters, digits and underscores, unique within the server.
+ */
+name: string, 
+/**
+ * Unset if the creator's account was deleted.
+ */
+creator_id: number | null, created_ts: string, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How events are written on the WebSockets, chosen per connection with
+ * `?encoding=`. JSON goes in text frames and the others in binary frames.
+ */
+export type Encoding = "json" | "msgpack" | "cbor";
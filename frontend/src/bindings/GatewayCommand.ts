@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PresenceStatus } from "./PresenceStatus";
+
+/**
+ * Sent by the client, in the same form as `GatewayEvent`.
+ */
+export type GatewayCommand = { "op": "Identify" } | { "op": "Resume", "d": { seq: number, } } | { "op": "Heartbeat" } | { "op": "Typing", "d": { channel_id: number, typing: boolean, } } | { "op": "Presence", "d": { status: PresenceStatus, } };
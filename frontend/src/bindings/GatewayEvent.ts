@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Update } from "./Update";
+
+/**
+ * Sent by the server as `{"op": ..., "d": ...}`, in the connection's
+ * encoding.
+ */
+export type GatewayEvent = { "op": "Hello", "d": { heartbeat_interval_ms: number, } } | { "op": "Ready", "d": { seq: number, } } | { "op": "Dispatch", "d": { seq: number, update: Update, } } | { "op": "HeartbeatAck" } | { "op": "Reconnect" } | { "op": "InvalidSession" };
@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Invite = { code: string, server_id: number, 
+/**
+ * The channel the invite points at, if any.
+ */
+channel_id: number | null, 
+/**
+ * Unset for invites made by the server itself.
+ */
+creator_id: number | null, created_ts: string, 
+/**
+ * Never expires if unset.
+ */
+expires_ts: string | null, 
+/**
+ * Unlimited if unset.
+ */
+max_uses: number | null, uses: number, };
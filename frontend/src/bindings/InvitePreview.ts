@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Channel } from "./Channel";
+import type { Server } from "./Server";
+
+/**
+ * What someone holding an invite code can see before redeeming it.
+ */
+export type InvitePreview = { code: string, server: Server, channel: Channel | null, member_count: bigint, expires_ts: string | null, };
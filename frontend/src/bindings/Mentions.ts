@@ -0,0 +1,22 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Who and what a message mentions, resolved from `<@user_id>`,
+ * `<@&role_id>`, `<#channel_id>`, `@everyone` and `@here` in its text.
+ * Mentions of things outside the message's server are dropped, and so are
+ * role and everyone mentions by authors without MENTION_EVERYONE. In DMs
+ * only recipients can be mentioned.
+ */
+export type Mentions = { 
+/**
+ * Members of the server.
+ */
+users: Array<number>, roles: Array<number>, 
+/**
+ * Channels of the server.
+ */
+channels: Array<number>, 
+/**
+ * Whether the message mentions `@everyone` or `@here`.
+ */
+everyone: boolean, };
@@ -1,3 +1,40 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Attachment } from "./Attachment";
+import type { Mentions } from "./Mentions";
+import type { ReactionCount } from "./ReactionCount";
 
-export type Message = { user_id: number, channel_id: number, server_id: number, ts: string, id: bigint, text: string, };
+export type Message = { user_id: number, channel_id: number, 
+/**
+ * Unset in DMs.
+ */
+server_id: number | null, 
+/**
+ * The thread in the channel this was posted in, if any.
+ */
+thread_id: number | null, ts: string, id: bigint, text: string, 
+/**
+ * When the text was last changed. Unset if it never was.
+ */
+edited_ts: string | null, 
+/**
+ * The message this replies to. Stays set if that message is deleted.
+ */
+reply_to: bigint | null, 
+/**
+ * Author of the replied-to message. Unset if it was deleted.
+ */
+reply_user_id: number | null, 
+/**
+ * Start of the replied-to message's text. Unset if it was deleted.
+ */
+reply_excerpt: string | null, mentions: Mentions, 
+/**
+ * Reactions as seen by the requesting user. Only filled in where
+ * messages are fetched, not in updates.
+ */
+reactions: Array<ReactionCount>, 
+/**
+ * Files sent with the message. Not filled in by storage, and left out
+ * of edit updates since they can't change.
+ */
+attachments: Array<Attachment>, };
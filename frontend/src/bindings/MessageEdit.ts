@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A previous version of a message's text.
+ */
+export type MessageEdit = { message_id: bigint, text: string, 
+/**
+ * When this text was replaced.
+ */
+edited_ts: string, };
@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * One user's reaction, as sent in updates. Exactly one of `emoji` and
+ * `emoji_id` is set.
+ */
+export type Reaction = { message_id: bigint, channel_id: number, 
+/**
+ * Unset in DMs.
+ */
+server_id: number | null, user_id: number, 
+/**
+ * A Unicode emoji.
+ */
+emoji: string | null, 
+/**
+ * One of the server's custom emoji.
+ */
+emoji_id: number | null, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How many users reacted to a message with one emoji.
+ */
+export type ReactionCount = { emoji: string | null, emoji_id: number | null, count: number, 
+/**
+ * Whether the requesting user is one of them.
+ */
+me: boolean, };
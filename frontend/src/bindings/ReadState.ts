@@ -0,0 +1,23 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How far a user has read a channel.
+ */
+export type ReadState = { user_id: number, channel_id: number, 
+/**
+ * Unset for DMs.
+ */
+server_id: number | null, 
+/**
+ * Unset if the user never read the channel.
+ */
+last_read_message_id: bigint | null, 
+/**
+ * Messages after the last read one, threads included.
+ */
+unread_count: number, 
+/**
+ * Unread messages mentioning the user, directly, by role or with
+ * `@everyone`.
+ */
+mention_count: number, };
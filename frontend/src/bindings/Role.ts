@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A named set of permissions in a server. Position 0 is the server's
+ * @everyone role; higher positions outrank lower ones.
+ */
+export type Role = { id: number, server_id: number, name: string, 
+/**
+ * `Permissions` bits.
+ */
+permissions: number, position: number, };
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Message } from "./Message";
+
+/**
+ * A message matching a search.
+ */
+export type SearchHit = { message: Message, 
+/**
+ * The part of the text that matched, with matched words between
+ * `<mark>` and `</mark>`. The text itself isn't escaped.
+ */
+snippet: string, };
@@ -1,3 +1,8 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type Server = { id: number, name: string, };
+export type Server = { id: number, name: string, 
+/**
+ * Has every permission in the server. Unset for servers created before
+ * ownership existed and for the seeded server.
+ */
+owner_id: number | null, };
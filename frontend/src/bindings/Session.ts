@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { User } from "./User";
+
+/**
+ * A logged in session. The token is also set as an HttpOnly cookie, so
+ * browser clients don't need to store it; other clients send it as a
+ * `Authorization: Bearer <token>` header.
+ */
+export type Session = { user: User, token: string, expires_ts: string, };
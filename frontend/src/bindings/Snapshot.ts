@@ -1,7 +1,35 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { Channel } from "./Channel";
+import type { Emoji } from "./Emoji";
 import type { Message } from "./Message";
+import type { PresenceStatus } from "./PresenceStatus";
+import type { ReadState } from "./ReadState";
 import type { Server } from "./Server";
+import type { Thread } from "./Thread";
+import type { UnreadCounts } from "./UnreadCounts";
 import type { User } from "./User";
 
-export type Snapshot = { users: { [key in number]?: User }, channels: { [key in number]?: Array<Channel> }, servers: { [key in number]?: Server }, messages: { [key in number]?: { [key in number]?: Array<Message> } }, };
+/**
+ * Everything a client needs on startup. Servers, channels and messages are
+ * limited to the servers the requesting user is a member of, and channels and
+ * messages to the channels they can view. Each channel has its newest
+ * messages, and older ones are paged in from its history. Only active
+ * threads are included. DMs are listed apart from servers, with their
+ * recipients and newest messages.
+ * Message reactions, read states and unread counts are for the requesting
+ * user.
+ */
+export type Snapshot = { 
+/**
+ * The last update included. Later ones can be included too, if they
+ * happened while the snapshot was being read.
+ */
+seq: number, users: { [key in number]?: User }, joined_servers: Array<number>, channels: { [key in number]?: Array<Channel> }, servers: { [key in number]?: Server }, messages: { [key in number]?: { [key in number]?: Array<Message> } }, threads: { [key in number]?: Array<Thread> }, emojis: { [key in number]?: Array<Emoji> }, dms: Array<Channel>, recipients: { [key in number]?: Array<number> }, dm_messages: { [key in number]?: Array<Message> }, read_states: { [key in number]?: ReadState }, 
+/**
+ * Totals of the read states per server.
+ */
+unreads: { [key in number]?: UnreadCounts }, 
+/**
+ * Users connected to the gateway. Everyone else is offline.
+ */
+presences: { [key in number]?: PresenceStatus }, };
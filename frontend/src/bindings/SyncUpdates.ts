@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Update } from "./Update";
+
+/**
+ * The updates since a snapshot or an earlier sync.
+ */
+export type SyncUpdates = { 
+/**
+ * Where these updates leave off, to sync or subscribe from next.
+ */
+seq: number, 
+/**
+ * Oldest first, and only the ones the user can see.
+ */
+updates: Array<Update>, };
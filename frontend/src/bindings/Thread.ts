@@ -0,0 +1,30 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A conversation inside a channel. Its messages are channel messages with
+ * `thread_id` set, so the channel's permissions apply to them.
+ */
+export type Thread = { id: number, channel_id: number, server_id: number, name: string, 
+/**
+ * The channel message the thread was started from, if any. Unset if it
+ * was deleted.
+ */
+starter_message_id: bigint | null, 
+/**
+ * Unset if the creator's account was deleted.
+ */
+creator_id: number | null, created_ts: string, 
+/**
+ * When the thread was created, last posted in, or unarchived.
+ */
+last_active_ts: string, 
+/**
+ * Archived threads are left out of the snapshot. Posting in one
+ * unarchives it.
+ */
+archived: boolean, 
+/**
+ * Only members with MANAGE_MESSAGES can post in or unarchive a locked
+ * thread.
+ */
+locked: boolean, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Unread totals over a server's channels.
+ */
+export type UnreadCounts = { unread_count: number, mention_count: number, };
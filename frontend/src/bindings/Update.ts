@@ -1,8 +1,18 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Attachment } from "./Attachment";
 import type { Channel } from "./Channel";
+import type { ChannelOverwrite } from "./ChannelOverwrite";
+import type { Emoji } from "./Emoji";
+import type { MemberRole } from "./MemberRole";
 import type { Message } from "./Message";
+import type { PresenceStatus } from "./PresenceStatus";
+import type { Reaction } from "./Reaction";
+import type { ReadState } from "./ReadState";
+import type { Role } from "./Role";
 import type { Server } from "./Server";
+import type { ServerMember } from "./ServerMember";
+import type { Thread } from "./Thread";
 import type { Typing } from "./Typing";
 import type { User } from "./User";
 
-export type Update = { "User": User } | { "Server": Server } | { "Channel": Channel } | { "Message": Message } | { "Typing": Typing } | { "VoiceJoin": { user_id: number, channel_id: number, } } | { "VoiceLeave": { user_id: number, channel_id: number, } };
+export type Update = { "User": User } | { "Server": Server } | { "Channel": Channel } | { "Message": Message } | { "MessageEdit": Message } | { "MessageDelete": { server_id: number | null, channel_id: number, message_id: bigint, } } | { "MessageBulkDelete": { server_id: number | null, channel_id: number, message_ids: Array<bigint>, } } | { "AttachmentUpdate": { server_id: number | null, channel_id: number, attachment: Attachment, } } | { "Typing": { channel_id: number, typing: Typing, } } | { "RecipientAdd": { channel_id: number, user_id: number, } } | { "RecipientRemove": { channel_id: number, user_id: number, } } | { "MemberJoin": ServerMember } | { "MemberLeave": { server_id: number, user_id: number, } } | { "Roles": { server_id: number, roles: Array<Role>, } } | { "MemberRoleAdd": MemberRole } | { "MemberRoleRemove": MemberRole } | { "ChannelOverwrites": { server_id: number, channel_id: number, overwrites: Array<ChannelOverwrite>, } } | { "ReactionAdd": Reaction } | { "ReactionRemove": Reaction } | { "Emojis": { server_id: number, emojis: Array<Emoji>, } } | { "ThreadCreate": Thread } | { "ThreadUpdate": Thread } | { "ReadStateUpdate": ReadState } | { "VoiceJoin": { user_id: number, channel_id: number, } } | { "VoiceLeave": { user_id: number, channel_id: number, } } | { "Presence": { user_id: number, status: PresenceStatus, } };
@@ -1,15 +1,25 @@
 'use client';
 import Link from 'next/link';
 
-export function ServerPill({ href, label, active }: { href: string; label: string; active?: boolean }) {
+export function ServerPill({ href, label, active, unread, mentions }: { href: string; label: string; active?: boolean; unread?: boolean; mentions?: number }) {
   return (
-    <Link href={href} className={`w-12 h-12 rounded-2xl grid place-items-center text-sm font-medium transition-all duration-200
+    <Link href={href} className={`relative w-12 h-12 rounded-2xl grid place-items-center text-sm font-medium transition-all duration-200
       ${active ? 'bg-[#5865f2] text-white rounded-xl' : 'bg-[#313338] hover:bg-[#5865f2] hover:rounded-xl text-white'}`}>
       {label.slice(0, 2).toUpperCase()}
+      {unread && !active && <span className="absolute -left-3 w-1 h-2 rounded-r bg-white"/>}
+      {!!mentions && <MentionBadge count={mentions} className="absolute -bottom-1 -right-1"/>}
     </Link>
   );
 }
 
+export function MentionBadge({ count, className }: { count: number; className?: string }) {
+  return (
+    <span className={`min-w-4 h-4 px-1 rounded-full bg-[#f23f43] text-white text-[11px] font-bold grid place-items-center ${className ?? ''}`}>
+      {count}
+    </span>
+  );
+}
+
 export function PlusButton({ onClick }: { onClick(): void }) {
   return (
     <button onClick={onClick} className="w-12 h-12 rounded-2xl bg-[#313338] hover:bg-[#23a559] hover:rounded-xl transition-all duration-200 grid place-items-center text-[#23a559] hover:text-white text-3xl font-light">+</button>
@@ -2,25 +2,85 @@
 
 import { Update } from "@bindings/Update";
 import { Snapshot } from "@bindings/Snapshot";
+import { ReactionCount } from "@bindings/ReactionCount";
+import { ReadState } from "@bindings/ReadState";
+import { Message } from "@bindings/Message";
+import { Channel } from "@bindings/Channel";
 import { useRef, createContext, useContext, useEffect, useMemo, useState } from 'react';
 
 type State = {
 	userId: number | null;
 	snapshot: Snapshot | null;
-	typingUsers: Set<number>;
+	typingUsers: Map<number, Set<number>>; // channel_id -> Set<user_id>
 	voiceUsers: Map<number, Set<number>>; // channel_id -> Set<user_id>
 	setUserId(id: number | null): void;
-	setSnapshot(s: Snapshot | null): void;
+	setSnapshot: React.Dispatch<React.SetStateAction<Snapshot | null>>;
 }
 
 const AppStateCtx = createContext<State | null>(null);
 
+// Matches REPLY_EXCERPT_LEN in the backend.
+const REPLY_EXCERPT_LEN = 100;
+
+// Messages of a server channel, or of a DM when there's no server.
+export function channelMessages(snapshot: Snapshot, server_id: number | null, channel_id: number): Message[] {
+	return (server_id === null
+		? snapshot.dm_messages[channel_id]
+		: snapshot.messages[server_id]?.[channel_id]) ?? [];
+}
+
+export function setChannelMessages(snapshot: Snapshot, server_id: number | null, channel_id: number, messages: Message[]): Snapshot {
+	if (server_id === null) {
+		return { ...snapshot, dm_messages: { ...snapshot.dm_messages, [channel_id]: messages } };
+	}
+	return {
+		...snapshot,
+		messages: {
+			...snapshot.messages,
+			[server_id]: { ...snapshot.messages[server_id], [channel_id]: messages }
+		}
+	};
+}
+
+// Unnamed DMs go by the other recipients' names.
+export function dmName(snapshot: Snapshot, channel: Channel, userId: number | null): string {
+	if (channel.name) return channel.name;
+	const names = (snapshot.recipients[channel.id] ?? [])
+		.filter((id) => id !== userId)
+		.map((id) => snapshot.users[id]?.name ?? 'unknown user');
+	return names.join(', ') || 'Empty group';
+}
+
+// Swaps in a channel's new read state and moves the server's totals by the
+// difference. DMs have no server totals.
+function setReadState(snapshot: Snapshot, read_state: ReadState): Snapshot {
+	const old = snapshot.read_states[read_state.channel_id];
+	const read_states = { ...snapshot.read_states, [read_state.channel_id]: read_state };
+	if (read_state.server_id === null) return { ...snapshot, read_states };
+	const totals = snapshot.unreads[read_state.server_id] ?? { unread_count: 0, mention_count: 0 };
+	return {
+		...snapshot,
+		read_states,
+		unreads: {
+			...snapshot.unreads,
+			[read_state.server_id]: {
+				unread_count: totals.unread_count + read_state.unread_count - (old?.unread_count ?? 0),
+				mention_count: totals.mention_count + read_state.mention_count - (old?.mention_count ?? 0),
+			}
+		}
+	};
+}
+
 export function AppStateProvider({ children }: { children: React.ReactNode }) {
 	const [userId, setUserId] = useState<number | null>(null);
 	const [snapshot, setSnapshot] = useState<Snapshot | null>(null);
-	const [typingUsers, setTypingUsers] = useState<Set<number>>(new Set());
+	const [typingUsers, setTypingUsers] = useState<Map<number, Set<number>>>(new Map());
 	const [voiceUsers, setVoiceUsers] = useState<Map<number, Set<number>>>(new Map());
 	const sseRef = useRef<EventSource | null>(null);
+	// The last update applied to the snapshot.
+	const seqRef = useRef(0);
+	// Updates that arrived while fetching a new snapshot.
+	const pendingRef = useRef<[number, Update][] | null>(null);
 
 	async function getSnapshot(): Promise<Snapshot | null> {
     const res = await fetch('/snapshot', { method: 'GET' });
@@ -53,6 +113,13 @@ export function AppStateProvider({ children }: { children: React.ReactNode }) {
 			};
 		} else if ("Channel" in u) {
 			const { Channel } = u;
+			if (Channel.server_id === null) {
+				// Group DMs change owner or name, so replace what we have.
+				return {
+					...snapshot,
+					dms: [...snapshot.dms.filter((c) => c.id !== Channel.id), Channel]
+				};
+			}
 			const channels = snapshot.channels[Channel.server_id] ?? [];
 			const updatedChannels = channels.some((c) => c.id === Channel.id)
 				? channels
@@ -63,30 +130,213 @@ export function AppStateProvider({ children }: { children: React.ReactNode }) {
 			};
 		} else if ("Message" in u) {
 			const { Message } = u;
-			const channels = snapshot.messages[Message.server_id] ?? {};
-			const messages = channels[Message.channel_id] ?? [];
-			const updatedMessages = messages.some((m) => m.id === Message.id)
-				? messages
-				: [...messages, Message];
+			const messages = channelMessages(snapshot, Message.server_id, Message.channel_id);
+			if (messages.some((m) => m.id === Message.id)) return snapshot;
+			const read_state = snapshot.read_states[Message.channel_id];
+			// The server marks our own messages read. Role mentions are only
+			// counted by the server, so they show up on the next snapshot.
+			const mine = Message.user_id === userId;
+			const mentioned = Message.mentions.everyone || (userId !== null && Message.mentions.users.includes(userId));
+			if (read_state) {
+				snapshot = setReadState(snapshot, mine
+					? { ...read_state, last_read_message_id: Message.id, unread_count: 0, mention_count: 0 }
+					: { ...read_state, unread_count: read_state.unread_count + 1, mention_count: read_state.mention_count + (mentioned ? 1 : 0) });
+			}
+			return setChannelMessages(snapshot, Message.server_id, Message.channel_id, [...messages, Message]);
+		} else if ("MessageEdit" in u) {
+			const { MessageEdit } = u;
+			const messages = channelMessages(snapshot, MessageEdit.server_id, MessageEdit.channel_id);
+			return setChannelMessages(snapshot, MessageEdit.server_id, MessageEdit.channel_id, messages.map((m) => {
+				// Updates don't carry reactions or attachments, so keep the ones we have.
+				if (m.id === MessageEdit.id) return { ...MessageEdit, reactions: m.reactions, attachments: m.attachments };
+				if (m.reply_to === MessageEdit.id) {
+					return { ...m, reply_excerpt: Array.from(MessageEdit.text).slice(0, REPLY_EXCERPT_LEN).join('') };
+				}
+				return m;
+			}));
+		} else if ("MessageDelete" in u || "MessageBulkDelete" in u) {
+			const { server_id, channel_id, deleted } = "MessageDelete" in u
+				? { ...u.MessageDelete, deleted: [u.MessageDelete.message_id] }
+				: { ...u.MessageBulkDelete, deleted: u.MessageBulkDelete.message_ids };
+			const messages = channelMessages(snapshot, server_id, channel_id);
+			return setChannelMessages(snapshot, server_id, channel_id, messages
+				.filter((m) => !deleted.includes(m.id))
+				// Replies stay, but lose the preview of what they replied to.
+				.map((m) => m.reply_to !== null && deleted.includes(m.reply_to)
+					? { ...m, reply_user_id: null, reply_excerpt: null }
+					: m));
+		} else if ("AttachmentUpdate" in u) {
+			const { server_id, channel_id, attachment } = u.AttachmentUpdate;
+			const messages = channelMessages(snapshot, server_id, channel_id);
+			return setChannelMessages(snapshot, server_id, channel_id, messages.map((m) => m.id === attachment.message_id
+				? { ...m, attachments: m.attachments.map((a) => a.id === attachment.id ? attachment : a) }
+				: m));
+		} else if ("ReactionAdd" in u || "ReactionRemove" in u) {
+			const added = "ReactionAdd" in u;
+			const reaction = added ? u.ReactionAdd : u.ReactionRemove;
+			const messages = channelMessages(snapshot, reaction.server_id, reaction.channel_id);
+			const me = reaction.user_id === userId;
+			const same = (r: ReactionCount) => r.emoji === reaction.emoji && r.emoji_id === reaction.emoji_id;
+			return setChannelMessages(snapshot, reaction.server_id, reaction.channel_id, messages.map((m) => {
+				if (m.id !== reaction.message_id) return m;
+				const reactions = m.reactions.some(same)
+					? m.reactions.map((r) => same(r)
+						? { ...r, count: r.count + (added ? 1 : -1), me: me ? added : r.me }
+						: r)
+					: [...m.reactions, { emoji: reaction.emoji, emoji_id: reaction.emoji_id, count: 1, me }];
+				return { ...m, reactions: reactions.filter((r) => r.count > 0) };
+			}));
+		} else if ("Emojis" in u) {
+			const { server_id, emojis } = u.Emojis;
+			// Reactions with a deleted emoji are deleted along with it.
+			const ids = new Set(emojis.map((e) => e.id));
+			const channels = snapshot.messages[server_id] ?? {};
 			return {
 				...snapshot,
+				emojis: { ...snapshot.emojis, [server_id]: emojis },
 				messages: {
 					...snapshot.messages,
-					[Message.server_id]: {
-						...channels,
-						[Message.channel_id]: updatedMessages
-					}
+					[server_id]: Object.fromEntries(Object.entries(channels).map(([channel_id, messages]) => [
+						channel_id,
+						messages?.map((m) => ({
+							...m,
+							reactions: m.reactions.filter((r) => r.emoji_id === null || ids.has(r.emoji_id))
+						}))
+					]))
 				}
 			};
+		} else if ("RecipientAdd" in u || "RecipientRemove" in u) {
+			const added = "RecipientAdd" in u;
+			const { channel_id, user_id } = added ? u.RecipientAdd : u.RecipientRemove;
+			const recipients = (snapshot.recipients[channel_id] ?? []).filter((id) => id !== user_id);
+			return {
+				...snapshot,
+				recipients: { ...snapshot.recipients, [channel_id]: added ? [...recipients, user_id] : recipients }
+			};
+		} else if ("ReadStateUpdate" in u) {
+			return setReadState(snapshot, u.ReadStateUpdate);
+		} else if ("ThreadCreate" in u || "ThreadUpdate" in u) {
+			const thread = "ThreadCreate" in u ? u.ThreadCreate : u.ThreadUpdate;
+			// The snapshot only keeps active threads.
+			const threads = (snapshot.threads[thread.channel_id] ?? []).filter((t) => t.id !== thread.id);
+			return {
+				...snapshot,
+				threads: {
+					...snapshot.threads,
+					[thread.channel_id]: thread.archived ? threads : [thread, ...threads]
+				}
+			};
+		} else if ("Presence" in u) {
+			const { user_id, status } = u.Presence;
+			const presences = { ...snapshot.presences };
+			if (status === "Offline") {
+				delete presences[user_id];
+			} else {
+				presences[user_id] = status;
+			}
+			return { ...snapshot, presences };
 		} else {
 			return snapshot;
 		}
 	}
 
 	useEffect(() => {
+		if (userId === null) return;
+
+		// Applies an update unless the snapshot already has it. While a new
+		// snapshot is on its way, updates wait for it.
+		function receive(seq: number, parsed: Update) {
+			if (pendingRef.current !== null) {
+				pendingRef.current.push([seq, parsed]);
+				return;
+			}
+			if (seq <= seqRef.current) return;
+			seqRef.current = seq;
+			apply(parsed);
+		}
+
+		function apply(parsed: Update) {
+			if ("Typing" in parsed) {
+				const { channel_id, typing } = parsed.Typing;
+				setTypingUsers((prev) => {
+					const next = new Map(prev);
+					const users = new Set(next.get(channel_id) || []);
+					if ("Start" in typing) {
+						users.add(typing.Start);
+					} else {
+						users.delete(typing.Stop);
+					}
+					next.set(channel_id, users);
+					return next;
+				});
+			} else if ("MemberJoin" in parsed && parsed.MemberJoin.user_id === userId) {
+				// Joining a server brings in its channels and messages.
+				refresh();
+			} else if ("MemberLeave" in parsed && parsed.MemberLeave.user_id === userId) {
+				refresh();
+			} else if (("RecipientAdd" in parsed && parsed.RecipientAdd.user_id === userId)
+				|| ("RecipientRemove" in parsed && parsed.RecipientRemove.user_id === userId)) {
+				// Being added brings in the DM and its messages, and being removed
+				// takes them away.
+				refresh();
+			} else if ("ChannelOverwrites" in parsed || "Roles" in parsed
+				|| ("MemberRoleAdd" in parsed && parsed.MemberRoleAdd.user_id === userId)
+				|| ("MemberRoleRemove" in parsed && parsed.MemberRoleRemove.user_id === userId)) {
+				// Permission changes can show or hide channels.
+				refresh();
+			} else if ("VoiceJoin" in parsed) {
+				const { user_id, channel_id } = parsed.VoiceJoin;
+				setVoiceUsers((prev) => {
+					const next = new Map(prev);
+					const users = new Set(next.get(channel_id) || []);
+					users.add(user_id);
+					next.set(channel_id, users);
+					return next;
+				});
+			} else if ("VoiceLeave" in parsed) {
+				const { user_id, channel_id } = parsed.VoiceLeave;
+				setVoiceUsers((prev) => {
+					const next = new Map(prev);
+					const users = new Set(next.get(channel_id) || []);
+					users.delete(user_id);
+					if (users.size === 0) {
+						next.delete(channel_id);
+					} else {
+						next.set(channel_id, users);
+					}
+					return next;
+				});
+			} else {
+				setSnapshot((prev) => {
+					if (prev === null) {
+						return null;
+					} else {
+						return handleUpdate(prev, parsed);
+					}
+				});
+			}
+		}
+
+		async function refresh() {
+			if (pendingRef.current !== null) return;
+			pendingRef.current = [];
+			const fresh = await getSnapshot();
+			const pending = pendingRef.current;
+			pendingRef.current = null;
+			if (fresh !== null) {
+				seqRef.current = fresh.seq;
+				setSnapshot(fresh);
+			}
+			for (const [seq, parsed] of pending) receive(seq, parsed);
+		}
+
 		(async() => {
-			setSnapshot(await getSnapshot());
-			const es = new EventSource('/updates');
+			const fresh = await getSnapshot();
+			seqRef.current = fresh?.seq ?? 0;
+			setSnapshot(fresh);
+			// Starting from the snapshot's version, so nothing sent while it was
+			// on its way is missed.
+			const es = new EventSource(fresh === null ? '/updates' : `/updates?last_event_id=${fresh.seq}`);
 			sseRef.current = es;
 
 			es.addEventListener('open', () => {
@@ -98,52 +348,17 @@ export function AppStateProvider({ children }: { children: React.ReactNode }) {
 				console.error('SSE readyState:', es.readyState);
 			});
 
+			// Reconnecting replays what was missed, unless the server no longer
+			// has it, in which case we start again from a new snapshot.
+			es.addEventListener('resync', () => {
+				console.log('SSE resync required');
+				refresh();
+			});
+
 			es.addEventListener('message', (evt) => {
 				console.log('SSE message received:', evt.data);
 				try {
-					const parsed: Update = JSON.parse(evt.data);
-					if ("Typing" in parsed) {
-						const { Typing } = parsed;
-						if ("Start" in Typing) {
-							setTypingUsers((prev) => new Set(prev).add(Typing.Start));
-						} else {
-							setTypingUsers((prev) => {
-								const next = new Set(prev);
-								next.delete(Typing.Stop);
-								return next;
-							});
-						}
-					} else if ("VoiceJoin" in parsed) {
-						const { user_id, channel_id } = parsed.VoiceJoin;
-						setVoiceUsers((prev) => {
-							const next = new Map(prev);
-							const users = new Set(next.get(channel_id) || []);
-							users.add(user_id);
-							next.set(channel_id, users);
-							return next;
-						});
-					} else if ("VoiceLeave" in parsed) {
-						const { user_id, channel_id } = parsed.VoiceLeave;
-						setVoiceUsers((prev) => {
-							const next = new Map(prev);
-							const users = new Set(next.get(channel_id) || []);
-							users.delete(user_id);
-							if (users.size === 0) {
-								next.delete(channel_id);
-							} else {
-								next.set(channel_id, users);
-							}
-							return next;
-						});
-					} else {
-						setSnapshot((prev) => {
-							if (prev === null) {
-								return null;
-							} else {
-								return handleUpdate(prev, parsed);
-							}
-						});
-					}
+					receive(Number(evt.lastEventId), JSON.parse(evt.data));
 				} catch {
 					console.error(`Error parsing message json: ${evt.data}`);
 				}
@@ -153,7 +368,7 @@ export function AppStateProvider({ children }: { children: React.ReactNode }) {
 				sseRef.current = null;
 			};
 		})()
-	}, [setSnapshot, sseRef]);
+	}, [userId, setSnapshot, sseRef]);
 
 	const api = useMemo<State>(() => ({
 		userId,
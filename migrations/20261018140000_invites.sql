@@ -0,0 +1,16 @@
+-- Invites made by the server itself, like the seeded one, have no creator.
+CREATE TABLE invites (
+    code TEXT PRIMARY KEY NOT NULL,
+	server_id INTEGER NOT NULL,
+	channel_id INTEGER,
+	creator_id INTEGER,
+	created_ts DATETIME NOT NULL,
+	expires_ts DATETIME,
+	max_uses INTEGER,
+	uses INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (creator_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX invites_server_id ON invites (server_id);
@@ -0,0 +1,15 @@
+-- Allow/deny permission bits layered over a member's role permissions in one
+-- channel. Each row targets either a role or a single user.
+CREATE TABLE channel_overwrites (
+	channel_id INTEGER NOT NULL,
+	role_id INTEGER,
+	user_id INTEGER,
+	allow INTEGER NOT NULL,
+	deny INTEGER NOT NULL,
+	CHECK ((role_id IS NULL) <> (user_id IS NULL)),
+	UNIQUE (channel_id, role_id),
+	UNIQUE (channel_id, user_id),
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (role_id) REFERENCES roles(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,33 @@
+-- A sub-conversation inside a channel, optionally started from one of the
+-- channel's messages. Threads are archived after a period without messages.
+CREATE TABLE threads (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel_id INTEGER NOT NULL,
+	server_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+	starter_message_id INTEGER UNIQUE,
+	creator_id INTEGER,
+	created_ts DATETIME NOT NULL,
+	last_active_ts DATETIME NOT NULL,
+	archived BOOLEAN NOT NULL DEFAULT FALSE,
+	locked BOOLEAN NOT NULL DEFAULT FALSE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (starter_message_id) REFERENCES messages(id) ON DELETE SET NULL,
+	FOREIGN KEY (creator_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX threads_channel_id ON threads (channel_id);
+CREATE INDEX threads_active ON threads (archived, last_active_ts);
+
+CREATE TABLE thread_members (
+	thread_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	joined_ts DATETIME NOT NULL,
+	PRIMARY KEY (thread_id, user_id),
+	FOREIGN KEY (thread_id) REFERENCES threads(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Thread messages keep their channel's ID so channel permissions apply.
+ALTER TABLE messages ADD COLUMN thread_id INTEGER REFERENCES threads(id) ON DELETE CASCADE;
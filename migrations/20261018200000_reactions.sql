@@ -0,0 +1,30 @@
+-- Custom emoji a server adds for its members to react with.
+CREATE TABLE emojis (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	server_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+	creator_id INTEGER,
+	created_ts DATETIME NOT NULL,
+	UNIQUE (server_id, name),
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (creator_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- One row per user and emoji on a message. Each row is either a Unicode
+-- emoji or one of the server's custom emoji.
+CREATE TABLE reactions (
+	message_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	emoji TEXT,
+	emoji_id INTEGER,
+	created_ts DATETIME NOT NULL,
+	CHECK ((emoji IS NULL) <> (emoji_id IS NULL)),
+	UNIQUE (message_id, user_id, emoji),
+	UNIQUE (message_id, user_id, emoji_id),
+	FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+	FOREIGN KEY (emoji_id) REFERENCES emojis(id) ON DELETE CASCADE
+);
+
+-- ADD_REACTIONS (4096) is new, so roles that can send messages get it too.
+UPDATE roles SET permissions = permissions | 4096 WHERE permissions & 2 <> 0;
@@ -0,0 +1,2 @@
+-- Channel history is paged by message ID, newest first.
+CREATE INDEX messages_channel_id ON messages (channel_id, id);
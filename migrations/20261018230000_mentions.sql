@@ -0,0 +1,19 @@
+-- Mentions resolved from a message's text when it's sent or edited. Messages
+-- sent before this keep their text but have no mentions.
+ALTER TABLE messages ADD COLUMN mention_everyone BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Each row mentions exactly one of a user, a role or a channel.
+CREATE TABLE message_mentions (
+	message_id INTEGER NOT NULL,
+	user_id INTEGER,
+	role_id INTEGER,
+	channel_id INTEGER,
+	CHECK ((user_id IS NOT NULL) + (role_id IS NOT NULL) + (channel_id IS NOT NULL) = 1),
+	FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+	FOREIGN KEY (role_id) REFERENCES roles(id) ON DELETE CASCADE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_mentions_message_id ON message_mentions (message_id);
+CREATE INDEX message_mentions_user_id ON message_mentions (user_id);
@@ -0,0 +1,12 @@
+-- How far each user has read each channel. The last read message may since
+-- have been deleted, so it isn't a foreign key. mention_count goes up as
+-- mentioning messages arrive and is recounted when the user acks.
+CREATE TABLE read_states (
+	user_id INTEGER NOT NULL,
+	channel_id INTEGER NOT NULL,
+	last_read_message_id INTEGER,
+	mention_count INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (user_id, channel_id),
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE
+);
@@ -0,0 +1,82 @@
+-- no-transaction
+-- DMs are channels without a server, so `channels.server_id` and
+-- `messages.server_id` become nullable. SQLite can't drop NOT NULL from a
+-- column, so both tables are rebuilt following SQLite's table rebuild
+-- procedure: foreign keys are turned off, which can't be done inside a
+-- transaction, so that dropping the old tables doesn't cascade to the rows
+-- referencing them, and are checked before committing.
+PRAGMA foreign_keys = OFF;
+BEGIN;
+
+-- `kind` is 0 for server text channels, 1 for DMs and 2 for group DMs.
+CREATE TABLE channels_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL,
+	server_id INTEGER,
+	kind INTEGER NOT NULL DEFAULT 0,
+	owner_id INTEGER,
+	CHECK ((server_id IS NULL) = (kind <> 0)),
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE SET NULL
+);
+INSERT INTO channels_new (id, name, server_id) SELECT id, name, server_id FROM channels;
+DROP TABLE channels;
+ALTER TABLE channels_new RENAME TO channels;
+
+CREATE TABLE messages_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	ts DATETIME NOT NULL,
+    text TEXT NOT NULL,
+	server_id INTEGER,
+	channel_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	edited_ts DATETIME,
+	reply_to INTEGER,
+	thread_id INTEGER REFERENCES threads(id) ON DELETE CASCADE,
+	mention_everyone BOOLEAN NOT NULL DEFAULT FALSE,
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+INSERT INTO messages_new (
+	id, ts, text, server_id, channel_id, user_id, edited_ts, reply_to, thread_id, mention_everyone
+)
+SELECT
+	id, ts, text, server_id, channel_id, user_id, edited_ts, reply_to, thread_id, mention_everyone
+FROM messages;
+-- Dropping the table drops its index and search triggers too. The search
+-- index itself is keyed by message ID, which is kept.
+DROP TABLE messages;
+ALTER TABLE messages_new RENAME TO messages;
+
+CREATE INDEX messages_channel_id ON messages (channel_id, id);
+
+CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+	INSERT INTO messages_fts (rowid, text) VALUES (new.id, new.text);
+END;
+CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+	INSERT INTO messages_fts (messages_fts, rowid, text) VALUES ('delete', old.id, old.text);
+END;
+CREATE TRIGGER messages_fts_update AFTER UPDATE OF text ON messages BEGIN
+	INSERT INTO messages_fts (messages_fts, rowid, text) VALUES ('delete', old.id, old.text);
+	INSERT INTO messages_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+-- Who is in a DM. 1:1 DMs always have the same two recipients.
+CREATE TABLE channel_recipients (
+	channel_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	added_ts DATETIME NOT NULL,
+	PRIMARY KEY (channel_id, user_id),
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX channel_recipients_user_id ON channel_recipients (user_id);
+
+-- Fails before committing if the rebuild left any reference broken.
+CREATE TEMP TABLE foreign_key_errors (count INTEGER NOT NULL CHECK (count = 0));
+INSERT INTO foreign_key_errors SELECT count(*) FROM pragma_foreign_key_check;
+DROP TABLE foreign_key_errors;
+COMMIT;
+PRAGMA foreign_keys = ON;
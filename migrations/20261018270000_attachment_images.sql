@@ -0,0 +1,10 @@
+-- What the background image workers find out about an attachment. `processed`
+-- is set once a worker has looked at it, image or not, so anything still
+-- unprocessed at startup is queued again. The thumbnail is a blob like the
+-- attachment itself.
+ALTER TABLE attachments ADD COLUMN width INTEGER;
+ALTER TABLE attachments ADD COLUMN height INTEGER;
+ALTER TABLE attachments ADD COLUMN thumbnail_hash TEXT;
+ALTER TABLE attachments ADD COLUMN processed BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX attachments_unprocessed ON attachments (id) WHERE NOT processed;
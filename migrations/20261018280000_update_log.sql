@@ -0,0 +1,8 @@
+-- Updates in the order they were sent, so clients that reconnect can catch up
+-- on what they missed. Only written with `updates.log` on, and trimmed to the
+-- newest `updates.log_len`. `data` is the update as JSON.
+CREATE TABLE update_log (
+	seq INTEGER PRIMARY KEY NOT NULL,
+	ts DATETIME NOT NULL,
+	data TEXT NOT NULL
+);
@@ -0,0 +1,28 @@
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE servers (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE channels (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+	server_id INTEGER NOT NULL,
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE
+);
+
+CREATE TABLE messages (
+    id BIGSERIAL PRIMARY KEY,
+	ts TIMESTAMPTZ NOT NULL,
+    text TEXT NOT NULL,
+	server_id INTEGER NOT NULL,
+	channel_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
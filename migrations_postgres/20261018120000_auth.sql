@@ -0,0 +1,15 @@
+ALTER TABLE users ADD COLUMN password_hash TEXT;
+
+-- Accounts created before passwords existed can't log in, so names only
+-- need to be unique among users that have a password.
+CREATE UNIQUE INDEX users_name_unique ON users (name) WHERE password_hash IS NOT NULL;
+
+CREATE TABLE sessions (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	created_ts TIMESTAMPTZ NOT NULL,
+	expires_ts TIMESTAMPTZ NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
@@ -0,0 +1,15 @@
+CREATE TABLE server_members (
+	server_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	joined_ts TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY (server_id, user_id),
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX server_members_user_id ON server_members (user_id);
+
+-- Every server used to be open to every user, so keep existing users in
+-- the servers they could already post in.
+INSERT INTO server_members (server_id, user_id, joined_ts)
+SELECT servers.id, users.id, CURRENT_TIMESTAMP FROM servers, users;
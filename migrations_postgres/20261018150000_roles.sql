@@ -0,0 +1,40 @@
+-- The owner has every permission. Servers created before ownership existed
+-- have none.
+ALTER TABLE servers ADD COLUMN owner_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
+
+-- Position 0 is the server's @everyone role, which every member implicitly
+-- has. Higher positions outrank lower ones.
+CREATE TABLE roles (
+    id SERIAL PRIMARY KEY,
+	server_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+	permissions BIGINT NOT NULL,
+	position INTEGER NOT NULL,
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE
+);
+
+CREATE INDEX roles_server_id ON roles (server_id, position);
+
+CREATE TABLE member_roles (
+	server_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	role_id INTEGER NOT NULL,
+	PRIMARY KEY (server_id, user_id, role_id),
+	FOREIGN KEY (server_id, user_id) REFERENCES server_members(server_id, user_id) ON DELETE CASCADE,
+	FOREIGN KEY (role_id) REFERENCES roles(id) ON DELETE CASCADE
+);
+
+CREATE TABLE server_bans (
+	server_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	banned_ts TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY (server_id, user_id),
+	FOREIGN KEY (server_id) REFERENCES servers(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Existing servers have no owner, so their @everyone role keeps the ability
+-- to create channels that every member used to have. 1675 is VIEW_CHANNEL,
+-- SEND_MESSAGES, MANAGE_CHANNELS, CREATE_INVITE, CONNECT and SPEAK.
+INSERT INTO roles (server_id, name, permissions, position)
+SELECT id, '@everyone', 1675, 0 FROM servers;
@@ -0,0 +1,13 @@
+ALTER TABLE messages ADD COLUMN edited_ts TIMESTAMPTZ;
+
+-- The text a message had before each edit. `edited_ts` is when it was
+-- replaced.
+CREATE TABLE message_edits (
+    id BIGSERIAL PRIMARY KEY,
+	message_id BIGINT NOT NULL,
+    text TEXT NOT NULL,
+	edited_ts TIMESTAMPTZ NOT NULL,
+	FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_edits_message_id ON message_edits (message_id);
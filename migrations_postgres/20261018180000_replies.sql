@@ -0,0 +1,3 @@
+-- Not a foreign key: a reply keeps pointing at its parent after the parent is
+-- deleted, so clients can say the original message is gone.
+ALTER TABLE messages ADD COLUMN reply_to BIGINT;
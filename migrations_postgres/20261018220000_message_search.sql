@@ -0,0 +1,6 @@
+-- Full-text index over message text. The generated column keeps it in step
+-- with inserts and edits.
+ALTER TABLE messages
+	ADD COLUMN text_search TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', text)) STORED;
+
+CREATE INDEX messages_text_search ON messages USING GIN (text_search);
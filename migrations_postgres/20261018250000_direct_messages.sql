@@ -0,0 +1,20 @@
+-- DMs are channels without a server, so `channels.server_id` and
+-- `messages.server_id` become nullable.
+-- `kind` is 0 for server text channels, 1 for DMs and 2 for group DMs.
+ALTER TABLE channels ALTER COLUMN server_id DROP NOT NULL;
+ALTER TABLE channels ADD COLUMN kind INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE channels ADD COLUMN owner_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
+ALTER TABLE channels ADD CHECK ((server_id IS NULL) = (kind <> 0));
+ALTER TABLE messages ALTER COLUMN server_id DROP NOT NULL;
+
+-- Who is in a DM. 1:1 DMs always have the same two recipients.
+CREATE TABLE channel_recipients (
+	channel_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	added_ts TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY (channel_id, user_id),
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX channel_recipients_user_id ON channel_recipients (user_id);
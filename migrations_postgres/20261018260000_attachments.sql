@@ -0,0 +1,13 @@
+-- Files sent with a message. The content lives in the blob store under its
+-- SHA-256 hash, so identical uploads share one blob.
+CREATE TABLE attachments (
+	id SERIAL PRIMARY KEY,
+	message_id BIGINT NOT NULL,
+	filename TEXT NOT NULL,
+	content_type TEXT NOT NULL,
+	size BIGINT NOT NULL,
+	hash TEXT NOT NULL,
+	FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX attachments_message_id ON attachments (message_id);
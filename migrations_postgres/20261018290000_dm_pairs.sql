@@ -0,0 +1,35 @@
+-- The DM between two users, at most one per pair. `user_id` is the lower of
+-- the two IDs, so opening a DM from either side finds the same row.
+CREATE TABLE dm_pairs (
+	user_id INTEGER NOT NULL,
+	other_id INTEGER NOT NULL,
+	channel_id INTEGER NOT NULL UNIQUE,
+	PRIMARY KEY (user_id, other_id),
+	CHECK (user_id < other_id),
+	FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+	FOREIGN KEY (other_id) REFERENCES users(id) ON DELETE CASCADE,
+	FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE
+);
+
+-- Opening a DM from both sides at once could create two. The oldest is kept,
+-- which is the one that was being opened since.
+INSERT INTO dm_pairs (user_id, other_id, channel_id)
+SELECT a.user_id, b.user_id, MIN(a.channel_id)
+FROM channel_recipients a
+JOIN channel_recipients b ON b.channel_id = a.channel_id AND b.user_id > a.user_id
+JOIN channels ON channels.id = a.channel_id AND channels.kind = 1
+GROUP BY a.user_id, b.user_id;
+
+-- Messages in the others move to it, and the others go.
+CREATE TEMP TABLE duplicate_dms AS
+SELECT a.channel_id AS id, dm_pairs.channel_id AS kept_id
+FROM dm_pairs
+JOIN channel_recipients a ON a.user_id = dm_pairs.user_id
+JOIN channel_recipients b ON b.channel_id = a.channel_id AND b.user_id = dm_pairs.other_id
+JOIN channels ON channels.id = a.channel_id AND channels.kind = 1
+WHERE a.channel_id <> dm_pairs.channel_id;
+UPDATE messages
+SET channel_id = (SELECT kept_id FROM duplicate_dms WHERE duplicate_dms.id = messages.channel_id)
+WHERE channel_id IN (SELECT id FROM duplicate_dms);
+DELETE FROM channels WHERE id IN (SELECT id FROM duplicate_dms);
+DROP TABLE duplicate_dms;